@@ -0,0 +1,25 @@
+// Records what the binary was built from for --build-info: the commit, when
+// building from a git checkout, and the target.
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+    if let Some(commit) = commit {
+        println!("cargo:rustc-env=FILES_TO_PROMPT_GIT_COMMIT={}", commit);
+    }
+    println!("cargo:rustc-env=FILES_TO_PROMPT_TARGET={}", std::env::var("TARGET").unwrap_or_default());
+
+    // A missing path would rebuild every time, as outside a checkout
+    println!("cargo:rerun-if-changed=build.rs");
+    for path in [".git/HEAD", ".git/refs"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+}
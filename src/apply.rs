@@ -0,0 +1,348 @@
+// Reading files back out of a prompt-shaped response for
+// `files-to-prompt apply`: documents in any of the text output formats are
+// written to disk, or with --patch-out turned into a unified diff instead.
+// `files-to-prompt convert` reads earlier output the same way.
+use crate::collect::OutputFormat;
+use regex::Regex;
+use std::collections::HashSet;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Lines of unchanged context around each change in a diff.
+const CONTEXT_LINES: usize = 3;
+
+/// A file named in a response and the full contents given for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Document {
+    pub path: PathBuf,
+    pub content: String,
+}
+
+impl Document {
+    /// The content to write over `old`, the file's current text if it
+    /// exists. A file without a final newline keeps going without one.
+    pub fn new_content(&self, old: Option<&str>) -> String {
+        match old {
+            Some(old) if !old.ends_with('\n') => self.content.trim_end_matches('\n').to_string(),
+            _ => self.content.clone(),
+        }
+    }
+}
+
+/// The documents in `text`, which may be in the XML, Markdown or default
+/// output format. Anything around the documents, such as an explanation
+/// from the model, is skipped.
+pub fn parse_documents(text: &str) -> Vec<Document> {
+    let documents = if text.contains("<document_content>") { parse_xml(text) } else { parse_text(text) };
+    documents.into_iter().map(with_final_newline).collect()
+}
+
+/// The documents in output written in `format`, each with its content
+/// exactly as it was written, to render again in another format.
+pub fn read_output(text: &str, format: OutputFormat) -> io::Result<Vec<Document>> {
+    match format {
+        OutputFormat::Cxml => Ok(parse_xml(text)),
+        OutputFormat::Default | OutputFormat::Markdown => Ok(parse_text(text)),
+        OutputFormat::Json => {
+            let values: Vec<serde_json::Value> =
+                serde_json::from_str(text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            values
+                .iter()
+                .map(|value| match (value["path"].as_str(), value["content"].as_str()) {
+                    (Some(path), Some(content)) => Ok(document(path, content)),
+                    _ => Err(io::Error::new(io::ErrorKind::InvalidData, "expected objects with a path and content")),
+                })
+                .collect()
+        }
+    }
+}
+
+// Documents in the default or Markdown format. Lines are split at `\n`
+// alone, so content with `\r\n` line breaks keeps them; the lines around
+// it are compared without their `\r`.
+fn parse_text(text: &str) -> Vec<Document> {
+    let lines: Vec<&str> = text.split('\n').collect();
+    let bare = |j: usize| lines[j].strip_suffix('\r').unwrap_or(lines[j]);
+    let mut documents = Vec::new();
+    let mut i = 0;
+    while i + 1 < lines.len() {
+        let (path, next) = (bare(i).trim(), bare(i + 1));
+        if path.is_empty() {
+            i += 1;
+            continue;
+        }
+
+        if next == "---" {
+            // Content ends with an empty line and `---`, before the next document or the end
+            let start = i + 2;
+            let end = (start + 1..lines.len()).find(|&j| {
+                bare(j) == "---"
+                    && bare(j - 1).is_empty()
+                    && (j + 1 == lines.len() || (j + 2 < lines.len() && bare(j + 2) == "---") || bare(j + 1).is_empty())
+            });
+            if let Some(end) = end {
+                documents.push(document(path, &lines[start..end - 1].join("\n")));
+                i = end + 1;
+                continue;
+            }
+        } else if next.starts_with("```") {
+            let fence: String = next.chars().take_while(|&c| c == '`').collect();
+            let start = i + 2;
+            if let Some(end) = (start..lines.len()).find(|&j| bare(j) == fence) {
+                documents.push(document(path, &lines[start..end].join("\n")));
+                i = end + 1;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    documents
+}
+
+// A CDATA section holds the content of --cxml-strict output, whose source
+// is escaped as well
+fn parse_xml(text: &str) -> Vec<Document> {
+    let document_regex = Regex::new(
+        r"(?s)<document\b[^>]*>\s*<source[^>]*>(.*?)</source>\s*<document_content>\n?(?:<!\[CDATA\[(.*?)\]\]>|(.*?))\n?</document_content>",
+    )
+    .unwrap();
+    document_regex
+        .captures_iter(text)
+        .map(|cap| match cap.get(2) {
+            Some(content) => {
+                let path = cap[1].trim().replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&");
+                document(&path, &content.as_str().replace("]]]]><![CDATA[>", "]]>"))
+            }
+            None => document(cap[1].trim(), &cap[3]),
+        })
+        .collect()
+}
+
+fn document(path: &str, content: &str) -> Document {
+    Document { path: PathBuf::from(path), content: content.to_string() }
+}
+
+// The writers add a line break after the content, so a final one may be missing
+fn with_final_newline(mut document: Document) -> Document {
+    if !document.content.is_empty() && !document.content.ends_with('\n') {
+        document.content.push('\n');
+    }
+    document
+}
+
+/// Whether `path` stays inside the directory it is applied in: relative,
+/// with no `..` components.
+pub fn is_safe_path(path: &Path) -> bool {
+    path.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+        && path.file_name().is_some()
+}
+
+/// A unified diff turning `old` into `new` for the file at `path`, or an
+/// empty string when they are the same. A missing file is diffed as
+/// `/dev/null`.
+pub fn unified_diff(path: &Path, old: Option<&str>, new: &str) -> String {
+    let old_text = old.unwrap_or_default();
+    if old.is_some() && old_text == new {
+        return String::new();
+    }
+
+    let old_lines: Vec<&str> = old_text.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let edits = diff_lines(&old_lines, &new_lines);
+
+    let name = path.to_string_lossy().replace('\\', "/");
+    let mut diff = String::new();
+    match old {
+        Some(_) => diff.push_str(&format!("--- a/{}\n", name)),
+        None => diff.push_str("--- /dev/null\n"),
+    }
+    diff.push_str(&format!("+++ b/{}\n", name));
+
+    for hunk in hunks(&edits) {
+        let (old_start, old_count, new_start, new_count) = hunk_range(&edits[hunk.clone()]);
+        diff.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            range(old_start, old_count),
+            range(new_start, new_count)
+        ));
+        for edit in &edits[hunk] {
+            let unterminated = match *edit {
+                Edit::Keep(i, _) => {
+                    diff.push_str(&format!(" {}\n", old_lines[i]));
+                    i + 1 == old_lines.len() && !old_text.ends_with('\n')
+                }
+                Edit::Delete(i) => {
+                    diff.push_str(&format!("-{}\n", old_lines[i]));
+                    i + 1 == old_lines.len() && !old_text.ends_with('\n')
+                }
+                Edit::Insert(j) => {
+                    diff.push_str(&format!("+{}\n", new_lines[j]));
+                    j + 1 == new_lines.len() && !new.ends_with('\n')
+                }
+            };
+            if unterminated {
+                diff.push_str("\\ No newline at end of file\n");
+            }
+        }
+    }
+    diff
+}
+
+/// One step of turning the old lines into the new, by line index.
+#[derive(Debug, Clone, Copy)]
+enum Edit {
+    Keep(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+// Myers' shortest edit script. Lines found on only one side can never be
+// kept, so they are left out of the search, which makes a full rewrite
+// cheap; the kept lines of the rest are then found in linear space
+fn diff_lines(old: &[&str], new: &[&str]) -> Vec<Edit> {
+    let in_old: HashSet<&str> = old.iter().copied().collect();
+    let in_new: HashSet<&str> = new.iter().copied().collect();
+    let old_shared: Vec<usize> = (0..old.len()).filter(|&i| in_new.contains(old[i])).collect();
+    let new_shared: Vec<usize> = (0..new.len()).filter(|&j| in_old.contains(new[j])).collect();
+    let a: Vec<&str> = old_shared.iter().map(|&i| old[i]).collect();
+    let b: Vec<&str> = new_shared.iter().map(|&j| new[j]).collect();
+    let mut kept = Vec::new();
+    common_lines(&a, &b, (0, 0), &mut kept);
+
+    // Everything between two kept lines is deleted, then inserted
+    let mut edits = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for (x, y) in kept {
+        let (x, y) = (old_shared[x], new_shared[y]);
+        edits.extend((i..x).map(Edit::Delete));
+        edits.extend((j..y).map(Edit::Insert));
+        edits.push(Edit::Keep(x, y));
+        (i, j) = (x + 1, y + 1);
+    }
+    edits.extend((i..old.len()).map(Edit::Delete));
+    edits.extend((j..new.len()).map(Edit::Insert));
+    edits
+}
+
+// The pairs of lines of a longest common subsequence of `a` and `b`, in
+// order and offset by `start`. Each call finds the middle snake of a
+// shortest edit script and recurses on either side of it.
+fn common_lines(a: &[&str], b: &[&str], start: (usize, usize), kept: &mut Vec<(usize, usize)>) {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    kept.extend((0..prefix).map(|i| (start.0 + i, start.1 + i)));
+    let (a, b) = (&a[prefix..], &b[prefix..]);
+    let suffix = a.iter().rev().zip(b.iter().rev()).take_while(|(x, y)| x == y).count();
+    let (a, b) = (&a[..a.len() - suffix], &b[..b.len() - suffix]);
+    let start = (start.0 + prefix, start.1 + prefix);
+
+    // With both ends differing, the script has at least two edits, so each
+    // side of the snake is a smaller problem
+    if !a.is_empty() && !b.is_empty() {
+        let ((x0, y0), (x1, y1)) = middle_snake(a, b);
+        common_lines(&a[..x0], &b[..y0], start, kept);
+        kept.extend((0..x1 - x0).map(|i| (start.0 + x0 + i, start.1 + y0 + i)));
+        common_lines(&a[x1..], &b[y1..], (start.0 + x1, start.1 + y1), kept);
+    }
+    kept.extend((0..suffix).map(|i| (start.0 + a.len() + i, start.1 + b.len() + i)));
+}
+
+// The start and end of the snake in the middle of a shortest edit script,
+// found by searching from both ends at once
+fn middle_snake(a: &[&str], b: &[&str]) -> ((usize, usize), (usize, usize)) {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let delta = n - m;
+    let max = (n + m + 1) / 2 + 1;
+    let offset = max + 1;
+    // The furthest x on each diagonal, forwards and from the end
+    let mut forward = vec![0isize; 2 * max as usize + 3];
+    let mut backward = vec![0isize; 2 * max as usize + 3];
+    let at = |k: isize| (k + offset) as usize;
+
+    for d in 0..=max {
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && forward[at(k - 1)] < forward[at(k + 1)]) {
+                forward[at(k + 1)]
+            } else {
+                forward[at(k - 1)] + 1
+            };
+            let (x0, y0) = (x, x - k);
+            while x < n && x - k < m && a[x as usize] == b[(x - k) as usize] {
+                x += 1;
+            }
+            forward[at(k)] = x;
+            let back = delta - k;
+            if delta % 2 != 0 && back > -d && back < d && x + backward[at(back)] >= n {
+                return ((x0 as usize, y0 as usize), (x as usize, (x - k) as usize));
+            }
+        }
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && backward[at(k - 1)] < backward[at(k + 1)]) {
+                backward[at(k + 1)]
+            } else {
+                backward[at(k - 1)] + 1
+            };
+            let (x0, y0) = (x, x - k);
+            while x < n && x - k < m && a[(n - x - 1) as usize] == b[(m - (x - k) - 1) as usize] {
+                x += 1;
+            }
+            backward[at(k)] = x;
+            let ahead = delta - k;
+            if delta % 2 == 0 && ahead >= -d && ahead <= d && x + forward[at(ahead)] >= n {
+                let (x1, y1) = (n - x, m - (x - k));
+                return ((x1 as usize, y1 as usize), ((n - x0) as usize, (m - y0) as usize));
+            }
+        }
+    }
+    unreachable!("a shortest edit script has a middle snake")
+}
+
+// The ranges of `edits` shown as hunks: each change with its context,
+// merging changes whose context overlaps
+fn hunks(edits: &[Edit]) -> Vec<std::ops::Range<usize>> {
+    let mut hunks: Vec<std::ops::Range<usize>> = Vec::new();
+    for (i, edit) in edits.iter().enumerate() {
+        if matches!(edit, Edit::Keep(..)) {
+            continue;
+        }
+        let start = i.saturating_sub(CONTEXT_LINES);
+        let end = (i + CONTEXT_LINES + 1).min(edits.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.end => last.end = end,
+            _ => hunks.push(start..end),
+        }
+    }
+    hunks
+}
+
+// 1-based starts and line counts on each side of a hunk
+fn hunk_range(edits: &[Edit]) -> (usize, usize, usize, usize) {
+    let (mut old_count, mut new_count) = (0, 0);
+    let (mut old_start, mut new_start) = (None, None);
+    for edit in edits {
+        match *edit {
+            Edit::Keep(i, j) => {
+                old_start.get_or_insert(i);
+                new_start.get_or_insert(j);
+                old_count += 1;
+                new_count += 1;
+            }
+            Edit::Delete(i) => {
+                old_start.get_or_insert(i);
+                old_count += 1;
+            }
+            Edit::Insert(j) => {
+                new_start.get_or_insert(j);
+                new_count += 1;
+            }
+        }
+    }
+    (old_start.map_or(0, |i| i + 1), old_count, new_start.map_or(0, |j| j + 1), new_count)
+}
+
+fn range(start: usize, count: usize) -> String {
+    if count == 1 {
+        start.to_string()
+    } else {
+        format!("{},{}", start, count)
+    }
+}
@@ -1,28 +1,880 @@
-use clap::{arg, command, ArgAction}; // Uncomment and remove Command
-use glob::Pattern;
-use std::collections::HashMap;
-use std::fs::{self, File};
-use std::io::{self, BufRead, Read, Write};
-use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::apply;
+use crate::clipboard;
+use crate::config;
+use crate::daemon;
+use crate::error::Context;
+use crate::collect::{
+    absolute_path, collect, collect_split, explain_rules, list_files, BinaryFiles, BudgetOrder, Options, OutputFormat,
+    PathSettings, Scorer, SortOrder, SymbolFilter, Truncate,
+};
+use crate::init;
+use crate::manifest;
+use crate::names;
+use crate::patch;
+use crate::picker;
+use crate::remote::{self, Revision};
+use crate::stats::{self, StatsFormat};
+use crate::suggest;
+use crate::template::Template;
+use crate::timestamp;
+use crate::github::{self, PullRequest};
+use crate::vfs::{CachedFs, FileSystem, MemoryFs, OverlayFs, RealFs, RootedFs};
+use clap::{arg, command, Arg, ArgAction, ArgGroup, ArgMatches};
+use notify::{RecursiveMode, Watcher};
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-static GLOBAL_INDEX: AtomicUsize = AtomicUsize::new(1);
+/// A run that failed with an exit code and nothing more to report, such as
+/// a `--pipe` command that failed; `main` exits with the code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Exit(pub i32);
 
+impl std::fmt::Display for Exit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "exited with status {}", self.0)
+    }
+}
+
+impl std::error::Error for Exit {}
+
+/// The names of the output formats for `convert`.
+const FORMAT_NAMES: [&str; 4] = ["default", "cxml", "markdown", "json"];
+
+/// How long --watch lets a burst of changes settle before writing again.
+const WATCH_SETTLE: Duration = Duration::from_millis(200);
 
 pub fn run() -> io::Result<()> {
+    let args = with_defaults(std::env::args_os().collect(), &RealFs)?;
+    let matches = cli_command().get_matches_from(&args);
+
+    if let Some(matches) = matches.subcommand_matches("suggest-ignores") {
+        return suggest_ignores(matches, &RealFs);
+    }
+    if let Some(matches) = matches.subcommand_matches("init") {
+        return init(matches, &RealFs);
+    }
+    if let Some(matches) = matches.subcommand_matches("apply") {
+        return apply(matches, &RealFs);
+    }
+    if let Some(matches) = matches.subcommand_matches("convert") {
+        return convert(matches, &RealFs);
+    }
+    if let Some(matches) = matches.subcommand_matches("daemon") {
+        return serve(matches);
+    }
+
+    if matches.get_flag("build-info") {
+        let mut out = io::stdout().lock();
+        return writeln!(out, "{}", serde_json::to_string_pretty(&build_info()).unwrap_or_default());
+    }
+
+    if let Some(socket) = matches.get_one::<String>("daemon-socket") {
+        // Piped paths or content go along with the command line
+        let mut stdin = None;
+        if !atty::is(atty::Stream::Stdin) {
+            let mut content = Vec::new();
+            io::stdin().lock().read_to_end(&mut content)?;
+            stdin = Some(content);
+        }
+        let response = daemon::request(Path::new(socket), &args[1..], stdin.as_deref())?;
+        io::stdout().lock().write_all(response.stdout.as_bytes())?;
+        io::stderr().lock().write_all(response.stderr.as_bytes())?;
+        if response.status != 0 {
+            return Err(io::Error::new(io::ErrorKind::Other, Exit(response.status)));
+        }
+        return Ok(());
+    }
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    let streams = Streams {
+        out: &mut out,
+        err: &mut io::stderr(),
+        stdin: &mut io::stdin(),
+        piped_stdin: !atty::is(atty::Stream::Stdin),
+        terminal: atty::is(atty::Stream::Stdout),
+        dir: Path::new(""),
+    };
+    collect_paths(&matches, &RealFs, streams)?;
+    out.flush()
+}
+
+/// Where the main command reads paths from and writes to: the process's
+/// own streams and working directory, or those of a daemon request.
+struct Streams<'a> {
+    out: &'a mut dyn Write,
+    err: &'a mut dyn Write,
+    /// Paths, or the content of `-`.
+    stdin: &'a mut dyn Read,
+    /// Whether more paths may be read from stdin.
+    piped_stdin: bool,
+    /// Whether `out` is a terminal, where large outputs are confirmed first.
+    terminal: bool,
+    /// The directory relative paths are taken from; empty for the working
+    /// directory. Reads go through the filesystem, which must agree.
+    dir: &'a Path,
+}
+
+/// Run the main command: collect the paths given and write the prompt.
+fn collect_paths(matches: &ArgMatches, disk: &dyn FileSystem, streams: Streams) -> io::Result<()> {
+    let Streams { out, err, stdin, piped_stdin, terminal, dir } = streams;
+
+    // Get paths from CLI args, or only from a manifest
+    let exact_manifest = matches.get_one::<String>("exact-manifest");
+    let mut entries: Vec<(PathBuf, PathSettings)> = match exact_manifest {
+        Some(manifest) => manifest::read_exact(Path::new(manifest), disk)?
+            .into_iter()
+            .map(|path| (path, PathSettings::default()))
+            .collect(),
+        None => matches.get_many::<String>("PATHS").unwrap_or_default().map(|path| annotated(PathBuf::from(path), disk)).collect(),
+    };
+
+    // `-` stands for content piped on stdin, which then holds no paths
+    let stdin_name = PathBuf::from(matches.get_one::<String>("stdin-name").map_or("stdin", String::as_str));
+    let stdin_content = entries.iter().any(|(path, _)| path.as_os_str() == "-");
+    if stdin_content {
+        for (path, _) in entries.iter_mut().filter(|(path, _)| path.as_os_str() == "-") {
+            *path = stdin_name.clone();
+        }
+    } else if piped_stdin && exact_manifest.is_none() {
+        // Read paths from stdin if available
+        let use_null_separator = matches.get_flag("null");
+        let json = matches.get_one::<String>("stdin-format").is_some_and(|format| format == "json");
+        entries.extend(read_paths_from_stdin(stdin, use_null_separator, json, disk)?);
+    }
+
+    let mut paths = Vec::new();
+    let mut path_settings = HashMap::new();
+    for (path, settings) in entries {
+        if settings != PathSettings::default() {
+            path_settings.insert(path.clone(), settings);
+        }
+        paths.push(path);
+    }
+
+    let has_inputs = !paths.is_empty()
+        || matches.contains_id("variant")
+        || matches.contains_id("from-patch")
+        || exact_manifest.is_some();
+    if !has_inputs && matches.get_flag("require-paths") {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "no paths given"));
+    }
+
+    // Run by hand with nothing to go on, or looking for changed files, the
+    // current directory is the natural input. Empty piped input stays empty.
+    if !has_inputs && (!piped_stdin || matches.contains_id("git-changes")) {
+        paths.push(PathBuf::from("."));
+    }
+
+    // Content fetched from pull requests or piped on stdin is served
+    // alongside the disk
+    let mut fetched = MemoryFs::new();
+    if stdin_content {
+        let mut content = Vec::new();
+        stdin.read_to_end(&mut content)?;
+        fetched.add_file(&stdin_name, content);
+    }
+    paths = expand_pull_requests(paths, disk, &mut fetched)?;
+    let fs = OverlayFs { upper: fetched, lower: disk };
+
+    // Repositories given by URL are cloned for the length of the run
+    let revision = match (matches.get_one::<String>("branch"), matches.get_one::<String>("rev")) {
+        (Some(branch), _) => Revision::Branch(branch.clone()),
+        (_, Some(rev)) => Revision::Commit(rev.clone()),
+        _ => Revision::Default,
+    };
+    let clones = clone_repositories(&mut paths, &revision, &fs)?;
+
+    let from_patch: Vec<PathBuf> = matches.get_many::<String>("from-patch").unwrap_or_default().map(PathBuf::from).collect();
+    paths = expand_patches(paths, from_patch, &fs)?;
+
+    let claude_xml = matches.get_flag("cxml");
+    let markdown = matches.get_flag("markdown");
+    let symbol = match matches.get_one::<String>("symbol") {
+        Some(name) => Some(SymbolFilter::new(
+            name,
+            matches.get_one::<usize>("symbol-context").copied(),
+            matches.get_one::<String>("tags").map(Path::new),
+            matches.get_one::<String>("lsif").map(Path::new),
+            &fs,
+        )?),
+        None => None,
+    };
+
+    let output_paths: Vec<PathBuf> = matches.get_many::<String>("output").unwrap_or_default().map(|path| dir.join(path)).collect();
+
+    let git_base = if let Some(git_ref) = matches.get_one::<String>("since-ref") {
+        Some(GitBase::MergeBase(git_ref.clone()))
+    } else if let Some(git_ref) = matches.get_one::<String>("since") {
+        Some(GitBase::Ref(git_ref.clone()))
+    } else {
+        matches.get_flag("git-diff").then_some(GitBase::WorkingTree)
+    };
+
+    let tails: Vec<(Option<String>, usize)> =
+        matches.get_many::<(Option<String>, usize)>("tail").unwrap_or_default().cloned().collect();
+    let tail_lines = tails.iter().rev().find(|(glob, _)| glob.is_none()).map(|(_, lines)| *lines);
+
+    let mut options = Options {
+        // Output files may live inside a walked directory; never read them back
+        excluded_paths: output_paths
+            .iter()
+            .map(|output_path| crate::collect::absolute_path(&fs, output_path))
+            .collect(),
+        line_numbers: matches.get_flag("line-numbers"),
+        cxml_lines: matches.get_flag("cxml-lines"),
+        cxml_strict: matches.get_flag("cxml-strict"),
+        truncate: match (matches.get_one::<usize>("max-lines").or(matches.get_one::<usize>("head")), tail_lines) {
+            (Some(&lines), None) => Some(Truncate::Head(lines)),
+            (None, Some(lines)) => Some(Truncate::Tail(lines)),
+            (None, None) => None,
+            (Some(_), Some(_)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "--tail N cannot be used with --head or --max-lines; give --tail GLOB=N to keep the end of some files",
+                ))
+            }
+        },
+        tail_patterns: tails.iter().filter_map(|(glob, lines)| Some((glob.clone()?, *lines))).collect(),
+        strip_comments_for: matches.get_many::<String>("strip-comments-for").unwrap_or_default().cloned().collect(),
+        strip_comments: matches.get_flag("strip-comments"),
+        strip_blank_lines: matches.get_flag("strip-blank-lines"),
+        redact_secrets: matches.get_flag("redact-secrets"),
+        nbconvert: matches.get_flag("nbconvert"),
+        nb_strip_output: matches.get_flag("nb-strip-output"),
+        split_sfc: matches.get_flag("split-sfc"),
+        template: match matches.get_one::<String>("template") {
+            Some(path) => Some(read_template(Path::new(path), &fs)?),
+            None => None,
+        },
+        expand_tabs: matches.get_flag("expand-tabs"),
+        with_tests: matches.get_flag("with-tests"),
+        interleave: matches.get_flag("interleave"),
+        include_empty_dirs: matches.get_flag("include-empty-dirs"),
+        timeout: matches.get_one::<Duration>("timeout").copied(),
+        symbol,
+        todos: matches.get_flag("todos"),
+        entry_points: matches.get_flag("entry-points"),
+        path_contains: matches.get_many::<String>("path-contains").unwrap_or_default().cloned().collect(),
+        path_regex: matches.get_many::<Regex>("path-regex").unwrap_or_default().cloned().collect(),
+        sample_fraction: matches.get_one::<f64>("sample-frac").copied(),
+        seed: matches.get_one::<u64>("seed").copied().unwrap_or_default(),
+        annotations: matches.get_many::<(Regex, String)>("annotate").unwrap_or_default().cloned().collect(),
+        todo_context: matches.get_one::<usize>("todo-context").copied(),
+        command_header: if matches.get_flag("emit-command-header") {
+            command_header(matches.get_flag("local-time"), dir)
+        } else {
+            Vec::new()
+        },
+        git_log: match matches.get_one::<usize>("git-log") {
+            Some(&count) => git_log(count, matches.get_flag("git-log-scoped").then_some(&paths[..]), dir, err)?,
+            None => Vec::new(),
+        },
+        tree: matches.get_flag("tree"),
+        show_skipped: matches.get_flag("show-skipped"),
+        embed_warnings: matches.get_flag("embed-warnings"),
+        repeat_header: matches.get_one::<u64>("repeat-header").map(|&n| n as usize),
+        doc_separator: matches.get_one::<String>("doc-separator").cloned(),
+        turns: matches.get_one::<u64>("turns").map(|&n| n as usize),
+        max_bytes: [
+            matches.get_one::<usize>("max-bytes").copied(),
+            matches.get_one::<usize>("max-tokens").map(|&tokens| tokens.saturating_mul(stats::BYTES_PER_TOKEN)),
+        ]
+        .into_iter()
+        .flatten()
+        .min(),
+        budget_order: match matches.get_one::<String>("budget-order").map(String::as_str) {
+            Some("smallest") => BudgetOrder::Smallest,
+            _ => BudgetOrder::Path,
+        },
+        scorer: matches.get_one::<String>("score-cmd").map(|command| score_command(command.clone(), dir)),
+        sort: match matches.get_one::<String>("sort").map(String::as_str) {
+            Some("name") => Some(SortOrder::Name),
+            Some("path") => Some(SortOrder::Path),
+            Some("size") => Some(SortOrder::Size),
+            Some("mtime") => Some(SortOrder::Mtime),
+            Some("extension") => Some(SortOrder::Extension),
+            _ => None,
+        },
+        reverse: matches.get_flag("reverse"),
+        start_index: matches.get_one::<usize>("start-index").copied(),
+        binary: match matches.get_one::<String>("binary").map(String::as_str) {
+            Some("summary") => BinaryFiles::Summary,
+            Some("error") => BinaryFiles::Error,
+            _ => BinaryFiles::Skip,
+        },
+        binary_preview: matches.get_one::<usize>("binary-preview").copied().unwrap_or_default(),
+        decompress: matches.get_flag("decompress"),
+        extract: matches.get_flag("extract"),
+        lossy: matches.get_flag("lossy"),
+        mention_excluded: matches.get_flag("mention-excluded"),
+        only_paths: match &git_base {
+            Some(base) => Some(changed_files(base, dir, &fs)?),
+            None => None,
+        },
+        label_root: if matches.get_flag("paths-from-root") {
+            git_root(dir, &fs, err)?
+        } else {
+            None
+        },
+        stats: matches.get_flag("stats").then(|| match matches.get_one::<String>("stats-format").map(String::as_str) {
+            Some("json") => StatsFormat::Json,
+            Some("csv") => StatsFormat::Csv,
+            _ => StatsFormat::Table,
+        }),
+        strict: matches.get_flag("strict"),
+        profile: matches.get_flag("profile"),
+        cache_blocks: matches.get_flag("cache-blocks"),
+        path_separator: matches.get_one::<String>("path-separator").and_then(|sep| sep.chars().next()),
+        max_path_length: matches.get_one::<u64>("max-path-length").map(|&max| max as usize),
+        path_settings,
+        root_names: clones.iter().map(|clone| (clone.dir.clone(), clone.name.clone())).collect(),
+        variants: matches.get_many::<(String, PathBuf)>("variant").unwrap_or_default().cloned().collect(),
+        ..walk_options(matches, dir)
+    };
+
+    if let Some(path) = matches.get_one::<String>("explain-rules") {
+        return explain_rules(Path::new(path), &options, &fs, out);
+    }
+    if matches.get_flag("estimate") {
+        return estimate(&paths, &options, &fs, out, err);
+    }
+    if matches.get_flag("list") {
+        return list(&paths, &options, &fs, matches.get_flag("long"), out, err);
+    }
+
+    // With --diff-only each changed file is replaced by its diff
+    let mut diffs = MemoryFs::new();
+    if let (Some(base), true) = (&git_base, matches.get_flag("diff-only")) {
+        let files = list_files(&paths, &options, &fs, &mut io::sink())?;
+        paths = diff_files(base, dir, &files, &fs, &mut diffs)?;
+        options.only_paths = None;
+    }
+    let fs = OverlayFs { upper: diffs, lower: fs };
+
+    // The files chosen by hand are the only ones collected from here on
+    if matches.get_flag("interactive") {
+        let files = list_files(&paths, &options, &fs, &mut io::sink())?;
+        let entries: Vec<picker::Entry> =
+            files.iter().map(|file| picker::Entry { path: file.clone(), bytes: fs.size(file).unwrap_or(0) }).collect();
+        let Some(chosen) = picker::pick(&entries)? else {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "no files chosen, the picker was cancelled"));
+        };
+        let chosen = files.iter().zip(chosen).filter(|(_, chosen)| *chosen).map(|(file, _)| absolute_path(&fs, file));
+        options.only_paths = Some(chosen.collect());
+    }
+
+    let requested = if claude_xml {
+        Some(OutputFormat::Cxml)
+    } else if markdown {
+        Some(OutputFormat::Markdown)
+    } else if matches.get_flag("json") {
+        Some(OutputFormat::Json)
+    } else {
+        None
+    };
+
+    let escape = matches.get_one::<String>("escape").map(String::as_str).filter(|&mode| mode != "none");
+
+    if let Some(manifest_path) = matches.get_one::<String>("write-manifest") {
+        let files = list_files(&paths, &options, &fs, &mut io::sink())?;
+        let manifest_path = dir.join(manifest_path);
+        let mut file = create_output(&manifest_path)?;
+        manifest::write(&files, &fs, matches.get_flag("local-time"), &mut file)?;
+        file.flush().context("write", &manifest_path)?;
+    }
+
+    if let Some(out_dir) = matches.get_one::<String>("out-dir") {
+        options.format = requested.unwrap_or_default();
+        return write_by_dir(&paths, &mut options, &fs, escape, &dir.join(out_dir), err);
+    }
+
+    if let Some(command) = matches.get_one::<String>("pipe") {
+        options.format = requested.unwrap_or_default();
+        return pipe_to(command, dir, |out| render(&paths, &options, &fs, escape, out, err));
+    }
+
+    if matches.get_flag("copy") {
+        options.format = requested.unwrap_or_default();
+        let mut buffer = Vec::new();
+        render(&paths, &options, &fs, escape, &mut buffer, err)?;
+        confirm_size(matches, buffer.len())?;
+        clipboard::copy(&String::from_utf8_lossy(&buffer))?;
+        writeln!(
+            err,
+            "Copied {} ({} tokens) to the clipboard",
+            stats::format_size(buffer.len() as u64),
+            stats::format_tokens(stats::estimate_tokens(buffer.len()))
+        )?;
+        return Ok(());
+    }
+
+    // Setup output writers, rendering once per output
+    if output_paths.is_empty() {
+        options.format = requested.unwrap_or_default();
+
+        if matches.get_flag("yes") || !terminal {
+            return render(&paths, &options, &fs, escape, out, err);
+        }
+
+        // Hold the output back until we know it is not an accidental dump
+        let mut buffer = Vec::new();
+        render(&paths, &options, &fs, escape, &mut buffer, err)?;
+        confirm_size(matches, buffer.len())?;
+        return out.write_all(&buffer);
+    }
+
+    let mut outputs = Vec::new();
+    for output_path in &output_paths {
+        let inferred = OutputFormat::from_extension(output_path);
+
+        let format = match (requested, inferred) {
+            (Some(requested), Some(inferred)) if requested != inferred => {
+                writeln!(
+                    err,
+                    "Warning: writing {} output to {} despite its {} extension",
+                    requested.name(),
+                    output_path.display(),
+                    inferred.name()
+                )?;
+                requested
+            }
+            (Some(format), _) | (None, Some(format)) => format,
+            (None, None) => OutputFormat::Default,
+        };
+        outputs.push((output_path.as_path(), format));
+    }
+    let split = matches.get_one::<u64>("split-bytes").map(|&bytes| bytes as usize).or_else(|| {
+        matches.get_one::<u64>("split-tokens").map(|&tokens| (tokens as usize).saturating_mul(stats::BYTES_PER_TOKEN))
+    });
+    if !matches.get_flag("watch") {
+        return write_outputs(&paths, &mut options, &fs, escape, split, &outputs, err);
+    }
+
+    // Events only say that something under the inputs changed. Comparing
+    // the included files says whether it matters, and also notices files
+    // that appear, disappear or become ignored. Watching starts before the
+    // first write, so no change goes unseen.
+    let (sender, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(watch_error)?;
+    for path in &paths {
+        let mode = if fs.is_dir(path) { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+        if let Err(e) = watcher.watch(path, mode) {
+            writeln!(err, "Warning: can't watch {}: {}", path.display(), e)?;
+        }
+    }
+    let mut stamps = file_stamps(&paths, &options, &fs)?;
+    write_outputs(&paths, &mut options, &fs, escape, split, &outputs, err)?;
+    writeln!(err, "Watching {} files for changes, press Ctrl-C to stop", stamps.len())?;
+    loop {
+        match events.recv() {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => {
+                writeln!(err, "Warning: {}", e)?;
+                continue;
+            }
+            Err(_) => return Ok(()),
+        }
+        // Saving or checking out files changes many at once
+        while events.recv_timeout(WATCH_SETTLE).is_ok() {}
+
+        // A file can vanish mid-walk; the next change gets another try
+        let current = match file_stamps(&paths, &options, &fs) {
+            Ok(current) => current,
+            Err(e) => {
+                writeln!(err, "Warning: {}", e)?;
+                continue;
+            }
+        };
+        let changed = stamps.keys().chain(current.keys()).collect::<HashSet<_>>();
+        let changed = changed.into_iter().filter(|file| stamps.get(*file) != current.get(*file)).count();
+        if changed == 0 {
+            continue;
+        }
+
+        if let Err(e) = write_outputs(&paths, &mut options, &fs, escape, split, &outputs, err) {
+            writeln!(err, "Warning: could not update the output: {}", e)?;
+            continue;
+        }
+        let names: Vec<String> = outputs.iter().map(|(path, _)| path.display().to_string()).collect();
+        writeln!(err, "Updated {} after {} changed file{}", names.join(", "), changed, if changed == 1 { "" } else { "s" })?;
+        stamps = current;
+    }
+}
+
+fn watch_error(e: notify::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("can't watch for changes: {}", e))
+}
+
+/// Print how many files a run would include and their size, from the
+/// directory walk alone.
+fn estimate(
+    paths: &[PathBuf],
+    options: &Options,
+    fs: &dyn FileSystem,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> io::Result<()> {
+    let files = list_files(paths, options, fs, err)?;
+    let files: Vec<&PathBuf> = files.iter().filter(|file| fs.is_file(file)).collect();
+    let bytes: u64 = files.iter().map(|file| fs.size(file).unwrap_or(0)).sum();
+    writeln!(
+        out,
+        "{} file{}, {}, {} tokens",
+        stats::group_thousands(files.len() as u64),
+        if files.len() == 1 { "" } else { "s" },
+        stats::format_size(bytes),
+        stats::format_tokens(stats::estimate_tokens(bytes as usize))
+    )
+}
+
+/// Print the path of each file a run would include, one per line, from the
+/// directory walk alone. With `long` each path follows its size and
+/// estimated tokens.
+fn list(
+    paths: &[PathBuf],
+    options: &Options,
+    fs: &dyn FileSystem,
+    long: bool,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> io::Result<()> {
+    let files = list_files(paths, options, fs, err)?;
+    for file in files.iter().filter(|file| fs.is_file(file)) {
+        if long {
+            let bytes = fs.size(file).unwrap_or(0);
+            let tokens = stats::format_tokens(stats::estimate_tokens(bytes as usize));
+            writeln!(out, "{:>9}  {:>5} tokens  {}", stats::format_size(bytes), tokens, file.display())?;
+        } else {
+            writeln!(out, "{}", file.display())?;
+        }
+    }
+    Ok(())
+}
+
+/// Render the prompt into each output file in its own format. With `split`,
+/// each is written as numbered parts of at most that many bytes instead.
+fn write_outputs(
+    paths: &[PathBuf],
+    options: &mut Options,
+    fs: &dyn FileSystem,
+    escape: Option<&str>,
+    split: Option<usize>,
+    outputs: &[(&Path, OutputFormat)],
+    err: &mut dyn Write,
+) -> io::Result<()> {
+    for &(output_path, format) in outputs {
+        options.format = format;
+        if let Some(max_bytes) = split {
+            let open_part = |number| -> io::Result<Box<dyn Write>> {
+                Ok(Box::new(create_output(&part_path(output_path, number))?))
+            };
+            let count = collect_split(paths, options, fs, max_bytes, err, open_part)?;
+            writeln!(err, "Wrote {} part{} of {}", count, if count == 1 { "" } else { "s" }, output_path.display())?;
+            continue;
+        }
+        let mut file = create_output(output_path)?;
+        render(paths, options, fs, escape, &mut file, err)?;
+        file.flush().context("write", output_path)?;
+    }
+    Ok(())
+}
+
+/// Render the prompt into `dir` once for each top-level directory of the
+/// inputs, in a file named after it with the extension of the format. Files
+/// directly in an input, or given as inputs, go in `root`.
+fn write_by_dir(
+    paths: &[PathBuf],
+    options: &mut Options,
+    fs: &dyn FileSystem,
+    escape: Option<&str>,
+    dir: &Path,
+    err: &mut dyn Write,
+) -> io::Result<()> {
+    // Earlier output in the directory is not read back
+    let out_dir = absolute_path(fs, dir);
+    let mut groups: Vec<(String, Vec<PathBuf>, HashSet<PathBuf>)> = Vec::new();
+    for path in paths {
+        for file in list_files(std::slice::from_ref(path), options, fs, err)? {
+            let absolute = absolute_path(fs, &file);
+            if absolute.starts_with(&out_dir) {
+                continue;
+            }
+            let below = file.strip_prefix(path).unwrap_or(&file);
+            let mut components = below.components().filter(|component| matches!(component, Component::Normal(_)));
+            let name = match (components.next(), components.next()) {
+                (Some(top), Some(_)) => top.as_os_str().to_string_lossy().into_owned(),
+                _ => "root".to_string(),
+            };
+            let index = match groups.iter().position(|(group, _, _)| *group == name) {
+                Some(index) => index,
+                None => {
+                    groups.push((name, Vec::new(), HashSet::new()));
+                    groups.len() - 1
+                }
+            };
+            let (_, inputs, files) = &mut groups[index];
+            if !inputs.contains(path) {
+                inputs.push(path.clone());
+            }
+            files.insert(absolute);
+        }
+    }
+
+    // Each file is rendered from the inputs it was found in, restricted to
+    // its group
+    for (name, inputs, files) in &groups {
+        let output_path = dir.join(format!("{}.{}", name, options.format.extension()));
+        options.only_paths = Some(files.clone());
+        let mut file = create_output(&output_path)?;
+        render(inputs, options, fs, escape, &mut file, err)?;
+        file.flush().context("write", &output_path)?;
+    }
+    let count = groups.len();
+    writeln!(err, "Wrote {} file{} to {}", count, if count == 1 { "" } else { "s" }, dir.display())
+}
+
+/// Create an output file, along with any directories it goes in that are
+/// missing.
+fn create_output(path: &Path) -> io::Result<BufWriter<File>> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent).context("create directory", parent)?;
+    }
+    File::create(path).map(BufWriter::new).context("create", path)
+}
+
+/// Where part `number` of the output `path` goes: `out.txt` becomes
+/// `out.part1.txt`.
+fn part_path(path: &Path, number: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}.part{}.{}", stem, number, extension.to_string_lossy()),
+        None => format!("{}.part{}", stem, number),
+    };
+    path.with_file_name(name)
+}
+
+/// The modification time and size of every file a run would include, for
+/// --watch to notice changes.
+fn file_stamps(
+    paths: &[PathBuf],
+    options: &Options,
+    fs: &dyn FileSystem,
+) -> io::Result<HashMap<PathBuf, Option<(SystemTime, u64)>>> {
+    let files = list_files(paths, options, fs, &mut io::sink())?;
+    Ok(files
+        .into_iter()
+        .map(|file| {
+            let stamp = fs.modified(&file).zip(fs.size(&file).ok());
+            (file, stamp)
+        })
+        .collect())
+}
+
+/// `args` with the defaults from the configuration file and environment
+/// put before the command line, which is parsed first to see what it
+/// already sets.
+fn with_defaults(args: Vec<OsString>, fs: &dyn FileSystem) -> io::Result<Vec<OsString>> {
+    let matches = cli_command().get_matches_from(&args);
+    if matches.subcommand().is_some() {
+        return Ok(args);
+    }
+
+    let defaults = config::default_args(&cli_command(), &matches, Path::new("."), fs)?;
+    let (program, given) = args.split_at(1);
+    Ok(program.iter().chain(&defaults).chain(given).cloned().collect())
+}
+
+/// Answer collection requests on a socket until killed, reading the disk
+/// through a cache shared by every request.
+fn serve(matches: &ArgMatches) -> io::Result<()> {
+    let socket = matches.get_one::<String>("socket").map(PathBuf::from).unwrap_or_else(daemon::default_socket);
+    let disk = CachedFs::new();
+
+    daemon::serve(&socket, |request| {
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        let status = match answer(&request, &disk, &mut stdout, &mut stderr) {
+            Ok(()) => 0,
+            Err(e) => match e.get_ref().and_then(|inner| inner.downcast_ref::<Exit>()) {
+                Some(Exit(code)) => *code,
+                None => {
+                    let _ = writeln!(stderr, "Error: {}", e);
+                    1
+                }
+            },
+        };
+        daemon::Response {
+            status,
+            stdout: String::from_utf8_lossy(&stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&stderr).into_owned(),
+        }
+    })
+}
+
+// The client has already applied its configuration file and environment,
+// so the arguments are used as they are
+fn answer(request: &daemon::Request, disk: &CachedFs, out: &mut dyn Write, err: &mut dyn Write) -> io::Result<()> {
+    let args = std::iter::once("files-to-prompt").chain(request.args.iter().map(String::as_str));
+    let matches = cli_command()
+        .try_get_matches_from(args)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string().trim_end().to_string()))?;
+    if matches.subcommand().is_some() || matches.get_flag("watch") {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "the daemon only runs one-off collections"));
+    }
+    // These act on the daemon's own process and terminal, not the client's
+    for option in ["pipe", "copy", "interactive"] {
+        if matches.value_source(option) == Some(clap::parser::ValueSource::CommandLine) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("the daemon cannot run --{}", option)));
+        }
+    }
+
+    // Relative paths are the client's, so they are taken from its directory
+    let disk = RootedFs { root: request.cwd.clone(), fs: disk };
+    let streams = Streams {
+        out,
+        err,
+        stdin: &mut request.stdin.as_deref().unwrap_or_default(),
+        piped_stdin: request.stdin.is_some(),
+        terminal: false,
+        dir: &request.cwd,
+    };
+    collect_paths(&matches, &disk, streams)
+}
+
+/// What this build is and supports, for integrations to check before
+/// passing newer options.
+fn build_info() -> serde_json::Value {
+    let command = cli_command();
+    let mut options: Vec<&str> = command.get_arguments().filter_map(|arg| arg.get_long()).collect();
+    options.sort_unstable();
+    let subcommands: Vec<&str> = command.get_subcommands().map(|command| command.get_name()).collect();
+    // The crate has no optional cargo features; platform-dependent parts
+    // are listed instead
+    let features: Vec<&str> = if cfg!(unix) { vec!["daemon"] } else { Vec::new() };
+
+    serde_json::json!({
+        "schema_version": crate::SCHEMA_VERSION,
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_commit": option_env!("FILES_TO_PROMPT_GIT_COMMIT"),
+        "target": env!("FILES_TO_PROMPT_TARGET"),
+        "features": features,
+        "formats": ["default", "cxml", "markdown", "json", "template"],
+        "tokenizers": [{"name": "bytes", "bytes_per_token": stats::BYTES_PER_TOKEN}],
+        "options": options,
+        "subcommands": subcommands,
+    })
+}
+
+/// The command line of the main command and its subcommands.
+fn cli_command() -> clap::Command {
     // Fix the -0 flag by using the more verbose Arg construction instead of arg! macro
-    let matches = command!()
+    command!()
         .about("Concatenate a directory full of files into a single prompt for use with LLMs")
-        .arg(arg!([PATHS] ... "Paths to files or directories").required(false))
-        .arg(arg!(-e --extension <EXT> ... "File extensions to include"))
-        .arg(arg!(--"include-hidden" "Include files and folders starting with .").action(ArgAction::SetTrue))
-        .arg(arg!(--"ignore-files-only" "--ignore option only ignores files").action(ArgAction::SetTrue))
-        .arg(arg!(--"ignore-gitignore" "Ignore .gitignore files and include all files").action(ArgAction::SetTrue))
-        .arg(arg!(--ignore <PATTERN> ... "List of patterns to ignore"))
-        .arg(arg!(-o --output <FILE> "Output to a file instead of stdout"))
+        .arg(arg!([PATHS] ... "Paths to files or directories, or - for content piped on stdin").required(false))
+        .arg(arg!(--"build-info" "Print the version, commit and supported formats and options as JSON, and exit").visible_alias("version-json").action(ArgAction::SetTrue))
+        .arg(arg!(--"stdin-name" <NAME> "The document name for content read from stdin with -").default_value("stdin"))
+        .args(walk_args())
+        .arg(arg!(--"include-empty-dirs" "Include a placeholder entry for each empty directory").action(ArgAction::SetTrue))
+        .arg(arg!(--"write-manifest" <FILE> "Write the files included, with their sizes and content hashes, to FILE as JSON"))
+        .arg(arg!(--"exact-manifest" <FILE> "Include exactly the files a --write-manifest FILE lists, failing if any is missing or has changed").conflicts_with_all(["PATHS", "from-patch", "variant", "git-changes", "with-tests", "sample-frac", "path-contains", "path-regex", "entry-points"]))
+        .arg(arg!(--branch <BRANCH> "Clone this branch or tag of repositories given by URL instead of their default branch"))
+        .arg(arg!(--rev <REV> "Clone this commit of repositories given by URL").conflicts_with("branch"))
+        .arg(arg!(--"from-patch" <FILE> ... "Include a patch and the current contents of every file it touches"))
+        .arg(arg!(--variant <NAME_PATH> ... "Output matching files from several roots side by side, as NAME=PATH").value_parser(parse_variant))
+        .arg(arg!(-o --output <FILE> ... "Output to a file instead of stdout; the format is inferred from a .md, .xml or .json extension"))
+        .arg(arg!(--escape <MODE> "Escape the whole output as a JSON string or a single-quoted shell argument").value_parser(["json", "shell", "none"]).default_value("none"))
+        .arg(arg!(--pipe <COMMAND> "Stream the output into the stdin of a shell command and exit with its status").conflicts_with("output"))
+        .arg(arg!(-C --copy "Copy the output to the clipboard instead of printing it").action(ArgAction::SetTrue).conflicts_with_all(["output", "pipe"]))
         .arg(arg!(-c --cxml "Output in XML-ish format suitable for Claude's long context window").action(ArgAction::SetTrue))
+        .arg(arg!(--"cxml-lines" "Write each line as a <line n=\"N\"> element in XML output").action(ArgAction::SetTrue))
+        .arg(arg!(--"cxml-strict" "Keep XML output well-formed, with contents in CDATA sections").action(ArgAction::SetTrue))
         .arg(arg!(-m --markdown "Output Markdown with fenced code blocks").action(ArgAction::SetTrue))
+        .arg(arg!(--json "Output a JSON array of {path, content, size, extension} objects").action(ArgAction::SetTrue))
+        .arg(arg!(--template <FILE> "Write each file with a template using {{ path }}, {{ content }}, {{ index }}, {{ extension }} and {{ lang }}").conflicts_with_all(["cxml", "markdown", "json"]))
+        .arg(arg!(--"emit-command-header" "Start the output with a comment recording the command line, version, time and git commit").action(ArgAction::SetTrue))
+        .arg(arg!(--"local-time" "Write times in the command header and manifests in the local time zone instead of UTC").action(ArgAction::SetTrue))
+        .arg(arg!(--"git-log" <N> "Start with the subjects of the last N commits").value_parser(clap::value_parser!(usize)))
+        .arg(arg!(--"show-skipped" "Write a one-line placeholder where an ignored, unreadable or over-budget file was left out").action(ArgAction::SetTrue))
+        .arg(arg!(--"embed-warnings" "End the output with a section repeating the warnings, such as skipped files").action(ArgAction::SetTrue))
+        .arg(arg!(--tree "Start with a tree of the included files").action(ArgAction::SetTrue))
+        .arg(arg!(--"git-log-scoped" "Only list commits touching the given paths with --git-log").action(ArgAction::SetTrue))
+        .arg(arg!(--"repeat-header" <N> "Repeat a map of the included files every N thousand tokens").value_parser(clap::value_parser!(u64).range(1..)))
+        .arg(arg!(--turns <N> "Split the output into N parts of similar size, to paste over several messages").value_parser(clap::value_parser!(u64).range(1..)))
+        .arg(arg!(--"split-tokens" <N> "Write each -o file as parts of about N tokens at most, named like out.part1.txt").value_parser(clap::value_parser!(u64).range(1..)).requires("output").conflicts_with_all(["turns", "escape"]))
+        .arg(arg!(--"split-bytes" <N> "Write each -o file as parts of N bytes at most").value_parser(clap::value_parser!(u64).range(1..)).requires("output").conflicts_with_all(["split-tokens", "turns", "escape"]))
+        .arg(arg!(--"split-by-dir" "Write one file per top-level directory of the inputs to --out-dir, named like backend.md").action(ArgAction::SetTrue).requires("out-dir").conflicts_with_all(["output", "pipe", "copy"]))
+        .arg(arg!(--"out-dir" <DIR> "The directory --split-by-dir writes its files to").requires("split-by-dir"))
+        .arg(arg!(--"max-tokens" <N> "Leave out files once the output would pass about N tokens").value_parser(clap::value_parser!(usize)))
+        .arg(arg!(--"max-bytes" <N> "Leave out files once the file contents would pass N bytes").value_parser(clap::value_parser!(usize)))
+        .arg(arg!(--"budget-order" <ORDER> "Which files fill the --max-tokens or --max-bytes budget first").value_parser(["path", "smallest"]).default_value("path"))
+        .arg(arg!(--"score-cmd" <COMMAND> "Fill the --max-tokens or --max-bytes budget with the files a shell command scores highest first"))
+        .arg(arg!(--sort <ORDER> "Write the files of all inputs in one order instead of walk order").value_parser(["name", "path", "size", "mtime", "extension"]))
+        .arg(arg!(--reverse "Reverse the --sort order").action(ArgAction::SetTrue).requires("sort"))
+        .arg(arg!(--"start-index" <N> "Number documents from N, so outputs of several runs can be put together").value_parser(clap::value_parser!(usize)))
+        .arg(arg!(--binary <MODE> "What to do with files that are not UTF-8 text: skip them, write a summary in their place, or fail").value_parser(["skip", "summary", "error"]).default_value("skip"))
+        .arg(arg!(--"binary-preview" <BYTES> "Add a hex dump of this many leading bytes to each binary summary").value_parser(clap::value_parser!(usize)))
+        .arg(arg!(--decompress "Include the decompressed content of .gz and .zst files, using the gzip and zstd commands").action(ArgAction::SetTrue))
+        .arg(arg!(--extract "Include the text of HTML, PDF and .docx files instead of their markup or bytes").action(ArgAction::SetTrue))
+        .arg(arg!(--lossy "Replace bytes that are not valid text instead of skipping the file").action(ArgAction::SetTrue))
+        .arg(arg!(--"doc-separator" <TEXT> "Write TEXT on its own line between documents (not used with --cxml)"))
+        .arg(arg!(--"mention-excluded" "End with a list of local files that included files import but that were left out").action(ArgAction::SetTrue))
+        .arg(arg!(--"paths-from-root" "Show every path relative to the root of the git repository").action(ArgAction::SetTrue))
+        .arg(arg!(--"since-ref" <REF> "Only include files changed since the branch diverged from REF, e.g. origin/main"))
+        .arg(arg!(--since <REF> "Only include files that differ from the commit or branch REF"))
+        .arg(arg!(--"git-diff" "Only include files with uncommitted changes, including untracked files").action(ArgAction::SetTrue))
+        .group(ArgGroup::new("git-changes").args(["since-ref", "since", "git-diff"]))
+        .arg(arg!(--"diff-only" "Output the git diff of each changed file instead of its contents").action(ArgAction::SetTrue).requires("git-changes"))
+        .arg(arg!(--estimate "Only walk the paths and print how many files, bytes and tokens a run would include, then exit").action(ArgAction::SetTrue))
+        .arg(arg!(--list "Only print the paths of the files a run would include, one per line, then exit").action(ArgAction::SetTrue).conflicts_with_all(["estimate", "output", "pipe", "copy", "split-by-dir"]))
+        .arg(arg!(-l --long "With --list, print the size and estimated tokens of each file before its path").action(ArgAction::SetTrue).requires("list"))
+        .arg(arg!(--"explain-rules" <PATH> "Show every ignore rule consulted for PATH and whether it would be included, then exit"))
+        .arg(arg!(--"path-separator" <SEP> "Separator written between path components in headers and messages").value_parser(["/", "\\"]))
+        .arg(arg!(--"max-path-length" <N> "Shorten paths longer than N characters in headers by eliding middle directories").value_parser(clap::value_parser!(u64).range(1..)))
+        .arg(arg!(--"cache-blocks" "Output each file as a self-contained block headed by a content hash, sorted by path, to help prompt caching").action(ArgAction::SetTrue))
+        .arg(arg!(--stats "Report the size and estimated tokens of each file on stderr, then the lines, skipped files and time taken").action(ArgAction::SetTrue))
+        .arg(arg!(--"stats-format" <FORMAT> "Layout of the --stats report").value_parser(["table", "json", "csv"]).default_value("table"))
+        .arg(arg!(--strict "Exit with an error after writing the output if a path was missing or a file could not be read").action(ArgAction::SetTrue))
+        .arg(arg!(--profile "Report the time spent walking, reading, transforming, tokenizing and writing, and the slowest files, as JSON on stderr").action(ArgAction::SetTrue))
         .arg(arg!(-n --"line-numbers" "Add line numbers to the output").action(ArgAction::SetTrue))
+        .arg(arg!(--"max-lines" <N> "Truncate each file to its first N lines, noting how many were cut").value_parser(clap::value_parser!(usize)).conflicts_with("head"))
+        .arg(arg!(--head <N> "Truncate each file to its first N lines, the same as --max-lines").value_parser(clap::value_parser!(usize)))
+        .arg(arg!(--tail <TAIL> ... "Truncate each file to its last N lines, noting how many were cut, or only files matching a glob, as GLOB=N").value_parser(parse_tail))
+        .arg(arg!(--"strip-comments-for" <GLOB> ... "Remove comments and docstrings from files matching a glob, dropping the lines left empty"))
+        .arg(arg!(--"strip-comments" "Remove comments and docstrings from every file, dropping the lines left empty").action(ArgAction::SetTrue))
+        .arg(arg!(--"strip-blank-lines" "Remove empty and whitespace-only lines from every file").action(ArgAction::SetTrue))
+        .arg(arg!(--"redact-secrets" "Replace keys, tokens and other secrets in files with [REDACTED:<type>]").action(ArgAction::SetTrue))
+        .arg(arg!(--nbconvert "Write Jupyter notebooks as Markdown with fenced code cells instead of JSON").action(ArgAction::SetTrue))
+        .arg(arg!(--"nb-strip-output" "Leave cell outputs out of notebooks written with --nbconvert").action(ArgAction::SetTrue).requires("nbconvert"))
+        .arg(arg!(--"split-sfc" "Write the template, script and style of .vue and .svelte files as separate documents").action(ArgAction::SetTrue))
+        .arg(arg!(--"expand-tabs" "Replace tabs with spaces using the tab width from .editorconfig").action(ArgAction::SetTrue))
+        .arg(arg!(--interleave "Take files from each top-level directory in turn instead of one directory at a time").action(ArgAction::SetTrue))
+        .arg(arg!(--"with-tests" "Also include the conventional test file for each selected source file").action(ArgAction::SetTrue))
+        .arg(arg!(--symbol <NAME> "Only include files that define or reference this symbol"))
+        .arg(arg!(--tags <FILE> "ctags file used to find the definition of --symbol"))
+        .arg(arg!(--lsif <FILE> "LSIF dump used to find the definition and references of --symbol"))
+        .arg(arg!(--"symbol-context" <LINES> "Only output the lines within LINES of each symbol match").value_parser(clap::value_parser!(usize)))
+        .arg(arg!(--annotate <RULE> ... "Mark lines matching a regex with a label, as REGEX=LABEL").value_parser(parse_annotation))
+        .arg(arg!(--"sample-frac" <FRACTION> "Only include a reproducible random sample of about this fraction of the files").value_parser(parse_fraction))
+        .arg(arg!(--seed <N> "Seed choosing the files for --sample-frac").value_parser(clap::value_parser!(u64)).default_value("0").requires("sample-frac"))
+        .arg(arg!(--"path-contains" <TEXT> ... "Only include files whose path below the input contains TEXT"))
+        .arg(arg!(--"path-regex" <REGEX> ... "Only include files whose path below the input matches REGEX").value_parser(|value: &str| Regex::new(value).map_err(|e| e.to_string())))
+        .arg(arg!(--"entry-points" "Only include likely entry points (main.rs, index.ts, app.py, ...) and the files they import").action(ArgAction::SetTrue))
+        .arg(arg!(--todos "Only include files with TODO, FIXME or HACK comments").action(ArgAction::SetTrue))
+        .arg(arg!(--"todo-context" <LINES> "Only output the lines within LINES of each --todos marker").value_parser(clap::value_parser!(usize)).requires("todos"))
+        .arg(arg!(--"confirm-above" <TOKENS> "Ask before printing more than about TOKENS tokens to a terminal or copying them to the clipboard").value_parser(clap::value_parser!(usize)).default_value("100000"))
+        .arg(arg!(--timeout <DURATION> "Stop collecting after this long, e.g. 30s, 500ms or 2m").value_parser(parse_duration))
+        .arg(arg!(--"require-paths" "Fail when no paths are given instead of collecting the current directory").action(ArgAction::SetTrue))
+        .arg(arg!(-i --interactive "Choose the files to include from a tree of the filtered files before the output is written").action(ArgAction::SetTrue).conflicts_with_all(["watch", "daemon-socket", "estimate"]))
+        .arg(arg!(-y --yes "Never ask for confirmation before printing a large prompt").action(ArgAction::SetTrue))
+        .arg(arg!(--"no-config" "Do not read defaults from .files-to-prompt.toml").action(ArgAction::SetTrue))
+        .arg(arg!(--watch "Keep running and rewrite the -o files whenever an included file changes").action(ArgAction::SetTrue).requires("output").conflicts_with_all(["git-changes", "pipe", "copy"]))
+        .arg(arg!(--"daemon-socket" <SOCKET> "Have the `files-to-prompt daemon` listening on SOCKET do the collection"))
         // Replace this with a properly constructed Arg
         .arg(
             clap::Arg::new("null")
@@ -31,462 +883,807 @@ pub fn run() -> io::Result<()> {
                 .help("Use NUL character as separator when reading from stdin")
                 .action(ArgAction::SetTrue)
         )
-        .get_matches();
+        .arg(
+            arg!(--"stdin-format" <FORMAT> "How paths on stdin are given: one per line, or a JSON list of {\"path\", \"lines\", \"label\"} objects")
+                .value_parser(["lines", "json"])
+                .default_value("lines"),
+        )
+        .subcommand(
+            clap::Command::new("suggest-ignores")
+                .about("Propose ignore patterns for generated, binary and oversized files among the included set")
+                .arg(arg!(<PATHS> ... "Paths to files or directories"))
+                .args(walk_args()),
+        )
+        .subcommand(
+            clap::Command::new("init")
+                .about("Write a starter .files-to-prompt.toml based on what the project contains")
+                .arg(arg!([DIR] "Project directory").default_value("."))
+                .arg(arg!(--force "Overwrite an existing configuration file").action(ArgAction::SetTrue)),
+        )
+        .subcommand(
+            clap::Command::new("apply")
+                .about("Write the files in a model's response, given in any of the text output formats")
+                .arg(arg!([RESPONSE] "File holding the response; stdin when omitted or -"))
+                .arg(arg!(--"patch-out" <FILE> "Write a unified diff of what would change to FILE (- for stdout) instead of writing files")),
+        )
+        .subcommand(
+            clap::Command::new("convert")
+                .about("Render earlier output again in another format, without the files it was made from")
+                .arg(arg!([INPUT] "File holding the output; stdin when omitted or -"))
+                .arg(arg!(--from <FORMAT> "Format of the input").value_parser(FORMAT_NAMES).required(true))
+                .arg(arg!(--to <FORMAT> "Format to write").value_parser(FORMAT_NAMES).required(true)),
+        )
+        .subcommand(
+            clap::Command::new("daemon")
+                .about("Serve collections over a local socket, keeping file contents and directory listings in memory")
+                .arg(arg!(--socket <SOCKET> "Socket to listen on, by default files-to-prompt.sock in $XDG_RUNTIME_DIR or a private directory of the temp directory")),
+        )
+        .args_conflicts_with_subcommands(true)
+}
 
-    // Initialize the extension to language mapping
-    let ext_to_lang = initialize_ext_to_lang();
+/// Collect `paths` into `out`. With `escape`, the whole output is written
+/// as one JSON string or one single-quoted shell word instead, ready to be
+/// embedded in an API payload or a command line.
+fn render(
+    paths: &[PathBuf],
+    options: &Options,
+    fs: &dyn FileSystem,
+    escape: Option<&str>,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> io::Result<()> {
+    let Some(mode) = escape else {
+        return collect(paths, options, fs, out, err);
+    };
 
-    // Reset global index
-    GLOBAL_INDEX.store(1, Ordering::SeqCst);
+    let mut buffer = Vec::new();
+    collect(paths, options, fs, &mut buffer, err)?;
+    let text = String::from_utf8_lossy(&buffer);
+    let escaped = match mode {
+        "json" => serde_json::Value::from(text.as_ref()).to_string(),
+        _ => shell_quote(&text),
+    };
+    writeln!(out, "{}", escaped)
+}
 
-    // Get paths from CLI args
-    let mut paths: Vec<PathBuf> = matches
-        .get_many::<String>("PATHS")
-        .unwrap_or_default()
-        .map(|s| PathBuf::from(s))
-        .collect();
+/// Run `command` through the shell with `write` streaming into its stdin.
+/// A failing command fails the run with an [`Exit`] of its exit code, as a
+/// shell pipe would.
+fn pipe_to(command: &str, dir: &Path, write: impl FnOnce(&mut dyn Write) -> io::Result<()>) -> io::Result<()> {
+    let shell = if cfg!(windows) { ["cmd", "/C"] } else { ["sh", "-c"] };
+    let mut child = command_in(dir, shell[0])
+        .args([shell[1], command])
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("could not run '{}': {}", command, e)))?;
 
-    // Read paths from stdin if available
-    let use_null_separator = matches.get_flag("null");
-    let stdin_paths = read_paths_from_stdin(use_null_separator)?;
-    paths.extend(stdin_paths);
+    let mut out = BufWriter::new(child.stdin.take().expect("stdin is piped"));
+    let written = write(&mut out).and_then(|()| out.flush());
+    // Closing stdin tells the command the input is complete
+    drop(out);
+    let status = child.wait()?;
 
-    // Setup output writer
-    let mut output_file: Option<File> = None;
-    if let Some(output_path) = matches.get_one::<String>("output") {
-        output_file = Some(File::create(output_path)?);
+    match written {
+        // The command stopped reading early, which is its own business
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(e),
+        _ if !status.success() => Err(io::Error::new(io::ErrorKind::Other, Exit(status.code().unwrap_or(1)))),
+        _ => Ok(()),
     }
+}
 
-    let claude_xml = matches.get_flag("cxml");
-    let markdown = matches.get_flag("markdown");
-    let line_numbers = matches.get_flag("line-numbers");
-    let include_hidden = matches.get_flag("include-hidden");
-    let ignore_files_only = matches.get_flag("ignore-files-only");
-    let ignore_gitignore = matches.get_flag("ignore-gitignore");
-    
-    let extensions: Vec<String> = matches
-        .get_many::<String>("extension")
-        .unwrap_or_default()
-        .cloned()
-        .collect();
-    
-    let ignore_patterns: Vec<String> = matches
-        .get_many::<String>("ignore")
-        .unwrap_or_default()
-        .cloned()
-        .collect();
+/// The lines of the comment describing how this output was produced: the
+/// command line, tool version, timestamp and the git commit of `dir` with
+/// its time when there is one. Times are in UTC unless `local_time` is set.
+fn command_header(local_time: bool, dir: &Path) -> Vec<String> {
+    let command_line = std::env::args().map(|arg| shell_quote(&arg)).collect::<Vec<_>>().join(" ");
+    let mut lines = vec![
+        format!("files-to-prompt {}", env!("CARGO_PKG_VERSION")),
+        format!("command: {}", command_line),
+        format!("timestamp: {}", timestamp::format(SystemTime::now(), local_time)),
+    ];
+
+    let commit = command_in(dir, "git")
+        .args(["log", "-1", "--format=%H %ct"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+    if let Some((commit, time)) = commit.as_deref().and_then(|commit| commit.split_once(' ')) {
+        lines.push(format!("git commit: {}", commit));
+        if let Ok(secs) = time.parse() {
+            lines.push(format!("commit time: {}", timestamp::format(UNIX_EPOCH + Duration::from_secs(secs), local_time)));
+        }
+    }
+
+    lines
+}
+
+/// Arguments that decide which files are walked, shared with subcommands.
+fn walk_args() -> Vec<Arg> {
+    vec![
+        arg!(-e --extension <EXT> ... "File extensions to include"),
+        arg!(--"include-hidden" "Include files and folders starting with .").action(ArgAction::SetTrue),
+        arg!(--"ignore-files-only" "--ignore option only ignores files").action(ArgAction::SetTrue),
+        arg!(--"ignore-gitignore" "Ignore .gitignore files and include all files").action(ArgAction::SetTrue),
+        arg!(--"no-global-ignore" "Do not read the global git excludes file (core.excludesFile)").action(ArgAction::SetTrue),
+        arg!(--"respect-sparse" "Only include files inside the sparse checkout of a git repository").action(ArgAction::SetTrue),
+        arg!(--"include-junk" "Include OS junk and editor swap files such as .DS_Store and *.swp").action(ArgAction::SetTrue),
+        arg!(--ignore <PATTERN> ... "List of patterns to ignore"),
+        arg!(--include <PATTERN> ... "Only include files matching these globs from a directory walk, e.g. 'src/**/*.rs'"),
+        arg!(--"auto-extensions" "Without -e, only include the dominant languages by file count plus config and docs").action(ArgAction::SetTrue),
+        arg!(--mime <TYPES> ... "Only include files of these sniffed content types from a directory walk, e.g. text/*,application/json").value_delimiter(','),
+        arg!(--"max-file-size" <SIZE> "Leave out files from a directory walk larger than SIZE, e.g. 200KB").value_parser(parse_size),
+        arg!(--"min-file-size" <SIZE> "Leave out files from a directory walk smaller than SIZE").value_parser(parse_size),
+        arg!(--"hydrate-placeholders" "Read cloud placeholder files such as online-only OneDrive files, downloading them").action(ArgAction::SetTrue),
+        arg!(--"follow-symlinks" "Walk into symlinked directories and include symlinked files instead of listing each link").action(ArgAction::SetTrue),
+    ]
+}
 
-    let mut gitignore_rules = Vec::new();
+fn walk_options(matches: &ArgMatches, dir: &Path) -> Options {
+    Options {
+        extensions: matches.get_many::<String>("extension").unwrap_or_default().cloned().collect(),
+        include_hidden: matches.get_flag("include-hidden"),
+        include_junk: matches.get_flag("include-junk"),
+        ignore_files_only: matches.get_flag("ignore-files-only"),
+        ignore_gitignore: matches.get_flag("ignore-gitignore"),
+        global_excludes_file: if matches.get_flag("no-global-ignore") || matches.get_flag("ignore-gitignore") {
+            None
+        } else {
+            global_excludes_file(dir)
+        },
+        respect_sparse: matches.get_flag("respect-sparse"),
+        ignore_patterns: matches.get_many::<String>("ignore").unwrap_or_default().cloned().collect(),
+        include_patterns: matches.get_many::<String>("include").unwrap_or_default().cloned().collect(),
+        mime_types: matches.get_many::<String>("mime").unwrap_or_default().cloned().collect(),
+        auto_extensions: matches.get_flag("auto-extensions"),
+        max_file_size: matches.get_one::<u64>("max-file-size").copied(),
+        min_file_size: matches.get_one::<u64>("min-file-size").copied(),
+        hydrate_placeholders: matches.get_flag("hydrate-placeholders"),
+        follow_symlinks: matches.get_flag("follow-symlinks"),
+        ..Options::default()
+    }
+}
+
+/// Print ignore patterns for low-value files among those the given paths
+/// would include, in .gitignore syntax followed by the matching --ignore flags.
+fn suggest_ignores(matches: &ArgMatches, fs: &dyn FileSystem) -> io::Result<()> {
+    let paths: Vec<PathBuf> = matches.get_many::<String>("PATHS").unwrap_or_default().map(PathBuf::from).collect();
+    let files = list_files(&paths, &walk_options(matches, Path::new("")), fs, &mut io::stderr())?;
+    let suggestions = suggest::suggest_ignores(&files, fs);
 
-    // Start XML document if needed
-    if claude_xml && !paths.is_empty() {
-        write_output("<documents>", &mut output_file)?;
+    if suggestions.is_empty() {
+        eprintln!("No ignore patterns to suggest for {} files", files.len());
+        return Ok(());
     }
 
-    // Process each path
-    for path in &paths {
-        if !path.exists() {
-            eprintln!("Path does not exist: {}", path.display());
-            continue;
-        }
+    // .gitignore has no trailing comments, so totals go on each group's heading
+    let mut out = io::stdout().lock();
+    let mut rest = &suggestions[..];
+    while let Some(first) = rest.first() {
+        let len = rest.iter().take_while(|suggestion| suggestion.reason == first.reason).count();
+        let (group, remaining) = rest.split_at(len);
+        rest = remaining;
 
-        if !ignore_gitignore {
-            if let Some(parent) = path.parent() {
-                gitignore_rules.extend(read_gitignore(parent)?);
-            }
+        let files = group.iter().map(|suggestion| suggestion.files.len()).sum();
+        let bytes = group.iter().map(|suggestion| suggestion.bytes).sum();
+        writeln!(out, "# {} ({})", group[0].reason.describe(), suggest::describe_removed(files, bytes))?;
+        for suggestion in group {
+            writeln!(out, "{}", suggestion.pattern)?;
         }
-
-        process_path(
-            path,
-            &extensions,
-            include_hidden,
-            ignore_files_only,
-            ignore_gitignore,
-            &mut gitignore_rules,
-            &ignore_patterns,
-            &mut output_file,
-            claude_xml,
-            markdown,
-            line_numbers,
-            &ext_to_lang,
-        )?;
+        writeln!(out)?;
     }
 
-    // Close XML document if needed
-    if claude_xml {
-        write_output("</documents>", &mut output_file)?;
+    let flags: Vec<String> = suggestions
+        .iter()
+        .map(|suggestion| format!("--ignore {}", shell_quote(suggestion.ignore_flag())))
+        .collect();
+    writeln!(out, "# As command line options:")?;
+    writeln!(out, "# {}", flags.join(" "))?;
+
+    Ok(())
+}
+
+/// Write a starter configuration into the project directory, unless one is
+/// already there.
+fn init(matches: &ArgMatches, fs: &dyn FileSystem) -> io::Result<()> {
+    let dir = PathBuf::from(matches.get_one::<String>("DIR").expect("DIR has a default"));
+    let config_path = dir.join(init::CONFIG_FILE);
+    if fs.exists(&config_path) && !matches.get_flag("force") {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists, use --force to overwrite it", config_path.display()),
+        ));
     }
 
+    let files = list_files(std::slice::from_ref(&dir), &Options::default(), fs, &mut io::stderr())?;
+    std::fs::write(&config_path, init::starter_config(&dir, &files, fs)).context("write", &config_path)?;
+    eprintln!("Wrote {} based on {} files", config_path.display(), files.len());
     Ok(())
 }
 
-fn initialize_ext_to_lang() -> HashMap<String, &'static str> {
-    let mut map = HashMap::new();
-    map.insert("py".to_string(), "python");
-    map.insert("c".to_string(), "c");
-    map.insert("cpp".to_string(), "cpp");
-    map.insert("java".to_string(), "java");
-    map.insert("js".to_string(), "javascript");
-    map.insert("ts".to_string(), "typescript");
-    map.insert("html".to_string(), "html");
-    map.insert("css".to_string(), "css");
-    map.insert("xml".to_string(), "xml");
-    map.insert("json".to_string(), "json");
-    map.insert("yaml".to_string(), "yaml");
-    map.insert("yml".to_string(), "yaml");
-    map.insert("sh".to_string(), "bash");
-    map.insert("rb".to_string(), "ruby");
-    map
-}
-
-fn should_ignore(path: &Path, gitignore_rules: &[String]) -> bool {
-    let basename = path.file_name().unwrap_or_default().to_string_lossy();
-    
-    for rule in gitignore_rules {
-        let pattern = Pattern::new(rule).unwrap_or_else(|_| Pattern::new("*").unwrap());
-        
-        if pattern.matches(&basename) {
-            return true;
-        }
-        
-        if path.is_dir() && pattern.matches(&format!("{}/", basename)) {
-            return true;
+/// Write the files in a model's response over the current directory, or
+/// with --patch-out only describe the changes as a unified diff.
+fn apply(matches: &ArgMatches, fs: &dyn FileSystem) -> io::Result<()> {
+    let response = match matches.get_one::<String>("RESPONSE").filter(|path| *path != "-") {
+        Some(path) => fs.read_to_string(Path::new(path)).context("read", Path::new(path))?,
+        None => {
+            let mut response = String::new();
+            io::stdin().read_to_string(&mut response)?;
+            response
         }
+    };
+
+    let documents = apply::parse_documents(&response);
+    if documents.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "no documents found in the response"));
+    }
+    // Model output decides the paths, so it must not reach outside the project
+    if let Some(document) = documents.iter().find(|document| !apply::is_safe_path(&document.path)) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("refusing to write {} outside the current directory", document.path.display()),
+        ));
     }
-    
-    false
-}
 
-fn read_gitignore(path: &Path) -> io::Result<Vec<String>> {
-    let gitignore_path = path.join(".gitignore");
-    
-    if !gitignore_path.is_file() {
-        return Ok(Vec::new());
+    let current = |path: &Path| -> io::Result<Option<String>> {
+        if fs.is_file(path) {
+            fs.read_to_string(path).map(Some).context("read", path)
+        } else {
+            Ok(None)
+        }
+    };
+
+    if let Some(patch_out) = matches.get_one::<String>("patch-out") {
+        let mut patch = String::new();
+        let mut changed = 0;
+        for document in &documents {
+            let old = current(&document.path)?;
+            let diff = apply::unified_diff(&document.path, old.as_deref(), &document.new_content(old.as_deref()));
+            changed += usize::from(!diff.is_empty());
+            patch.push_str(&diff);
+        }
+        if patch_out == "-" {
+            io::stdout().lock().write_all(patch.as_bytes())?;
+        } else {
+            let mut file = create_output(Path::new(patch_out))?;
+            file.write_all(patch.as_bytes()).and_then(|()| file.flush()).context("write", Path::new(patch_out))?;
+            eprintln!("Wrote changes to {} of {} files to {}", changed, documents.len(), patch_out);
+        }
+        return Ok(());
     }
-    
-    let file = File::open(gitignore_path)?;
-    let reader = io::BufReader::new(file);
-    let mut rules = Vec::new();
-    
-    for line in reader.lines() {
-        let line = line?;
-        let trimmed = line.trim();
-        
-        if !trimmed.is_empty() && !trimmed.starts_with('#') {
-            rules.push(trimmed.to_string());
+
+    for document in &documents {
+        let old = current(&document.path)?;
+        let new = document.new_content(old.as_deref());
+        if old.as_deref() == Some(new.as_str()) {
+            continue;
         }
+        if let Some(parent) = document.path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).context("create directory", parent)?;
+        }
+        std::fs::write(&document.path, new).context("write", &document.path)?;
+        eprintln!("{} {}", if old.is_some() { "Updated" } else { "Created" }, document.path.display());
     }
-    
-    Ok(rules)
+    Ok(())
 }
 
-fn add_line_numbers(content: &str) -> String {
-    let lines: Vec<&str> = content.lines().collect();
-    let padding = lines.len().to_string().len();
-    
-    lines
-        .iter()
-        .enumerate()
-        .map(|(i, line)| format!("{:width$}  {}", i + 1, line, width = padding))
-        .collect::<Vec<String>>()
-        .join("\n")
-}
-
-fn print_path(
-    path: &Path,
-    content: &str,
-    output_file: &mut Option<File>,
-    cxml: bool,
-    markdown: bool,
-    line_numbers: bool,
-    ext_to_lang: &HashMap<String, &'static str>,
-) -> io::Result<()> {
-    if cxml {
-        print_as_xml(path, content, output_file, line_numbers)
-    } else if markdown {
-        print_as_markdown(path, content, output_file, line_numbers, ext_to_lang)
-    } else {
-        print_default(path, content, output_file, line_numbers)
+/// Read the documents of earlier output and write them in another format,
+/// as if collected from files with the same paths and contents.
+fn convert(matches: &ArgMatches, fs: &dyn FileSystem) -> io::Result<()> {
+    let input = match matches.get_one::<String>("INPUT").filter(|path| *path != "-") {
+        Some(path) => fs.read_to_string(Path::new(path)).context("read", Path::new(path))?,
+        None => {
+            let mut input = String::new();
+            io::stdin().read_to_string(&mut input)?;
+            input
+        }
+    };
+    let format = |name: &str| matches.get_one::<String>(name).map_or(OutputFormat::Default, |name| format_named(name));
+
+    let documents = apply::read_output(&input, format("from"))?;
+    if documents.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "no documents found in the input"));
     }
+    let mut files = MemoryFs::new();
+    for document in &documents {
+        files.add_file(&document.path, document.content.as_str());
+    }
+    let paths: Vec<PathBuf> = documents.into_iter().map(|document| document.path).collect();
+    let options = Options { format: format("to"), ..Options::default() };
+    collect(&paths, &options, &files, &mut io::stdout().lock(), &mut io::stderr().lock())
 }
 
-fn print_default(
-    path: &Path,
-    content: &str,
-    output_file: &mut Option<File>,
-    line_numbers: bool,
-) -> io::Result<()> {
-    write_output(&path.display().to_string(), output_file)?;
-    write_output("---", output_file)?;
-    
-    let output_content = if line_numbers {
-        add_line_numbers(content)
-    } else {
-        content.to_string()
+fn read_template(path: &Path, fs: &dyn FileSystem) -> io::Result<Template> {
+    let text = fs.read_to_string(path).context("read", path)?;
+    Template::parse(&text)
+        .map_err(|message| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), message)))
+}
+
+/// Parse a duration such as `30s`, `500ms` or `2m`. A bare number is seconds.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let split = value.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("invalid duration '{}'", value))?;
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => return Err(format!("unknown unit '{}' in duration '{}', use ms, s, m or h", unit, value)),
     };
-    
-    write_output(&output_content, output_file)?;
-    write_output("", output_file)?;
-    write_output("---", output_file)?;
-    
-    Ok(())
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("duration '{}' is out of range", value))
 }
 
-fn print_as_xml(
-    path: &Path,
-    content: &str,
-    output_file: &mut Option<File>,
-    line_numbers: bool,
-) -> io::Result<()> {
-    let index = GLOBAL_INDEX.fetch_add(1, Ordering::SeqCst);
-    
-    write_output(&format!("<document index=\"{}\">", index), output_file)?;
-    write_output(&format!("<source>{}</source>", path.display()), output_file)?;
-    write_output("<document_content>", output_file)?;
-    
-    let output_content = if line_numbers {
-        add_line_numbers(content)
-    } else {
-        content.to_string()
+fn format_named(name: &str) -> OutputFormat {
+    match name {
+        "cxml" => OutputFormat::Cxml,
+        "markdown" => OutputFormat::Markdown,
+        "json" => OutputFormat::Json,
+        _ => OutputFormat::Default,
+    }
+}
+
+/// Parse a size such as `200KB`, `1.5MB` or `512`, in bytes. Units are
+/// powers of 1024, as in the sizes reported by `--stats`.
+fn parse_size(value: &str) -> Result<u64, String> {
+    let split = value.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("invalid size '{}'", value))?;
+    let unit_bytes = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1024,
+        "M" | "MB" | "MIB" => 1024 * 1024,
+        "G" | "GB" | "GIB" => 1024 * 1024 * 1024,
+        _ => return Err(format!("unknown unit '{}' in size '{}', use B, KB, MB or GB", unit, value)),
     };
-    
-    write_output(&output_content, output_file)?;
-    write_output("</document_content>", output_file)?;
-    write_output("</document>", output_file)?;
-    
-    Ok(())
+    Ok((number * unit_bytes as f64).round() as u64)
 }
 
-fn print_as_markdown(
-    path: &Path,
-    content: &str,
-    output_file: &mut Option<File>,
-    line_numbers: bool,
-    ext_to_lang: &HashMap<String, &'static str>,
-) -> io::Result<()> {
-    let extension = path
-        .extension()
-        .unwrap_or_default()
-        .to_string_lossy()
-        .to_string();
-    
-    let lang = ext_to_lang.get(&extension).unwrap_or(&"");
-    
-    // Determine number of backticks needed
-    let mut backticks = "```".to_string();
-    while content.contains(&backticks) {
-        backticks.push('`');
+/// Parse a `--variant` value of the form `NAME=PATH`.
+fn parse_variant(value: &str) -> Result<(String, PathBuf), String> {
+    match value.split_once('=') {
+        Some((name, path)) if !name.is_empty() && !path.is_empty() => Ok((name.to_string(), PathBuf::from(path))),
+        _ => Err(format!("expected NAME=PATH, got '{}'", value)),
     }
-    
-    write_output(&path.display().to_string(), output_file)?;
-    write_output(&format!("{}{}", backticks, lang), output_file)?;
-    
-    let output_content = if line_numbers {
-        add_line_numbers(content)
-    } else {
-        content.to_string()
+}
+
+/// Parse a `--tail` value, either a line count or `GLOB=N` for the files
+/// matching a glob.
+fn parse_tail(value: &str) -> Result<(Option<String>, usize), String> {
+    let (glob, lines) = match value.rsplit_once('=') {
+        Some((glob, lines)) if !glob.is_empty() => (Some(glob.to_string()), lines),
+        Some(_) => return Err(format!("expected N or GLOB=N, got '{}'", value)),
+        None => (None, value),
     };
-    
-    write_output(&output_content, output_file)?;
-    write_output(&backticks, output_file)?;
-    
+    let lines = lines.parse().map_err(|_| format!("expected a number of lines, got '{}'", lines))?;
+    Ok((glob, lines))
+}
+
+fn parse_fraction(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(fraction) if fraction > 0.0 && fraction <= 1.0 => Ok(fraction),
+        _ => Err(format!("expected a fraction between 0 and 1, got '{}'", value)),
+    }
+}
+
+// The label is taken after the last `=`, so the pattern may contain one
+fn parse_annotation(value: &str) -> Result<(Regex, String), String> {
+    match value.rsplit_once('=') {
+        Some((pattern, label)) if !pattern.is_empty() && !label.is_empty() => {
+            let regex = Regex::new(pattern).map_err(|e| e.to_string())?;
+            Ok((regex, label.to_string()))
+        }
+        _ => Err(format!("expected REGEX=LABEL, got '{}'", value)),
+    }
+}
+
+/// Ask before emitting `bytes` of output when that is more than
+/// --confirm-above tokens, unless --yes was passed.
+fn confirm_size(matches: &ArgMatches, bytes: usize) -> io::Result<()> {
+    let tokens = stats::estimate_tokens(bytes);
+    let threshold = matches.get_one::<usize>("confirm-above").copied().unwrap_or(usize::MAX);
+    if matches.get_flag("yes") || tokens <= threshold {
+        return Ok(());
+    }
+    if !confirm(&format!("about to emit {} tokens, continue? [y/N] ", stats::format_tokens(tokens)))? {
+        return Err(io::Error::new(io::ErrorKind::Interrupted, "aborted, pass --yes to skip this check"));
+    }
     Ok(())
 }
 
-fn process_path(
-    path: &Path,
-    extensions: &[String],
-    include_hidden: bool,
-    ignore_files_only: bool,
-    ignore_gitignore: bool,
-    gitignore_rules: &mut Vec<String>,
-    ignore_patterns: &[String],
-    output_file: &mut Option<File>,
-    claude_xml: bool,
-    markdown: bool,
-    line_numbers: bool,
-    ext_to_lang: &HashMap<String, &'static str>,
-) -> io::Result<()> {
-    if path.is_file() {
-        match fs::read_to_string(path) {
-            Ok(content) => {
-                print_path(
-                    path,
-                    &content,
-                    output_file,
-                    claude_xml,
-                    markdown,
-                    line_numbers,
-                    ext_to_lang,
-                )?;
-            }
-            Err(e) => {
-                eprintln!("Warning: Skipping file {} due to error: {}", path.display(), e);
+/// Ask a yes/no question on the terminal. Stdin may be carrying paths, so
+/// the answer is read from the controlling terminal when it is not a TTY.
+fn confirm(question: &str) -> io::Result<bool> {
+    eprint!("{}", question);
+    io::stderr().flush()?;
+
+    let mut answer = String::new();
+    if atty::is(atty::Stream::Stdin) {
+        io::stdin().lock().read_line(&mut answer)?;
+    } else {
+        let tty = File::open("/dev/tty").map_err(|_| {
+            io::Error::new(io::ErrorKind::Unsupported, "cannot ask for confirmation without a terminal, pass --yes")
+        })?;
+        io::BufReader::new(tty).read_line(&mut answer)?;
+    }
+
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// The last `count` commits as `hash subject` lines, optionally limited to
+/// commits touching `paths`. Outside a git repository this warns and returns
+/// nothing.
+fn git_log(count: usize, paths: Option<&[PathBuf]>, dir: &Path, err: &mut dyn Write) -> io::Result<Vec<String>> {
+    let mut command = command_in(dir, "git");
+    command.args(["log", "--format=%h %s", &format!("-n{}", count)]);
+    if let Some(paths) = paths {
+        command.arg("--").args(paths);
+    }
+
+    match command.output() {
+        Ok(output) if output.status.success() => {
+            Ok(String::from_utf8_lossy(&output.stdout).lines().map(str::to_string).collect())
+        }
+        _ => {
+            writeln!(err, "Warning: could not read the git log, skipping --git-log")?;
+            Ok(Vec::new())
+        }
+    }
+}
+
+/// The top level of the git repository around `dir`.
+fn git_root(dir: &Path, fs: &dyn FileSystem, err: &mut dyn Write) -> io::Result<Option<PathBuf>> {
+    match command_in(dir, "git").args(["rev-parse", "--show-toplevel"]).output() {
+        Ok(output) if output.status.success() => {
+            let root = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim_end());
+            Ok(Some(fs.canonicalize(&root).unwrap_or(root)))
+        }
+        _ => {
+            writeln!(err, "Warning: not inside a git repository, skipping --paths-from-root")?;
+            Ok(None)
+        }
+    }
+}
+
+/// The user's global git excludes file: `core.excludesFile` when set,
+/// otherwise git's default of `$XDG_CONFIG_HOME/git/ignore`.
+fn global_excludes_file(dir: &Path) -> Option<PathBuf> {
+    let configured = command_in(dir, "git")
+        .args(["config", "--path", "--get", "core.excludesFile"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim_end().to_string())
+        .filter(|path| !path.is_empty());
+    if let Some(path) = configured {
+        return Some(PathBuf::from(path));
+    }
+
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_home.join("git/ignore"))
+}
+
+/// What the working tree is compared against to find changed files.
+enum GitBase {
+    /// The merge base of a ref and HEAD, for --since-ref.
+    MergeBase(String),
+    /// A commit or branch as it is, for --since.
+    Ref(String),
+    /// HEAD, counting untracked files as changed, for --git-diff.
+    WorkingTree,
+}
+
+impl GitBase {
+    /// The commit to diff the working tree in `dir` against.
+    fn commit(&self, dir: &Path) -> io::Result<String> {
+        match self {
+            GitBase::MergeBase(git_ref) => {
+                let base = git(dir, &["merge-base", git_ref, "HEAD"])?;
+                Ok(String::from_utf8_lossy(&base).trim().to_string())
             }
+            GitBase::Ref(git_ref) => Ok(git_ref.clone()),
+            GitBase::WorkingTree => Ok("HEAD".to_string()),
         }
-    } else if path.is_dir() {
-        walk_directory(
-            path,
-            extensions,
-            include_hidden,
-            ignore_files_only,
-            ignore_gitignore,
-            gitignore_rules,
-            ignore_patterns,
-            output_file,
-            claude_xml,
-            markdown,
-            line_numbers,
-            ext_to_lang,
-        )?;
     }
-    
-    Ok(())
 }
 
-fn walk_directory(
+/// `program`, to be run in `dir`, or in the working directory when `dir` is
+/// empty.
+fn command_in(dir: &Path, program: &str) -> Command {
+    let mut command = Command::new(program);
+    if !dir.as_os_str().is_empty() {
+        command.current_dir(dir);
+    }
+    command
+}
+
+fn git(dir: &Path, args: &[&str]) -> io::Result<Vec<u8>> {
+    let output = command_in(dir, "git").args(args).output()?;
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("git {} failed: {}", args.join(" "), message.trim()),
+        ));
+    }
+    Ok(output.stdout)
+}
+
+// Paths git prints are relative to the top of the repository
+fn git_paths(output: &[u8], root: &Path) -> Vec<PathBuf> {
+    output
+        .split(|&b| b == b'\0')
+        .filter(|name| !name.is_empty())
+        .map(|name| root.join(names::path_from_bytes(name)))
+        .collect()
+}
+
+/// Absolute paths of the files that differ between the working tree and
+/// `base`. Deleted files are left out.
+fn changed_files(base: &GitBase, dir: &Path, fs: &dyn FileSystem) -> io::Result<HashSet<PathBuf>> {
+    let commit = base.commit(dir)?;
+    let root = git(dir, &["rev-parse", "--show-toplevel"])?;
+    let root = PathBuf::from(String::from_utf8_lossy(&root).trim_end());
+    let root = fs.canonicalize(&root).unwrap_or(root);
+
+    let changed = git(dir, &["diff", "--name-only", "-z", "--no-renames", "--diff-filter=d", &commit])?;
+    let mut files: HashSet<PathBuf> = git_paths(&changed, &root).into_iter().collect();
+    if let GitBase::WorkingTree = base {
+        let untracked = git(dir, &["ls-files", "--others", "--exclude-standard", "-z", "--full-name", ":/"])?;
+        files.extend(git_paths(&untracked, &root));
+    }
+    Ok(files)
+}
+
+/// Put the diff of each of `files` against `base` into `diffs` as
+/// `<file>.diff`, returning those paths. Untracked files are diffed
+/// against an empty file.
+fn diff_files(
+    base: &GitBase,
     dir: &Path,
-    extensions: &[String],
-    include_hidden: bool,
-    ignore_files_only: bool,
-    ignore_gitignore: bool,
-    gitignore_rules: &mut Vec<String>,
-    ignore_patterns: &[String],
-    output_file: &mut Option<File>,
-    claude_xml: bool,
-    markdown: bool,
-    line_numbers: bool,
-    ext_to_lang: &HashMap<String, &'static str>,
-) -> io::Result<()> {
-    if !ignore_gitignore {
-        gitignore_rules.extend(read_gitignore(dir)?);
+    files: &[PathBuf],
+    fs: &dyn FileSystem,
+    diffs: &mut MemoryFs,
+) -> io::Result<Vec<PathBuf>> {
+    let commit = base.commit(dir)?;
+    let mut paths = Vec::new();
+    for file in files {
+        let name = file.to_string_lossy();
+        let diff = git(dir, &["diff", "--no-color", "--no-renames", &commit, "--", &name])?;
+        let diff = if diff.is_empty() && fs.is_file(file) {
+            // Exits with 1 whenever the files differ, which is the point
+            let output = command_in(dir, "git").args(["diff", "--no-color", "--no-index", "--", "/dev/null", &name]).output()?;
+            output.stdout
+        } else {
+            diff
+        };
+
+        let mut label = file.clone().into_os_string();
+        label.push(".diff");
+        diffs.add_file(&label, diff);
+        paths.push(PathBuf::from(label));
     }
-    
-    let mut entries: Vec<fs::DirEntry> = fs::read_dir(dir)?
-        .filter_map(Result::ok)
-        .filter(|entry| {
-            let path = entry.path();
-            let name = entry.file_name();
-            let name_str = name.to_string_lossy();
-            
-            // Check if hidden
-            if !include_hidden && name_str.starts_with('.') {
-                return false;
-            }
-            
-            // Check gitignore rules
-            if !ignore_gitignore && should_ignore(&path, gitignore_rules) {
-                return false;
-            }
-            
-            // Check ignore patterns
-            if !ignore_patterns.is_empty() {
-                let is_dir = path.is_dir();
-                if !is_dir || !ignore_files_only {
-                    for pattern in ignore_patterns {
-                        let fnpattern = Pattern::new(pattern).unwrap_or_else(|_| Pattern::new("*").unwrap());
-                        if fnpattern.matches(&name_str) {
-                            return false;
-                        }
-                    }
-                }
-            }
-            
-            true
-        })
-        .collect();
-    
-    // Sort entries by name
-    entries.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
-    
-    for entry in entries {
-        let path = entry.path();
-        
-        if path.is_dir() {
-            walk_directory(
-                &path,
-                extensions,
-                include_hidden,
-                ignore_files_only,
-                ignore_gitignore,
-                gitignore_rules,
-                ignore_patterns,
-                output_file,
-                claude_xml,
-                markdown,
-                line_numbers,
-                ext_to_lang,
-            )?;
-        } else if path.is_file() {
-            // Check extensions
-            if !extensions.is_empty() {
-                let ext = path
-                    .extension()
-                    .unwrap_or_default()
-                    .to_string_lossy()
-                    .to_string();
-                if !extensions.iter().any(|e| *e == ext) {
-                    continue;
-                }
-            }
-            
-            match fs::read_to_string(&path) {
-                Ok(content) => {
-                    print_path(
-                        &path,
-                        &content,
-                        output_file,
-                        claude_xml,
-                        markdown,
-                        line_numbers,
-                        ext_to_lang,
-                    )?;
+    Ok(paths)
+}
+
+/// A scorer running `command` through the shell in `dir` for each file, with
+/// the file's path as its last argument and content on stdin, reading the
+/// score it prints. A file the command fails on has no score, NaN.
+fn score_command(command: String, dir: &Path) -> Scorer {
+    let dir = dir.to_path_buf();
+    Box::new(move |document| {
+        let path = document.path.to_string_lossy();
+        run_scorer(&command, &dir, &path, &document.content).unwrap_or(f64::NAN)
+    })
+}
+
+fn run_scorer(command: &str, dir: &Path, path: &str, content: &str) -> Option<f64> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = command_in(dir, "cmd");
+        shell.args(["/C", &format!("{} {}", command, shell_quote(path))]);
+        shell
+    } else {
+        let mut shell = command_in(dir, "sh");
+        shell.args(["-c", &format!("{} \"$@\"", command), "sh", path]);
+        shell
+    };
+    let mut child = shell.stdin(Stdio::piped()).stdout(Stdio::piped()).spawn().ok()?;
+
+    // A command scoring by path alone may exit without reading its stdin
+    let mut stdin = child.stdin.take()?;
+    let content = content.to_string();
+    let writer = std::thread::spawn(move || {
+        let _ = stdin.write_all(content.as_bytes());
+    });
+    let output = child.wait_with_output().ok()?;
+    let _ = writer.join();
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+}
+
+fn shell_quote(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:,@+%".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// Replace each GitHub pull request URL in `paths` with the pull request's
+/// diff and the files it touches. Files missing from the local checkout are
+/// fetched from the pull request's head into `fetched`.
+fn expand_pull_requests(paths: Vec<PathBuf>, fs: &dyn FileSystem, fetched: &mut MemoryFs) -> io::Result<Vec<PathBuf>> {
+    let mut expanded = Vec::new();
+    let client = github::Client::from_env();
+
+    for path in paths {
+        let Some(pr) = path.to_str().and_then(PullRequest::from_url) else {
+            expanded.push(path);
+            continue;
+        };
+
+        let diff = client.diff(&pr)?;
+        let label = PathBuf::from(pr.label());
+        fetched.add_file(&label, diff.as_bytes());
+        expanded.push(label);
+
+        let mut head = None;
+        for file in patch::touched_files(&diff) {
+            if !fs.is_file(&file) {
+                if head.is_none() {
+                    head = Some(client.head(&pr)?);
                 }
-                Err(e) => {
-                    eprintln!("Warning: Skipping file {} due to error: {}", path.display(), e);
+                if let Some((repo, sha)) = &head {
+                    fetched.add_file(&file, client.file(repo, sha, &file.to_string_lossy())?);
                 }
             }
+            expanded.push(file);
         }
     }
-    
-    Ok(())
+
+    Ok(expanded)
 }
 
-fn read_paths_from_stdin(use_null_separator: bool) -> io::Result<Vec<PathBuf>> {
-    let stdin = io::stdin();
-    
-    // Check if stdin is a TTY (interactive terminal)
-    if atty::is(atty::Stream::Stdin) {
-        return Ok(Vec::new());
+/// Replace each repository URL in `paths` with a shallow clone of the
+/// repository at `revision`. Paths that exist locally are kept as they are.
+fn clone_repositories(paths: &mut [PathBuf], revision: &Revision, fs: &dyn FileSystem) -> io::Result<Vec<remote::Clone>> {
+    let mut clones = Vec::new();
+    for path in paths.iter_mut() {
+        let Some(url) = path.to_str().filter(|url| remote::is_repository_url(url)) else {
+            continue;
+        };
+        if fs.exists(Path::new(url)) {
+            continue;
+        }
+        let clone = remote::clone(url, revision)?;
+        *path = clone.dir.clone();
+        clones.push(clone);
+    }
+    Ok(clones)
+}
+
+/// Follow each patch in `paths` (by its .patch or .diff extension) and in
+/// `from_patch` with the files it touches, skipping files already listed.
+fn expand_patches(paths: Vec<PathBuf>, from_patch: Vec<PathBuf>, fs: &dyn FileSystem) -> io::Result<Vec<PathBuf>> {
+    let mut expanded: Vec<PathBuf> = Vec::new();
+    let mut touched: Vec<PathBuf> = Vec::new();
+
+    let explicit = from_patch.len();
+    for (i, path) in from_patch.into_iter().chain(paths).enumerate() {
+        if (i < explicit || patch::is_patch_file(&path)) && fs.is_file(&path) {
+            touched.extend(patch::touched_files(&fs.read_to_string(&path).context("read", &path)?));
+        }
+        expanded.push(path);
+    }
+
+    for path in touched {
+        if !expanded.contains(&path) {
+            expanded.push(path);
+        }
+    }
+
+    Ok(expanded)
+}
+
+fn read_paths_from_stdin(
+    stdin: &mut dyn Read,
+    use_null_separator: bool,
+    json: bool,
+    fs: &dyn FileSystem,
+) -> io::Result<Vec<(PathBuf, PathSettings)>> {
+    // Read raw bytes so paths that are not valid UTF-8 are kept intact
+    let mut content = Vec::new();
+    stdin.read_to_end(&mut content)?;
+
+    if json {
+        return parse_json_paths(&content);
     }
-    
-    let mut content = String::new();
-    stdin.lock().read_to_string(&mut content)?;
     
     let paths = if use_null_separator {
         content
-            .split('\0')
+            .split(|&b| b == b'\0')
             .filter(|s| !s.is_empty())
-            .map(PathBuf::from)
-            .collect()
+            .map(names::path_from_bytes)
+            .collect::<Vec<_>>()
     } else {
         content
-            .split_whitespace()
-            .map(PathBuf::from)
+            .split(|b| b.is_ascii_whitespace())
+            .filter(|s| !s.is_empty())
+            .map(names::path_from_bytes)
             .collect()
     };
     
-    Ok(paths)
+    Ok(paths.into_iter().map(|path| annotated(path, fs)).collect())
 }
 
-fn write_output(content: &str, output_file: &mut Option<File>) -> io::Result<()> {
-    match output_file {
-        Some(file) => {
-            writeln!(file, "{}", content)?;
-        }
-        None => {
-            println!("{}", content);
+/// Split off a `#key=value,...` annotation, unless the whole path exists or
+/// what follows the last `#` is not a list of attributes.
+fn annotated(path: PathBuf, fs: &dyn FileSystem) -> (PathBuf, PathSettings) {
+    let split = path
+        .to_str()
+        .filter(|_| !fs.exists(&path))
+        .and_then(|text| text.rsplit_once('#'))
+        .and_then(|(name, annotation)| Some((name, PathSettings::parse_attributes(annotation)?)));
+
+    match split {
+        Some((name, attributes)) => (PathBuf::from(name), PathSettings { attributes, ..PathSettings::default() }),
+        None => (path, PathSettings::default()),
+    }
+}
+
+/// Parse `[{"path": "...", "lines": "10-50", "label": "..."}]`, optionally
+/// with an `"attributes"` object. Entries may also be plain path strings.
+fn parse_json_paths(content: &[u8]) -> io::Result<Vec<(PathBuf, PathSettings)>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, format!("stdin: {}", message));
+    if content.iter().all(u8::is_ascii_whitespace) {
+        return Ok(Vec::new());
+    }
+
+    let json: serde_json::Value = serde_json::from_slice(content).map_err(|e| invalid(e.to_string()))?;
+    let entries = json.as_array().ok_or_else(|| invalid("expected a JSON list of paths".to_string()))?;
+
+    let mut paths = Vec::new();
+    for entry in entries {
+        if let Some(path) = entry.as_str() {
+            paths.push((PathBuf::from(path), PathSettings::default()));
+            continue;
         }
+
+        let path = entry["path"]
+            .as_str()
+            .ok_or_else(|| invalid(format!("entry without a \"path\" string: {}", entry)))?;
+        let lines = match &entry["lines"] {
+            serde_json::Value::Null => None,
+            serde_json::Value::String(range) => Some(
+                PathSettings::parse_lines(range)
+                    .ok_or_else(|| invalid(format!("invalid line range '{}' for {}, expected START-END", range, path)))?,
+            ),
+            other => return Err(invalid(format!("invalid line range {} for {}, expected a string", other, path))),
+        };
+        let label = entry["label"].as_str().map(str::to_string);
+        let attributes = match &entry["attributes"] {
+            serde_json::Value::Null => Vec::new(),
+            serde_json::Value::Object(map) => {
+                let mut attributes = Vec::new();
+                for (key, value) in map {
+                    if !PathSettings::is_attribute_name(key) {
+                        return Err(invalid(format!("invalid attribute name '{}' for {}", key, path)));
+                    }
+                    let value = value.as_str().map(str::to_string).unwrap_or_else(|| value.to_string());
+                    attributes.push((key.clone(), value));
+                }
+                attributes
+            }
+            other => return Err(invalid(format!("invalid attributes {} for {}, expected an object", other, path))),
+        };
+        paths.push((PathBuf::from(path), PathSettings { lines, label, attributes }));
     }
-    Ok(())
+
+    Ok(paths)
 }
@@ -0,0 +1,12 @@
+// Copying the output to the system clipboard for --copy, through arboard.
+// On Linux it talks to the X11 or Wayland server directly, and X11 only
+// keeps the text after we exit if a clipboard manager takes it over.
+use arboard::Clipboard;
+use std::io;
+
+/// Put `text` on the clipboard.
+pub(crate) fn copy(text: &str) -> io::Result<()> {
+    let error = |e: arboard::Error| io::Error::new(io::ErrorKind::Other, format!("cannot use the clipboard: {}", e));
+    let mut clipboard = Clipboard::new().map_err(error)?;
+    clipboard.set_text(text).map_err(error)
+}
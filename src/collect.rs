@@ -0,0 +1,3414 @@
+// File collection and rendering, independent of the command line
+use crate::apply::Document;
+use crate::comments;
+use crate::decompress;
+use crate::editorconfig;
+use crate::encoding;
+use crate::extract;
+use crate::imports;
+use crate::languages;
+use crate::mime;
+use crate::names::{self, NamePattern, PathRenderer};
+use crate::notebook;
+use crate::profile::{FileTiming, Phase, Profile};
+use crate::secrets;
+use crate::sfc;
+use crate::stats::{self, FileStats, StatsFormat, Summary};
+use crate::symbol_index;
+use crate::template::{FileValues, Template};
+use crate::tree;
+use crate::vfs::FileSystem;
+use regex::Regex;
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// OS metadata and editor swap/backup files skipped during directory walks
+/// unless `include_junk` is set.
+const JUNK_PATTERNS: &[&str] = &[
+    ".DS_Store",
+    "._*",
+    "Thumbs.db",
+    "ehthumbs.db",
+    "desktop.ini",
+    "*.swp",
+    "*.swo",
+    "*~",
+    ".#*",
+];
+
+/// Directory where this tool keeps caches, checkpoints and manifests. It is
+/// never walked, so auxiliary artifacts cannot end up in a prompt.
+const TOOL_DIR: &str = ".files-to-prompt";
+
+/// Heading of the --git-log section in the default and Markdown formats.
+const GIT_LOG_TITLE: &str = "Recent commits";
+
+/// Title of the file map repeated by `repeat_header`.
+const FILE_MAP_TITLE: &str = "Files in this prompt";
+
+/// Title of the list written by `mention_excluded`.
+const NOT_INCLUDED_TITLE: &str = "Imported but not included";
+
+/// Title of the overview written by `tree`.
+const TREE_TITLE: &str = "Directory tree";
+
+/// Title of the section written by `embed_warnings`.
+const DIAGNOSTICS_TITLE: &str = "Collection diagnostics";
+
+/// Content printed in place of a file for an empty directory.
+const EMPTY_DIR_STUB: &str = "(empty directory)";
+
+/// The reasons files are counted as left out in the `stats` summary.
+const SKIP_BINARY: &str = "not UTF-8 text";
+const SKIP_UNREADABLE: &str = "could not be read";
+const SKIP_MISSING: &str = "not found";
+const SKIP_OVER_BUDGET: &str = "over the size budget";
+const SKIP_PLACEHOLDER: &str = "not downloaded";
+
+/// How many directories deep a walk goes. Real trees stay far below this,
+/// so it only stops loops that symlink detection can't see, such as
+/// directories whose canonical path can't be found.
+const MAX_WALK_DEPTH: usize = 256;
+
+/// File stems that usually mark where a program starts or where its
+/// requests are routed, for --entry-points.
+const ENTRY_POINT_STEMS: &[&str] = &[
+    "main", "__main__", "app", "index", "server", "cli", "manage", "wsgi", "asgi", "routes", "router", "urls",
+];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Default,
+    Cxml,
+    Markdown,
+    /// A JSON array with one `{path, content, size, extension}` object per
+    /// document, for tools to consume.
+    Json,
+}
+
+impl OutputFormat {
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_string_lossy().to_lowercase().as_str() {
+            "xml" => Some(OutputFormat::Cxml),
+            "md" | "markdown" => Some(OutputFormat::Markdown),
+            "json" => Some(OutputFormat::Json),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputFormat::Default => "plain text",
+            OutputFormat::Cxml => "XML",
+            OutputFormat::Markdown => "Markdown",
+            OutputFormat::Json => "JSON",
+        }
+    }
+
+    /// The extension of files written in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Default => "txt",
+            OutputFormat::Cxml => "xml",
+            OutputFormat::Markdown => "md",
+            OutputFormat::Json => "json",
+        }
+    }
+}
+
+/// How documents are laid out by [`render`]. [`OutputFormat`] covers the
+/// built-in formats; other programs can implement it for their own.
+pub trait Formatter {
+    /// Write what comes before the first document.
+    fn start(&self, _out: &mut dyn Write) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Write `entry` as the document numbered `index`, counting from 1.
+    fn write_document(&self, out: &mut dyn Write, index: usize, entry: &FileEntry) -> io::Result<()>;
+
+    /// Write what comes after the last document.
+    fn finish(&self, _out: &mut dyn Write) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Whether free text, such as a document separator, can be placed
+    /// between documents without breaking the structure of the output.
+    fn allows_separator(&self) -> bool {
+        true
+    }
+}
+
+impl Formatter for OutputFormat {
+    fn start(&self, out: &mut dyn Write) -> io::Result<()> {
+        match self {
+            OutputFormat::Cxml => writeln!(out, "<documents>"),
+            OutputFormat::Json => writeln!(out, "["),
+            OutputFormat::Default | OutputFormat::Markdown => Ok(()),
+        }
+    }
+
+    fn write_document(&self, out: &mut dyn Write, index: usize, entry: &FileEntry) -> io::Result<()> {
+        static EXT_TO_LANG: OnceLock<HashMap<String, &'static str>> = OnceLock::new();
+        let language = detect_language(&entry.path, EXT_TO_LANG.get_or_init(initialize_ext_to_lang));
+        let path = entry.path.to_string_lossy();
+        match self {
+            OutputFormat::Default => writeln!(out, "{}\n---\n{}\n\n---", path, entry.content),
+            OutputFormat::Markdown => {
+                let mut backticks = "```".to_string();
+                while entry.content.contains(&backticks) {
+                    backticks.push('`');
+                }
+                let language = language.unwrap_or("");
+                writeln!(out, "{}\n{}{}\n{}\n{}", path, backticks, language, entry.content, backticks)
+            }
+            OutputFormat::Cxml => {
+                let mut attributes = format!("index=\"{}\"", index);
+                if let Some(language) = language {
+                    attributes.push_str(&format!(" language=\"{}\"", language));
+                }
+                writeln!(out, "<document {}>\n<source>{}</source>", attributes, path)?;
+                writeln!(out, "<document_content>\n{}\n</document_content>\n</document>", entry.content)
+            }
+            OutputFormat::Json => {
+                let document = serde_json::json!({
+                    "path": path,
+                    "content": entry.content,
+                    "size": entry.content.len(),
+                    "extension": entry.path.extension().map(|extension| extension.to_string_lossy()),
+                    "language": language,
+                    "schema_version": crate::SCHEMA_VERSION,
+                    "tool_version": env!("CARGO_PKG_VERSION"),
+                });
+                let separator = if index > 1 { "," } else { "" };
+                writeln!(out, "{}{}", separator, document)
+            }
+        }
+    }
+
+    fn finish(&self, out: &mut dyn Write) -> io::Result<()> {
+        match self {
+            OutputFormat::Cxml => writeln!(out, "</documents>"),
+            OutputFormat::Json => writeln!(out, "]"),
+            OutputFormat::Default | OutputFormat::Markdown => Ok(()),
+        }
+    }
+
+    /// XML documents all sit inside `<documents>` and JSON ones inside an
+    /// array, where loose text would break the structure.
+    fn allows_separator(&self) -> bool {
+        match self {
+            OutputFormat::Default | OutputFormat::Markdown => true,
+            OutputFormat::Cxml | OutputFormat::Json => false,
+        }
+    }
+}
+
+/// Which files `max_bytes` favours when not everything fits.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetOrder {
+    /// Files in the order they are found, so the budget runs out towards the
+    /// end of the walk.
+    #[default]
+    Path,
+    /// The smallest files first, which fits the most files in.
+    Smallest,
+}
+
+/// Rates how much a document is worth keeping when `max_bytes` forces some
+/// out; higher scores are kept first. NaN stands for no score, ranked last.
+pub type Scorer = Box<dyn Fn(&Document) -> f64 + Send + Sync>;
+
+/// The order `sort` writes files in, across every input. Files that
+/// compare equal stay in path order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    /// By file name, wherever the file is.
+    Name,
+    Path,
+    /// The smallest files first.
+    Size,
+    /// The least recently modified files first.
+    Mtime,
+    Extension,
+}
+
+/// What becomes of files that are not UTF-8 text.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryFiles {
+    /// Leave them out with a warning.
+    #[default]
+    Skip,
+    /// Write a stand-in document with the file's size and content type.
+    Summary,
+    /// Stop the run with an error.
+    Error,
+}
+
+/// Which end of each file `truncate` keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Truncate {
+    /// The first this many lines.
+    Head(usize),
+    /// The last this many lines.
+    Tail(usize),
+}
+
+impl Truncate {
+    fn lines(self) -> usize {
+        match self {
+            Truncate::Head(lines) | Truncate::Tail(lines) => lines,
+        }
+    }
+}
+
+/// Settings for one collection run. Each field mirrors a command line flag.
+#[derive(Default)]
+pub struct Options {
+    pub extensions: Vec<String>,
+    pub include_hidden: bool,
+    pub include_junk: bool,
+    pub ignore_files_only: bool,
+    pub ignore_gitignore: bool,
+    /// The user's global excludes file, read like `.git/info/exclude` for
+    /// paths inside a git repository.
+    pub global_excludes_file: Option<PathBuf>,
+    /// Leave out files outside the sparse checkout of the repository they
+    /// are in, as git would not check them out.
+    pub respect_sparse: bool,
+    pub ignore_patterns: Vec<String>,
+    /// When not empty, a directory walk only includes files matching one of
+    /// these globs. A glob with a `/` is matched against the path below the
+    /// input, otherwise against the file name.
+    pub include_patterns: Vec<String>,
+    /// When not empty, a directory walk only includes files whose sniffed
+    /// content type matches one of these, such as `text/*` or
+    /// `application/json`.
+    pub mime_types: Vec<String>,
+    /// Without `extensions`, have a directory walk only include the
+    /// dominant source languages by file count, plus configuration and
+    /// documentation.
+    pub auto_extensions: bool,
+    /// Absolute paths never included from a directory walk, such as the
+    /// files the output is being written to.
+    pub excluded_paths: HashSet<PathBuf>,
+    /// When set, only files whose absolute paths are listed are included,
+    /// such as the files changed on a branch.
+    pub only_paths: Option<HashSet<PathBuf>>,
+    /// Only include about this fraction of the files, picked by hashing each
+    /// path with `seed` so the same seed always picks the same files.
+    pub sample_fraction: Option<f64>,
+    /// Only include files whose path below the input, written with `/`,
+    /// contains one of these strings.
+    pub path_contains: Vec<String>,
+    /// Only include files whose path below the input, written with `/`,
+    /// matches one of these expressions.
+    pub path_regex: Vec<Regex>,
+    pub seed: u64,
+    /// Leave out any document that would take the documents written, with
+    /// their headers, past this many bytes, reporting what was left out to
+    /// `err`.
+    pub max_bytes: Option<usize>,
+    /// Leave out files found in a walk that are larger than this many
+    /// bytes, or smaller than `min_file_size`, noting each one on `err`.
+    pub max_file_size: Option<u64>,
+    pub min_file_size: Option<u64>,
+    pub budget_order: BudgetOrder,
+    /// Fill the `max_bytes` budget with the highest scoring files first,
+    /// in place of `budget_order`, writing them in that order. Each file is
+    /// scored on its content as read from disk.
+    pub scorer: Option<Scorer>,
+    /// Gather the files of every input, then write them in this order
+    /// instead of walk order.
+    pub sort: Option<SortOrder>,
+    /// Write the files in the opposite of the `sort` order.
+    pub reverse: bool,
+    /// The index of the first document in XML output and templates, 1 when
+    /// not set. Each file's index follows from its place among the files
+    /// found, so it is the same on every run over the same tree.
+    pub start_index: Option<usize>,
+    pub binary: BinaryFiles,
+    /// Write the decompressed content of `.gz` and `.zst` files.
+    pub decompress: bool,
+    /// Write the text of HTML, PDF and Word documents instead of their
+    /// markup or bytes.
+    pub extract: bool,
+    /// Replace bytes that are not UTF-8 with U+FFFD instead of skipping
+    /// files that are neither UTF-8 nor another encoding recognised.
+    pub lossy: bool,
+    /// With `BinaryFiles::Summary`, add a hex dump of this many leading bytes.
+    pub binary_preview: usize,
+    pub format: OutputFormat,
+    /// Write each document with this template instead of `format`, with its
+    /// header and footer around them.
+    pub template: Option<Template>,
+    pub line_numbers: bool,
+    /// Cut each document down to this many lines at one end, with a line
+    /// saying how many were left out.
+    pub truncate: Option<Truncate>,
+    /// Keep only the last lines of files matching these globs, given like
+    /// `include_patterns`, with the number of lines to keep. The first
+    /// matching glob decides, and it takes precedence over `truncate`.
+    pub tail_patterns: Vec<(String, usize)>,
+    /// Remove comments, and docstrings in Python, from files matching these
+    /// globs, given like `include_patterns`, dropping the lines left empty.
+    /// Files in languages that are not known are written as they are.
+    pub strip_comments_for: Vec<String>,
+    /// Remove comments from every file, as `strip_comments_for` does.
+    pub strip_comments: bool,
+    /// Remove the lines of every file that are empty or only whitespace.
+    pub strip_blank_lines: bool,
+    /// Replace credentials found in files, such as AWS keys, private keys,
+    /// bearer tokens and other values that look random, with
+    /// `[REDACTED:<type>]`, and say on `err` what was replaced in each file.
+    pub redact_secrets: bool,
+    /// Write Jupyter notebooks as Markdown with their code cells fenced,
+    /// rather than as the JSON they are stored in.
+    pub nbconvert: bool,
+    /// Leave the outputs of code cells out of converted notebooks.
+    pub nb_strip_output: bool,
+    /// Write the blocks of Vue and Svelte components, such as the template,
+    /// script and style, as documents of their own in their own languages.
+    pub split_sfc: bool,
+    /// Write each line of a document as a `<line n="12">` element in XML
+    /// output, for answers that must point at exact lines.
+    pub cxml_lines: bool,
+    /// Keep XML output well-formed whatever the files hold: contents go in
+    /// CDATA sections and other text is escaped. Off by default, as models
+    /// read raw contents just as well.
+    pub cxml_strict: bool,
+    pub expand_tabs: bool,
+    pub with_tests: bool,
+    pub interleave: bool,
+    pub include_empty_dirs: bool,
+    /// Read cloud placeholders found in a walk, downloading their contents,
+    /// instead of skipping them.
+    pub hydrate_placeholders: bool,
+    /// Walk into symlinked directories and read symlinked files found in a
+    /// walk, each directory and file once. Otherwise each link is written
+    /// as a one-line entry with its target.
+    pub follow_symlinks: bool,
+    /// Stop collecting once this much wall-clock time has passed. The output
+    /// is still closed properly and the coverage is reported to `err`.
+    pub timeout: Option<Duration>,
+    pub symbol: Option<SymbolFilter>,
+    /// Only include files with a TODO, FIXME or HACK comment.
+    pub todos: bool,
+    /// Only include likely entry points, such as `main.rs`, `index.ts` or
+    /// `app.py`, and the local files they import directly.
+    pub entry_points: bool,
+    /// With `todos`, only output the marker lines plus this many lines
+    /// either side.
+    pub todo_context: Option<usize>,
+    /// Lines matching a pattern get its label appended as `  <-- LABEL`.
+    pub annotations: Vec<(Regex, String)>,
+    /// Split the documents into this many parts of roughly equal size, each
+    /// rendered on its own and headed by what the earlier parts held, for
+    /// feeding a prompt over several conversation turns.
+    pub turns: Option<usize>,
+    /// Lines written as a comment before any documents.
+    pub command_header: Vec<String>,
+    /// Recent commits, one per line, written as a context section before
+    /// the files.
+    pub git_log: Vec<String>,
+    /// Start with a `tree`-style overview of the files that will follow.
+    pub tree: bool,
+    /// Write a one-line placeholder where a file or directory was left out
+    /// by a .gitignore or `ignore_patterns`, could not be read as text or
+    /// did not fit `max_bytes`.
+    pub show_skipped: bool,
+    /// Repeat the warnings written to `err` in a final diagnostics section,
+    /// so the reader of the prompt knows what it is missing.
+    pub embed_warnings: bool,
+    /// Named roots whose files are output side by side: each relative path
+    /// is emitted once per variant that has it, in the order given.
+    pub variants: Vec<(String, PathBuf)>,
+    /// Repeat a map of the collected files every this many thousand tokens
+    /// of output, so it is never far from any document in a long prompt.
+    pub repeat_header: Option<usize>,
+    /// Text written on its own line between documents, in the formats that
+    /// allow it.
+    pub doc_separator: Option<String>,
+    /// End with a list of the local files that included files import but
+    /// that were left out of the output.
+    pub mention_excluded: bool,
+    /// Label documents with their path relative to this absolute directory,
+    /// such as the repository root, instead of the path they were found by.
+    pub label_root: Option<PathBuf>,
+    /// Directories whose files are labeled as if under another name, such
+    /// as the temporary clones of repositories given by URL, labeled with
+    /// the repository's name.
+    pub root_names: Vec<(PathBuf, PathBuf)>,
+    /// Report the size of each document to `err` once collection is done,
+    /// then the lines, the files left out by reason and the time taken.
+    pub stats: Option<StatsFormat>,
+    /// Fail once the output is written if an input was not found or a file
+    /// could not be read, rather than only warning.
+    pub strict: bool,
+    /// Report the time spent in each phase and on the slowest files to
+    /// `err` as JSON once collection is done.
+    pub profile: bool,
+    /// Output every document as a self-contained block headed by a hash of
+    /// its content, in path order, so unchanged files render identically
+    /// from run to run and provider prompt caches keep matching.
+    pub cache_blocks: bool,
+    /// Settings for individual input files, keyed by the path as given.
+    pub path_settings: HashMap<PathBuf, PathSettings>,
+    /// Write paths in headers and messages with this separator instead of
+    /// the platform's own.
+    pub path_separator: Option<char>,
+    /// Shorten paths longer than this many characters in document headers
+    /// and file maps by replacing directories in the middle with `…`. The
+    /// XML `<source>`, JSON `path`, stats and manifests keep the full path.
+    pub max_path_length: Option<usize>,
+}
+
+/// Settings for one input file, such as those given with JSON on stdin.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathSettings {
+    /// Only output this 1-based, inclusive range of lines.
+    pub lines: Option<(usize, usize)>,
+    /// Show the document under this name instead of its path.
+    pub label: Option<String>,
+    /// Custom metadata for the document, or for every file under a
+    /// directory. A numeric `priority` also orders the inputs.
+    pub attributes: Vec<(String, String)>,
+}
+
+impl PathSettings {
+    /// Parse a line range such as `10-50`, or `10` for a single line.
+    pub fn parse_lines(range: &str) -> Option<(usize, usize)> {
+        let (start, end) = range.split_once('-').unwrap_or((range, range));
+        let (start, end) = (start.trim().parse().ok()?, end.trim().parse().ok()?);
+        (start >= 1 && start <= end).then_some((start, end))
+    }
+
+    /// Parse annotations such as `label=core,priority=1`. Keys are limited
+    /// to names that are also valid XML attribute names.
+    pub fn parse_attributes(annotation: &str) -> Option<Vec<(String, String)>> {
+        annotation
+            .split(',')
+            .map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                PathSettings::is_attribute_name(key).then(|| (key.to_string(), value.to_string()))
+            })
+            .collect()
+    }
+
+    pub fn is_attribute_name(key: &str) -> bool {
+        key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || "_-.".contains(c))
+    }
+
+    /// The `priority` attribute; lower numbers come first.
+    pub fn priority(&self) -> Option<i64> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == "priority")
+            .and_then(|(_, value)| value.parse().ok())
+    }
+}
+
+/// A .gitignore line and where it was read from. Like git, the last rule
+/// matching a path decides, so a `!` rule re-includes what an earlier one
+/// excluded.
+struct GitignoreRule {
+    pattern: NamePattern,
+    negated: bool,
+    /// Only matches directories, written with a trailing `/`.
+    dir_only: bool,
+    /// Matched against the path relative to `base` rather than the name,
+    /// because the pattern contains a `/` before its end.
+    anchored: bool,
+    /// The directory holding the .gitignore; rules only apply below it.
+    base: PathBuf,
+    /// Where `base` sits below the directory the rule is relative to, for
+    /// repository-wide rules read while entering a subdirectory.
+    prefix: PathBuf,
+    text: String,
+    file: PathBuf,
+    line: usize,
+}
+
+impl GitignoreRule {
+    fn parse(line: &str, base: &Path, file: &Path, number: usize) -> Option<Self> {
+        // Trailing spaces are dropped unless escaped; leading ones are kept
+        let mut text = line.trim_end_matches(['\n', '\r']);
+        while text.ends_with(' ') && !text.ends_with("\\ ") {
+            text = &text[..text.len() - 1];
+        }
+        if text.is_empty() || text.starts_with('#') {
+            return None;
+        }
+
+        let normalized = names::normalize_separators(text, true);
+        let (negated, glob) = match normalized.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => {
+                let escaped = normalized.strip_prefix('\\').filter(|rest| rest.starts_with(['#', '!']));
+                (false, escaped.unwrap_or(&normalized))
+            }
+        };
+        let (dir_only, glob) = match glob.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, glob),
+        };
+        let anchored = glob.contains('/');
+        let glob = glob.strip_prefix('/').unwrap_or(glob);
+
+        let pattern = if anchored {
+            NamePattern::new_path(glob)
+        } else {
+            NamePattern::new(&glob.replace("\\", ""))
+        };
+        Some(GitignoreRule {
+            pattern: pattern.unwrap_or_else(|| NamePattern::new_or_match_all("*")),
+            negated,
+            dir_only,
+            anchored,
+            base: base.to_path_buf(),
+            prefix: PathBuf::new(),
+            text: text.to_string(),
+            file: file.to_path_buf(),
+            line: number,
+        })
+    }
+
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.base) else {
+            return false;
+        };
+        !relative.as_os_str().is_empty() && self.matches_relative(&self.prefix.join(relative), is_dir)
+    }
+
+    /// Whether the rule matches `path`, given relative to the directory the
+    /// rule is relative to.
+    fn matches_relative(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            let components: Vec<_> = path.components().map(|c| names::name_bytes(c.as_os_str())).collect();
+            self.pattern.matches_bytes(&components.join(&b'/'))
+        } else {
+            path.file_name().is_some_and(|name| self.pattern.matches(name))
+        }
+    }
+}
+
+/// An `--ignore` pattern. One without a `/` matches names. One with a `/`
+/// matches the end of the path, so `gen/*.rs` ignores the files directly in
+/// any directory named `gen`. A trailing `/` only matches directories.
+struct IgnorePattern {
+    pattern: NamePattern,
+    spans_path: bool,
+    dir_only: bool,
+}
+
+impl IgnorePattern {
+    fn new(text: &str) -> Self {
+        let text = names::normalize_separators(text, false);
+        let (dir_only, glob) = match text.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, &text[..]),
+        };
+        if !glob.contains('/') {
+            return IgnorePattern { pattern: NamePattern::new_or_match_all(glob), spans_path: false, dir_only };
+        }
+        let pattern = NamePattern::new_path(&format!("**/{}", glob.trim_start_matches('/')));
+        IgnorePattern {
+            pattern: pattern.unwrap_or_else(|| NamePattern::new_or_match_all("*")),
+            spans_path: true,
+            dir_only,
+        }
+    }
+
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if !self.spans_path {
+            return path.file_name().is_some_and(|name| self.pattern.matches(name));
+        }
+        let components: Vec<_> = path.components().map(|c| names::name_bytes(c.as_os_str())).collect();
+        self.pattern.matches_bytes(&components.join(&b'/'))
+    }
+}
+
+/// The patterns of `.git/info/sparse-checkout` for `respect_sparse`, read
+/// for the inputs below `base`. They are written like .gitignore rules, but
+/// a path is checked out when the last pattern matching it is not a `!`
+/// one. Cone mode patterns are read the same way, which gives the same
+/// result.
+struct SparseCheckout {
+    base: PathBuf,
+    /// Where `base` sits in the repository.
+    prefix: PathBuf,
+    rules: Vec<GitignoreRule>,
+}
+
+impl SparseCheckout {
+    /// The pattern deciding whether `path` is checked out. As in git, a path
+    /// no pattern matches is decided by its directory, and one nothing
+    /// decides is not checked out.
+    fn deciding_rule(&self, path: &Path, is_dir: bool) -> Option<&GitignoreRule> {
+        let relative = self.prefix.join(path.strip_prefix(&self.base).ok()?);
+        relative.ancestors().take_while(|ancestor| !ancestor.as_os_str().is_empty()).enumerate().find_map(
+            |(i, ancestor)| self.rules.iter().rev().find(|rule| rule.matches_relative(ancestor, is_dir || i > 0)),
+        )
+    }
+}
+
+/// Whether a git config file turns sparse checkout on or off, when it
+/// sets `core.sparseCheckout` at all.
+fn sparse_checkout_setting(config: &str) -> Option<bool> {
+    let mut section = String::new();
+    let mut setting = None;
+    for line in config.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('[').and_then(|line| line.split(']').next()) {
+            section = name.trim().to_lowercase();
+            continue;
+        }
+        let (key, value) = line.split_once('=').unwrap_or((line, "true"));
+        if section == "core" && key.trim().eq_ignore_ascii_case("sparsecheckout") {
+            setting = Some(matches!(value.trim().to_lowercase().as_str(), "true" | "yes" | "on" | "1"));
+        }
+    }
+    setting
+}
+
+/// Where a rule that can exclude a path comes from.
+enum RuleSource {
+    /// A built-in exclusion such as hidden names.
+    Default,
+    Gitignore { file: PathBuf, line: usize },
+    SparseCheckout,
+    IgnoreOption,
+    ExtensionOption,
+}
+
+impl RuleSource {
+    fn describe(&self, renderer: &PathRenderer) -> String {
+        match self {
+            RuleSource::Default => "default".to_string(),
+            RuleSource::Gitignore { file, line } => format!("{} line {}", renderer.render(file), line),
+            RuleSource::SparseCheckout => "sparse checkout".to_string(),
+            RuleSource::IgnoreOption => "--ignore".to_string(),
+            RuleSource::ExtensionOption => "--extension".to_string(),
+        }
+    }
+}
+
+/// One rule consulted for a path, whether it matched and whether that
+/// excludes the path. A matching .gitignore rule only excludes when no later
+/// rule matches and it is not a `!` rule.
+struct RuleCheck {
+    source: RuleSource,
+    rule: String,
+    matched: bool,
+    excludes: bool,
+}
+
+impl RuleCheck {
+    fn new(source: RuleSource, rule: &str, matched: bool) -> Self {
+        RuleCheck { source, rule: rule.to_string(), matched, excludes: matched }
+    }
+}
+
+/// Where a rendering sits when the output is split with `turns`.
+struct Part {
+    number: usize,
+    count: usize,
+    /// The labels of the files in the earlier parts, when listed.
+    previous: Vec<String>,
+}
+
+/// Progress of a running collection, reported after each file is written.
+#[derive(Debug, Clone, Copy)]
+pub struct Progress<'a> {
+    /// The file (or empty directory) just written.
+    pub path: &'a Path,
+    /// How many files have been written so far.
+    pub files_done: usize,
+    /// How many files have been listed so far. This grows as each input
+    /// path is walked, so it is only final for the last one.
+    pub files_found: usize,
+}
+
+/// Collect `paths` according to `options`, writing the rendered prompt to
+/// `out` and warnings about skipped paths to `err`.
+pub fn collect(
+    paths: &[PathBuf],
+    options: &Options,
+    fs: &dyn FileSystem,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> io::Result<()> {
+    collect_with(paths, options, fs, out, err, |_| {}, &AtomicBool::new(false))
+}
+
+/// Like [`collect`], calling `progress` after each file and stopping once
+/// `cancel` is set. A cancelled collection still closes the output format
+/// before returning an `Interrupted` error.
+pub fn collect_with(
+    paths: &[PathBuf],
+    options: &Options,
+    fs: &dyn FileSystem,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+    mut progress: impl FnMut(Progress),
+    cancel: &AtomicBool,
+) -> io::Result<()> {
+    if let Some(turns) = options.turns.filter(|&turns| turns > 1) {
+        return collect_turns(paths, options, turns, fs, out, err, &mut progress, cancel);
+    }
+
+    let mut collector = Collector::new(options, fs, out, err, &mut progress, cancel);
+    collector.run(paths)
+}
+
+// Each part is a complete rendering of its files; the command header and
+// git log only go in the first
+#[allow(clippy::too_many_arguments)]
+fn collect_turns(
+    paths: &[PathBuf],
+    options: &Options,
+    turns: usize,
+    fs: &dyn FileSystem,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+    progress: &mut dyn FnMut(Progress),
+    cancel: &AtomicBool,
+) -> io::Result<()> {
+    let files = list_files(paths, options, fs, &mut io::sink())?;
+    if files.is_empty() {
+        return Collector::new(options, fs, out, err, progress, cancel).run(paths);
+    }
+
+    // Sized as written, after filters and truncation
+    let no_ranges = HashMap::new();
+    let empty = rendered_size(options, fs, &[], &no_ranges, None)?;
+    let sizes = files
+        .iter()
+        .map(|file| Ok(rendered_size(options, fs, std::slice::from_ref(file), &no_ranges, None)?.saturating_sub(empty) as u64))
+        .collect::<io::Result<Vec<u64>>>()?;
+    let parts = split_by_size(&sizes, turns);
+    let mut previous: Vec<String> = Vec::new();
+    for (number, range) in parts.iter().enumerate() {
+        let mut collector = Collector::new(options, fs, out, err, progress, cancel);
+        collector.part = Some(Part {
+            number: number + 1,
+            count: parts.len(),
+            previous: previous.clone(),
+        });
+        // Numbered on from the earlier parts, so the parts put together have no repeats
+        collector.first_index += range.start;
+        collector.run(&files[range.clone()])?;
+
+        let listed = files[range.clone()].iter().map(|file| header_path(&collector.renderer, &collector.label(file)));
+        previous.extend(listed);
+    }
+
+    Ok(())
+}
+
+/// Collect `paths` into parts of at most `max_bytes` each, every one a
+/// complete rendering headed like the parts of `turns`, writing part `n` to
+/// what `open_part(n)` returns. Files are kept whole unless one does not
+/// fit in a part of its own, when it is cut into line ranges. Returns how
+/// many parts were written.
+pub fn collect_split(
+    paths: &[PathBuf],
+    options: &Options,
+    fs: &dyn FileSystem,
+    max_bytes: usize,
+    err: &mut dyn Write,
+    mut open_part: impl FnMut(usize) -> io::Result<Box<dyn Write>>,
+) -> io::Result<usize> {
+    let mut progress = |_: Progress| {};
+    let cancel = AtomicBool::new(false);
+    let files = list_files(paths, options, fs, &mut io::sink())?;
+    if files.is_empty() {
+        let mut out = open_part(1)?;
+        Collector::new(options, fs, &mut *out, err, &mut progress, &cancel).run(paths)?;
+        return Ok(1);
+    }
+
+    let parts = plan_parts(&files, options, fs, max_bytes, err)?;
+    for (number, part) in parts.iter().enumerate() {
+        let part_files: Vec<PathBuf> = part.iter().map(|piece| piece.file.clone()).collect();
+        let mut out = open_part(number + 1)?;
+        let mut collector = Collector::new(options, fs, &mut *out, err, &mut progress, &cancel);
+        if parts.len() > 1 {
+            collector.part = Some(Part { number: number + 1, count: parts.len(), previous: Vec::new() });
+        }
+        collector.line_ranges = part.iter().filter_map(|piece| Some((piece.file.clone(), piece.lines?))).collect();
+        // Numbered as in a single output, so a file cut in two has one index
+        collector.first_index += files.iter().position(|file| *file == part_files[0]).unwrap_or(0);
+        collector.run(&part_files)?;
+    }
+    Ok(parts.len())
+}
+
+/// A file, or a range of its lines, and the size it adds to an output.
+struct Piece {
+    file: PathBuf,
+    lines: Option<(usize, usize)>,
+    size: usize,
+}
+
+// Each part is filled with whole files in order while they fit
+fn plan_parts(
+    files: &[PathBuf],
+    options: &Options,
+    fs: &dyn FileSystem,
+    max_bytes: usize,
+    err: &mut dyn Write,
+) -> io::Result<Vec<Vec<Piece>>> {
+    let no_ranges = HashMap::new();
+    let empty = rendered_size(options, fs, &[], &no_ranges, None)?;
+    // The first part's header differs from the rest. Every part holds at
+    // least a line, so there are never more parts than files and bytes.
+    let bytes: u64 = files.iter().map(|file| fs.size(file).unwrap_or(0)).sum();
+    let most = files.len() + bytes as usize;
+    let mut overhead = 0;
+    for number in [1, most] {
+        let part = Part { number, count: most, previous: Vec::new() };
+        overhead = overhead.max(rendered_size(options, fs, &[], &no_ranges, Some(part))?);
+    }
+    if overhead >= max_bytes {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("parts of {} leave no room for files after their headers", stats::format_size(max_bytes as u64)),
+        ));
+    }
+    let room = max_bytes - overhead;
+
+    let mut pieces = Vec::new();
+    for file in files {
+        let size = rendered_size(options, fs, std::slice::from_ref(file), &no_ranges, None)?.saturating_sub(empty);
+        let cut = options.path_settings.get(file).is_some_and(|settings| settings.lines.is_some());
+        if size <= room || cut || !fs.is_file(file) {
+            pieces.push(Piece { file: file.clone(), lines: None, size });
+        } else {
+            pieces.extend(cut_into_ranges(file, options, fs, room, empty)?);
+        }
+    }
+
+    let mut parts: Vec<Vec<Piece>> = Vec::new();
+    let mut used = 0;
+    for piece in pieces {
+        if piece.size > room {
+            let label = PathRenderer { separator: options.path_separator }.render(&piece.file);
+            writeln!(err, "Warning: {} could not be cut to fit in a part, so its part goes over the limit", label)?;
+        }
+        // Two ranges of one file never share a part
+        let new_part = parts.last().map_or(true, |part| {
+            used + piece.size > room || part.iter().any(|other| other.file == piece.file)
+        });
+        if new_part {
+            parts.push(Vec::new());
+            used = 0;
+        }
+        used += piece.size;
+        parts.last_mut().unwrap().push(piece);
+    }
+    Ok(parts)
+}
+
+// Take as many lines as their bytes allow, then give up a tenth of them at
+// a time until the rendering fits; a single line that does not fit is
+// taken alone
+fn cut_into_ranges(
+    file: &Path,
+    options: &Options,
+    fs: &dyn FileSystem,
+    room: usize,
+    empty: usize,
+) -> io::Result<Vec<Piece>> {
+    let content = encoding::decode(fs.read(file)?, true)?;
+    let lines: Vec<&str> = content.lines().collect();
+    let files = [file.to_path_buf()];
+    let mut pieces = Vec::new();
+    let mut start = 1;
+    while start <= lines.len() {
+        let mut end = start;
+        let mut bytes = lines[start - 1].len() + 1;
+        while end < lines.len() && bytes + lines[end].len() < room {
+            bytes += lines[end].len() + 1;
+            end += 1;
+        }
+        loop {
+            let ranges = HashMap::from([(file.to_path_buf(), (start, end))]);
+            let size = rendered_size(options, fs, &files, &ranges, None)?.saturating_sub(empty);
+            if size <= room || end == start {
+                pieces.push(Piece { file: file.to_path_buf(), lines: Some((start, end)), size });
+                break;
+            }
+            end = start + (end - start) * 9 / 10;
+        }
+        start = end + 1;
+    }
+    Ok(pieces)
+}
+
+/// The length of `files` rendered on their own, cut to `ranges`.
+fn rendered_size(
+    options: &Options,
+    fs: &dyn FileSystem,
+    files: &[PathBuf],
+    ranges: &HashMap<PathBuf, (usize, usize)>,
+    part: Option<Part>,
+) -> io::Result<usize> {
+    let mut out = Vec::new();
+    let mut progress = |_: Progress| {};
+    let cancel = AtomicBool::new(false);
+    let mut err = io::sink();
+    let mut collector = Collector::new(options, fs, &mut out, &mut err, &mut progress, &cancel);
+    collector.part = part;
+    collector.line_ranges = ranges.clone();
+    collector.run(files)?;
+    Ok(out.len())
+}
+
+/// List the files `collect` would include for `paths`; see [`Walker::files`].
+pub fn list_files(
+    paths: &[PathBuf],
+    options: &Options,
+    fs: &dyn FileSystem,
+    err: &mut dyn Write,
+) -> io::Result<Vec<PathBuf>> {
+    Walker::new(options, fs).files(paths, err)
+}
+
+/// A file selected for a prompt, as read from the file system.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileEntry {
+    pub path: PathBuf,
+    pub content: String,
+}
+
+/// The files `collect` would include for `paths`; see [`Walker::entries`].
+pub fn entries<'a>(
+    paths: &[PathBuf],
+    options: &Options,
+    fs: &'a dyn FileSystem,
+    err: &mut dyn Write,
+) -> io::Result<impl Iterator<Item = io::Result<FileEntry>> + 'a> {
+    let files = list_files(paths, options, fs, err)?;
+    Ok(read_entries(fs, files, options.lossy))
+}
+
+/// Walks input paths with the filters of an [`Options`], for programs that
+/// want the selected files rather than a rendered prompt.
+pub struct Walker<'a> {
+    options: &'a Options,
+    fs: &'a dyn FileSystem,
+}
+
+impl<'a> Walker<'a> {
+    pub fn new(options: &'a Options, fs: &'a dyn FileSystem) -> Self {
+        Walker { options, fs }
+    }
+
+    /// List the files `collect` would include for `paths`, without reading
+    /// or rendering them. Empty directories are included as themselves when
+    /// `include_empty_dirs` is set.
+    pub fn files(&self, paths: &[PathBuf], err: &mut dyn Write) -> io::Result<Vec<PathBuf>> {
+        let mut sink = io::sink();
+        let mut progress = |_: Progress| {};
+        let cancel = AtomicBool::new(false);
+        let mut collector = Collector::new(self.options, self.fs, &mut sink, err, &mut progress, &cancel);
+
+        let mut files = Vec::new();
+        for path in collector.existing_paths(paths)? {
+            collector.enter_path(path)?;
+            files.extend(collector.gather(path)?);
+        }
+        files.retain(|file| !collector.skipped.contains_key(file));
+        Ok(files)
+    }
+
+    /// The files `collect` would include for `paths`, for programs that
+    /// render them their own way, with [`render`] or otherwise. Directories
+    /// are walked up front, warning to `err` about missing paths; each file
+    /// is read as the iterator reaches it, and one that cannot be read as
+    /// text yields its error. The content is the file as stored, transcoded
+    /// to UTF-8: line numbers, slicing and other rendering options only
+    /// apply to `collect`.
+    pub fn entries(
+        &self,
+        paths: &[PathBuf],
+        err: &mut dyn Write,
+    ) -> io::Result<impl Iterator<Item = io::Result<FileEntry>> + 'a> {
+        let files = self.files(paths, err)?;
+        Ok(read_entries(self.fs, files, self.options.lossy))
+    }
+}
+
+// Each of `files` that is a file, read as the iterator reaches it
+fn read_entries(
+    fs: &dyn FileSystem,
+    files: Vec<PathBuf>,
+    lossy: bool,
+) -> impl Iterator<Item = io::Result<FileEntry>> + '_ {
+    files.into_iter().filter(|path| fs.is_file(path)).map(move |path| {
+        let content = encoding::decode(fs.read(&path)?, lossy)?;
+        Ok(FileEntry { path, content })
+    })
+}
+
+/// Write `entries` laid out by `formatter`, one of the [`OutputFormat`]s or
+/// a format of the caller's own, with `separator` between documents where
+/// the format allows it. Each document is only its path and content, as the
+/// rendering options of `collect` do not apply.
+pub fn render(
+    entries: impl IntoIterator<Item = FileEntry>,
+    formatter: &dyn Formatter,
+    separator: Option<&str>,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let separator = separator.filter(|_| formatter.allows_separator());
+    formatter.start(out)?;
+    for (i, entry) in entries.into_iter().enumerate() {
+        if let Some(separator) = separator.filter(|_| i > 0) {
+            writeln!(out, "{}", separator)?;
+        }
+        formatter.write_document(out, i + 1, &entry)?;
+    }
+    formatter.finish(out)
+}
+
+/// Write every filter rule consulted for `path`, with the .gitignore file
+/// and line, option or default it came from, and whether `path` would be
+/// included when walking down to it.
+pub fn explain_rules(path: &Path, options: &Options, fs: &dyn FileSystem, out: &mut dyn Write) -> io::Result<()> {
+    let mut err = io::sink();
+    let mut progress = |_: Progress| {};
+    let cancel = AtomicBool::new(false);
+    let mut collector = Collector::new(options, fs, out, &mut err, &mut progress, &cancel);
+    collector.explain(path)
+}
+
+struct Collector<'a> {
+    options: &'a Options,
+    fs: &'a dyn FileSystem,
+    out: &'a mut dyn Write,
+    err: &'a mut dyn Write,
+    progress: &'a mut dyn FnMut(Progress),
+    cancel: &'a AtomicBool,
+    cancelled: bool,
+    ignore_patterns: Vec<IgnorePattern>,
+    /// Each include glob, unless invalid, and whether it spans the path.
+    include_patterns: Vec<(Option<NamePattern>, bool)>,
+    /// `tail_patterns` compiled like `include_patterns`.
+    tail_patterns: Vec<((Option<NamePattern>, bool), usize)>,
+    /// The files gathered that match `tail_patterns`, with the number of
+    /// lines to keep.
+    tail_lines: HashMap<PathBuf, usize>,
+    /// `strip_comments_for` compiled like `include_patterns`.
+    strip_comments_patterns: Vec<(Option<NamePattern>, bool)>,
+    /// The files gathered that match `strip_comments_patterns`.
+    strip_comments: HashSet<PathBuf>,
+    gitignore_rules: Vec<GitignoreRule>,
+    sparse_checkouts: Vec<SparseCheckout>,
+    seen: HashSet<PathBuf>,
+    /// The index of each file found, from its place in the walk, so a file
+    /// keeps its index whichever others are left out.
+    indices: HashMap<PathBuf, usize>,
+    /// The index of the first file found.
+    first_index: usize,
+    ext_to_lang: HashMap<String, &'static str>,
+    deadline: Option<Instant>,
+    timed_out: bool,
+    walk_incomplete: bool,
+    files_found: usize,
+    files_emitted: usize,
+    variant: Option<String>,
+    /// The component block being written with `split_sfc`.
+    section: Option<Section>,
+    file_map: Vec<PathBuf>,
+    bytes_since_map: usize,
+    wrote_document: bool,
+    included: HashSet<PathBuf>,
+    imported: Vec<PathBuf>,
+    walk_stack: Vec<PathBuf>,
+    placeholders: usize,
+    current_input: Option<PathBuf>,
+    block_hash: Option<String>,
+    file_stats: Vec<FileStats>,
+    renderer: PathRenderer,
+    part: Option<Part>,
+    /// The last JSON document, held back until we know whether a comma
+    /// follows it.
+    pending_json: Option<String>,
+    budget_used: usize,
+    over_budget: Vec<(PathBuf, usize)>,
+    /// Output held back by `print_path` until it knows the document fits
+    /// the budget.
+    held: Option<String>,
+    /// Entries a walk left out but kept in place for `show_skipped`, with
+    /// the reason.
+    skipped: HashMap<PathBuf, String>,
+    /// How many files were left out for each reason, for the `stats`
+    /// summary and `strict`.
+    skip_counts: Vec<(&'static str, usize)>,
+    started: Instant,
+    /// Links a walk found and did not follow, with their targets.
+    symlinks: HashMap<PathBuf, PathBuf>,
+    /// The part of each file that is too long for one part of a split
+    /// output to write in this one.
+    line_ranges: HashMap<PathBuf, (usize, usize)>,
+    /// The canonical paths of the directories and files reached, with
+    /// `follow_symlinks`, so none is output twice.
+    visited: HashSet<PathBuf>,
+    /// Warnings kept for the diagnostics section with `embed_warnings`.
+    diagnostics: Vec<String>,
+    profile: Option<Profile>,
+}
+
+impl<'a> Collector<'a> {
+    fn new(
+        options: &'a Options,
+        fs: &'a dyn FileSystem,
+        out: &'a mut dyn Write,
+        err: &'a mut dyn Write,
+        progress: &'a mut dyn FnMut(Progress),
+        cancel: &'a AtomicBool,
+    ) -> Self {
+        Collector {
+            options,
+            fs,
+            out,
+            err,
+            progress,
+            cancel,
+            cancelled: false,
+            ignore_patterns: options
+                .ignore_patterns
+                .iter()
+                .map(|pattern| IgnorePattern::new(pattern))
+                .collect(),
+            include_patterns: options.include_patterns.iter().map(|pattern| include_pattern(pattern)).collect(),
+            tail_patterns: options
+                .tail_patterns
+                .iter()
+                .map(|(pattern, lines)| (include_pattern(pattern), *lines))
+                .collect(),
+            tail_lines: HashMap::new(),
+            strip_comments_patterns: options
+                .strip_comments_for
+                .iter()
+                .map(|pattern| include_pattern(pattern))
+                .collect(),
+            strip_comments: HashSet::new(),
+            gitignore_rules: Vec::new(),
+            sparse_checkouts: Vec::new(),
+            seen: HashSet::new(),
+            indices: HashMap::new(),
+            first_index: options.start_index.unwrap_or(1),
+            ext_to_lang: initialize_ext_to_lang(),
+            deadline: options.timeout.map(|timeout| Instant::now() + timeout),
+            timed_out: false,
+            walk_incomplete: false,
+            files_found: 0,
+            files_emitted: 0,
+            variant: None,
+            section: None,
+            file_map: Vec::new(),
+            bytes_since_map: 0,
+            wrote_document: false,
+            included: HashSet::new(),
+            imported: Vec::new(),
+            walk_stack: Vec::new(),
+            placeholders: 0,
+            current_input: None,
+            block_hash: None,
+            file_stats: Vec::new(),
+            renderer: PathRenderer {
+                separator: options.path_separator,
+            },
+            part: None,
+            pending_json: None,
+            budget_used: 0,
+            over_budget: Vec::new(),
+            held: None,
+            skipped: HashMap::new(),
+            skip_counts: Vec::new(),
+            started: Instant::now(),
+            symlinks: HashMap::new(),
+            line_ranges: HashMap::new(),
+            visited: HashSet::new(),
+            diagnostics: Vec::new(),
+            profile: options.profile.then(Profile::new),
+        }
+    }
+
+    fn run(&mut self, paths: &[PathBuf]) -> io::Result<()> {
+        let cxml = self.options.format == OutputFormat::Cxml;
+        let existing = self.existing_paths(paths)?;
+
+        if self.options.doc_separator.is_some() && !self.options.format.allows_separator() {
+            self.warn(format!("Warning: document separators are not used in {} output", self.options.format.name()))?;
+        }
+
+        let continuation = self.part.as_ref().is_some_and(|part| part.number > 1);
+        if !self.options.command_header.is_empty() && !continuation {
+            self.write_command_header()?;
+        }
+        if let Some(header) = self.options.template.as_ref().and_then(Template::header) {
+            self.write_output(&header)?;
+        }
+
+        // Start XML document if needed
+        let documents = cxml && (self.options.cxml_strict || !existing.is_empty() || !self.options.variants.is_empty());
+        if documents {
+            self.write_output("<documents>")?;
+        }
+        if self.options.format == OutputFormat::Json {
+            self.write_output("[")?;
+        }
+
+        if self.part.is_some() {
+            self.write_part_header()?;
+        }
+
+        if !self.options.git_log.is_empty() && !continuation {
+            self.write_git_log()?;
+        }
+
+        if self.options.tree {
+            self.write_tree(paths)?;
+        }
+
+        // Process each path
+        // Inputs with a priority come first, lowest number first
+        let mut existing = existing;
+        existing.sort_by_key(|path| {
+            let priority = self.options.path_settings.get(*path).and_then(PathSettings::priority);
+            (priority.is_none(), priority)
+        });
+
+        if self.options.cache_blocks || self.options.sort.is_some() {
+            self.process_sorted(&existing)?;
+        } else {
+            for path in existing {
+                if self.should_stop() {
+                    self.walk_incomplete = true;
+                    break;
+                }
+
+                self.enter_path(path)?;
+                self.current_input = Some(path.to_path_buf());
+                self.process_path(path)?;
+                self.current_input = None;
+            }
+        }
+
+        if !self.options.variants.is_empty() {
+            self.process_variants()?;
+        }
+
+        if self.options.mention_excluded {
+            self.write_not_included()?;
+        }
+
+        if self.timed_out {
+            self.report_timeout()?;
+        }
+        if !self.over_budget.is_empty() {
+            self.report_over_budget()?;
+        }
+        if self.placeholders > 0 {
+            self.warn(format!(
+                "Warning: skipped {} cloud placeholder{} that {} not downloaded; use --hydrate-placeholders to include them",
+                self.placeholders,
+                if self.placeholders == 1 { "" } else { "s" },
+                if self.placeholders == 1 { "is" } else { "are" }
+            ))?;
+        }
+        if !self.diagnostics.is_empty() {
+            let body = self.diagnostics.join("\n");
+            self.write_section(DIAGNOSTICS_TITLE, "diagnostics", &body)?;
+        }
+        if let Some(footer) = self.options.template.as_ref().and_then(Template::footer) {
+            self.write_output(&footer)?;
+        }
+
+        // Close XML document if needed
+        if documents {
+            self.write_output("</documents>")?;
+        }
+        if self.options.format == OutputFormat::Json {
+            if let Some(document) = self.pending_json.take() {
+                self.write_output(&document)?;
+            }
+            self.write_output("]")?;
+        }
+
+        self.out.flush()?;
+
+        if self.cancelled {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "collection cancelled"));
+        }
+        if let Some(format) = self.options.stats {
+            let summary = Summary {
+                skipped: self.skip_counts.iter().map(|(reason, count)| (reason.to_string(), *count)).collect(),
+                elapsed: self.started.elapsed(),
+            };
+            stats::write_stats(&self.file_stats, &summary, format, self.err)?;
+        }
+        if let Some(profile) = &self.profile {
+            profile.write_report(self.err)?;
+        }
+        let unreadable: usize = self
+            .skip_counts
+            .iter()
+            .filter(|(reason, _)| [SKIP_UNREADABLE, SKIP_MISSING].contains(reason))
+            .map(|(_, count)| count)
+            .sum();
+        if self.options.strict && unreadable > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("{} path{} could not be read (--strict)", unreadable, if unreadable == 1 { "" } else { "s" }),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// The inputs that exist, warning about the rest.
+    fn existing_paths<'p>(&mut self, paths: &'p [PathBuf]) -> io::Result<Vec<&'p Path>> {
+        let mut existing = Vec::new();
+        for path in paths {
+            if self.fs.exists(path) {
+                existing.push(path.as_path());
+            } else {
+                self.count_skip(SKIP_MISSING);
+                self.warn(format!("Path does not exist: {}", self.renderer.render(path)))?;
+            }
+        }
+        Ok(existing)
+    }
+
+    // Rules next to an input path apply to it as well as below it
+    fn enter_path(&mut self, path: &Path) -> io::Result<()> {
+        let dir = if self.fs.is_dir(path) { path } else { path.parent().unwrap_or(Path::new("")) };
+        if self.options.respect_sparse {
+            self.read_sparse_checkout(dir)?;
+        }
+        if !self.options.ignore_gitignore {
+            let rules = self.repository_rules(dir)?;
+            // Repository-wide rules rank below every .gitignore
+            self.gitignore_rules.splice(0..0, rules);
+
+            if let Some(parent) = path.parent() {
+                let file = parent.join(".gitignore");
+                if !self.gitignore_rules.iter().any(|rule| rule.file == file) {
+                    let rules = self.read_gitignore(parent)?;
+                    self.gitignore_rules.extend(rules);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn should_stop(&mut self) -> bool {
+        if self.cancel.load(Ordering::Relaxed) {
+            self.cancelled = true;
+        }
+        if !self.timed_out && self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            self.timed_out = true;
+        }
+        self.cancelled || self.timed_out
+    }
+
+    fn report_timeout(&mut self) -> io::Result<()> {
+        let timeout = self.options.timeout.unwrap_or_default().as_secs_f64();
+        if self.walk_incomplete {
+            self.warn(format!(
+                "Warning: timed out after {}s while listing files; the output covers {} files and the rest of the tree was not reached",
+                timeout, self.files_emitted
+            ))
+        } else {
+            let percent = self.files_emitted * 100 / self.files_found.max(1);
+            self.warn(format!(
+                "Warning: timed out after {}s; the output covers {} of {} files ({}%)",
+                timeout, self.files_emitted, self.files_found, percent
+            ))
+        }
+    }
+
+    /// Write the command header as a comment suited to the output format:
+    /// an HTML comment for XML and Markdown, `#` lines otherwise.
+    fn write_command_header(&mut self) -> io::Result<()> {
+        let options = self.options;
+        match options.format {
+            OutputFormat::Cxml | OutputFormat::Markdown => {
+                self.write_output("<!--")?;
+                for line in &options.command_header {
+                    let mut line = line.clone();
+                    // "--" may not appear inside an XML comment
+                    while options.format == OutputFormat::Cxml && line.contains("--") {
+                        line = line.replace("--", "-\\-");
+                    }
+                    self.write_output(&line)?;
+                }
+                self.write_output("-->")?;
+            }
+            OutputFormat::Default => {
+                for line in &options.command_header {
+                    self.write_output(&format!("# {}", line))?;
+                }
+            }
+            // JSON has no comments
+            OutputFormat::Json => {}
+        }
+
+        Ok(())
+    }
+
+    fn write_git_log(&mut self) -> io::Result<()> {
+        let log = self.options.git_log.join("\n");
+        self.write_section(GIT_LOG_TITLE, "git_log", &log)
+    }
+
+    /// Draw the files the walk will include. Filters that look at file
+    /// contents, such as `symbol`, are only applied later, when each file is
+    /// read.
+    fn write_tree(&mut self, paths: &[PathBuf]) -> io::Result<()> {
+        let files = list_files(paths, self.options, self.fs, &mut io::sink())?;
+        let components: Vec<Vec<String>> = files
+            .iter()
+            .map(|file| {
+                self.label(file)
+                    .components()
+                    .filter(|component| !matches!(component, Component::CurDir))
+                    .map(|component| names::display_path(Path::new(component.as_os_str())))
+                    .collect()
+            })
+            .collect();
+        self.write_section(TREE_TITLE, "directory_tree", &tree::render_tree(&components))
+    }
+
+    fn write_part_header(&mut self) -> io::Result<()> {
+        let Some(part) = &self.part else {
+            return Ok(());
+        };
+        let title = format!("Part {} of {}", part.number, part.count);
+        let body = if part.number == 1 {
+            format!("{}; the remaining files follow in later parts.", title)
+        } else if part.previous.is_empty() {
+            format!("{}; earlier parts hold the files before these.", title)
+        } else {
+            format!("{}; previous parts contained: {}", title, part.previous.join(", "))
+        };
+        self.write_section(&title, "part", &body)
+    }
+
+    /// List the imported files that never made it into the output, so the
+    /// reader knows they exist.
+    fn write_not_included(&mut self) -> io::Result<()> {
+        let missing: Vec<String> = self
+            .imported
+            .iter()
+            .filter(|file| !self.included.contains(*file))
+            .map(|file| self.header_label(file))
+            .collect();
+
+        if missing.is_empty() {
+            return Ok(());
+        }
+        self.write_section(NOT_INCLUDED_TITLE, "not_included", &missing.join("\n"))
+    }
+
+    /// Write the file map before the first document, then again once enough
+    /// output has gone by since the last one.
+    fn maybe_write_file_map(&mut self) -> io::Result<()> {
+        let Some(thousands) = self.options.repeat_header else {
+            return Ok(());
+        };
+        if self.files_emitted > 0 && self.bytes_since_map < thousands * 1000 * stats::BYTES_PER_TOKEN {
+            return Ok(());
+        }
+
+        let map: Vec<String> = self.file_map.iter().map(|file| self.header_label(file)).collect();
+        self.write_section(FILE_MAP_TITLE, "file_map", &map.join("\n"))?;
+        self.bytes_since_map = 0;
+        Ok(())
+    }
+
+    /// A block of text outside the documents: an element for XML, a titled
+    /// code block for Markdown and a titled `---` block otherwise. JSON
+    /// output only holds documents, so it is left out there.
+    fn write_section(&mut self, title: &str, tag: &str, body: &str) -> io::Result<()> {
+        match self.options.format {
+            OutputFormat::Cxml => {
+                self.write_output(&format!("<{}>", tag))?;
+                if self.options.cxml_strict {
+                    self.write_output(&escape_text(body))?;
+                } else {
+                    self.write_output(body)?;
+                }
+                self.write_output(&format!("</{}>", tag))?;
+            }
+            OutputFormat::Markdown => {
+                self.write_output(title)?;
+                self.write_output("```")?;
+                self.write_output(body)?;
+                self.write_output("```")?;
+            }
+            OutputFormat::Default => {
+                self.write_output(title)?;
+                self.write_output("---")?;
+                self.write_output(body)?;
+                self.write_output("")?;
+                self.write_output("---")?;
+            }
+            OutputFormat::Json => {}
+        }
+
+        Ok(())
+    }
+
+    fn gather(&mut self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let started = Instant::now();
+        let mut files = Vec::new();
+
+        if self.fs.is_file(path) {
+            files.push(path.to_path_buf());
+        } else if self.fs.is_dir(path) {
+            self.walk_directory(path, &mut files)?;
+
+            if !self.include_patterns.is_empty() {
+                files.retain(|file| self.matches_include(path, file));
+            }
+            if !self.options.mime_types.is_empty() {
+                files.retain(|file| self.matches_mime(file));
+            }
+            if self.options.max_file_size.is_some() || self.options.min_file_size.is_some() {
+                files = self.filter_by_size(files)?;
+            }
+            if self.options.auto_extensions && self.options.extensions.is_empty() {
+                files = self.auto_select(path, files)?;
+            }
+            if self.options.interleave {
+                files = interleave_by_top_level_dir(path, files);
+            }
+        }
+
+        if let Some(only_paths) = &self.options.only_paths {
+            files.retain(|file| only_paths.contains(&absolute_path(self.fs, file)));
+        }
+        if !self.options.path_contains.is_empty() || !self.options.path_regex.is_empty() {
+            files.retain(|file| self.matches_path_filters(path, file));
+        }
+        if self.options.entry_points {
+            files = entry_points(self.fs, files);
+        }
+        if let Some(fraction) = self.options.sample_fraction {
+            files.retain(|file| in_sample(file, self.options.seed, fraction));
+        }
+        let options = self.options;
+        if let (Some(scorer), Some(_)) = (&options.scorer, options.max_bytes) {
+            files = self.by_score(files, scorer)?;
+        } else if self.options.max_bytes.is_some() && self.options.budget_order == BudgetOrder::Smallest {
+            files.sort_by_cached_key(|file| self.fs.size(file).unwrap_or(0));
+        }
+        for file in &files {
+            let tail = self.tail_patterns.iter().find(|(pattern, _)| matches_include_pattern(pattern, path, file));
+            if let Some((_, lines)) = tail {
+                self.tail_lines.insert(file.clone(), *lines);
+            }
+            if self.strip_comments_patterns.iter().any(|pattern| matches_include_pattern(pattern, path, file)) {
+                self.strip_comments.insert(file.clone());
+            }
+        }
+
+        self.record(Phase::Walk, started);
+        Ok(files)
+    }
+
+    /// The files within `min_file_size` and `max_file_size`, noting each
+    /// one left out. With `show_skipped` those stay in place, marked skipped.
+    fn filter_by_size(&mut self, files: Vec<PathBuf>) -> io::Result<Vec<PathBuf>> {
+        let mut kept = Vec::new();
+        for file in files {
+            let size = match self.fs.size(&file) {
+                Ok(size) if self.fs.is_file(&file) && !self.skipped.contains_key(&file) => size,
+                _ => {
+                    kept.push(file);
+                    continue;
+                }
+            };
+            let reason = match (self.options.min_file_size, self.options.max_file_size) {
+                (Some(min), _) if size < min => format!("{}, under --min-file-size", stats::format_size(size)),
+                (_, Some(max)) if size > max => format!("{}, over --max-file-size", stats::format_size(size)),
+                _ => {
+                    kept.push(file);
+                    continue;
+                }
+            };
+            self.warn(format!("Skipping file {} for its size: {}", self.renderer.render(&file), reason))?;
+            if self.options.show_skipped {
+                self.skipped.insert(file.clone(), reason);
+                kept.push(file);
+            }
+        }
+        Ok(kept)
+    }
+
+    /// `files` from the highest score to the lowest. Files that can't be
+    /// read go last, as do those scoring NaN, with a warning.
+    fn by_score(&mut self, files: Vec<PathBuf>, scorer: &Scorer) -> io::Result<Vec<PathBuf>> {
+        let mut scored: Vec<(f64, PathBuf)> = Vec::new();
+        for path in files {
+            let score = match self.fs.read(&path) {
+                Ok(content) => {
+                    let content = String::from_utf8_lossy(&content).into_owned();
+                    scorer(&Document { path: path.clone(), content })
+                }
+                Err(_) => f64::NEG_INFINITY,
+            };
+            if score.is_nan() {
+                self.warn(format!("Warning: no score for {}, ranking it last", self.renderer.render(&path)))?;
+            }
+            scored.push((if score.is_nan() { f64::NEG_INFINITY } else { score }, path));
+        }
+        scored.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        Ok(scored.into_iter().map(|(_, path)| path).collect())
+    }
+
+    /// Whether `file`, found by walking the input `root`, matches one of the
+    /// include globs.
+    fn matches_include(&self, root: &Path, file: &Path) -> bool {
+        self.include_patterns.iter().any(|pattern| matches_include_pattern(pattern, root, file))
+    }
+
+    /// The files found under `root` in its dominant languages, or
+    /// configuration and documentation, noting the languages on `err`.
+    fn auto_select(&mut self, root: &Path, files: Vec<PathBuf>) -> io::Result<Vec<PathBuf>> {
+        let mix = languages::language_mix(&files);
+        let dominant = languages::dominant(&mix);
+        if !mix.is_empty() {
+            let listed: Vec<String> = mix
+                .iter()
+                .map(|(language, count)| {
+                    let mark = if dominant.contains(language) { "" } else { ", left out" };
+                    format!("{} ({} file{}{})", language, count, if *count == 1 { "" } else { "s" }, mark)
+                })
+                .collect();
+            writeln!(self.err, "Languages in {}: {}", self.renderer.render(root), listed.join(", "))?;
+        }
+        Ok(files.into_iter().filter(|file| languages::is_selected(file, &dominant)).collect())
+    }
+
+    /// Whether the sniffed content type of `file` is one of `mime_types`.
+    /// Unreadable files have no type and never match.
+    fn matches_mime(&self, file: &Path) -> bool {
+        let Ok(content) = self.fs.read(file) else {
+            return false;
+        };
+        let mime = mime::sniff(file, &content);
+        self.options.mime_types.iter().any(|pattern| mime::matches(pattern, mime))
+    }
+
+    /// Whether `file`, found under the input `root`, passes --path-contains
+    /// and --path-regex. A file given directly is matched as given.
+    fn matches_path_filters(&self, root: &Path, file: &Path) -> bool {
+        let options = self.options;
+        let text = relative_text(root, file);
+
+        (options.path_contains.is_empty() || options.path_contains.iter().any(|needle| text.contains(needle.as_str())))
+            && (options.path_regex.is_empty() || options.path_regex.iter().any(|regex| regex.is_match(&text)))
+    }
+
+    fn process_path(&mut self, path: &Path) -> io::Result<()> {
+        let files = self.gather(path)?;
+        self.files_found += files.len();
+        self.file_map.extend(files.iter().cloned());
+        self.number_files(&files);
+
+        for file in files {
+            if !self.emit_entry(&file)? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Gather every input before writing anything, then output the files
+    /// sorted by path, or in the `sort` order, whatever order the inputs
+    /// came in.
+    fn process_sorted(&mut self, paths: &[&Path]) -> io::Result<()> {
+        let mut files = Vec::new();
+        for &path in paths {
+            if self.should_stop() {
+                self.walk_incomplete = true;
+                break;
+            }
+
+            self.enter_path(path)?;
+            for file in self.gather(path)? {
+                files.push((file, path.to_path_buf()));
+            }
+        }
+
+        files.sort();
+        files.dedup_by(|a, b| a.0 == b.0);
+        match self.options.sort {
+            None | Some(SortOrder::Path) => {}
+            Some(SortOrder::Name) => files.sort_by(|a, b| a.0.file_name().cmp(&b.0.file_name())),
+            Some(SortOrder::Extension) => files.sort_by(|a, b| a.0.extension().cmp(&b.0.extension())),
+            Some(SortOrder::Size) => files.sort_by_cached_key(|(file, _)| self.fs.size(file).unwrap_or(0)),
+            Some(SortOrder::Mtime) => files.sort_by_cached_key(|(file, _)| self.fs.modified(file)),
+        }
+        if self.options.reverse {
+            files.reverse();
+        }
+        self.files_found += files.len();
+        self.file_map.extend(files.iter().map(|(file, _)| file.clone()));
+        self.number_files(files.iter().map(|(file, _)| file));
+
+        for (file, input) in files {
+            self.current_input = Some(input);
+            let keep_going = self.emit_entry(&file)?;
+            self.current_input = None;
+            if !keep_going {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Walk every variant root, then output each relative path from all the
+    /// variants that have it before moving on to the next path.
+    fn process_variants(&mut self) -> io::Result<()> {
+        let options = self.options;
+        let mut listings = Vec::new();
+        let mut relative_paths = BTreeSet::new();
+
+        for (name, root) in &options.variants {
+            if !self.fs.exists(root) {
+                self.warn(format!("Path does not exist: {}", self.renderer.render(root)))?;
+                continue;
+            }
+
+            self.enter_path(root)?;
+            let files = self.gather(root)?;
+            self.files_found += files.len();
+            self.file_map.extend(files.iter().cloned());
+
+            // A file root is paired up with the other variants by its name
+            let base = if self.fs.is_file(root) { root.parent().unwrap_or(root) } else { root.as_path() };
+            let relative: Vec<PathBuf> = files
+                .iter()
+                .map(|file| file.strip_prefix(base).unwrap_or(file).to_path_buf())
+                .collect();
+            relative_paths.extend(relative.iter().cloned());
+            listings.push((name, files, relative));
+        }
+
+        // Numbered in the order they are written
+        for relative_path in &relative_paths {
+            for (_, files, relative) in &listings {
+                self.number_files(relative.iter().position(|r| r == relative_path).map(|i| &files[i]));
+            }
+        }
+
+        for relative_path in relative_paths {
+            for (name, files, relative) in &listings {
+                let Some(i) = relative.iter().position(|r| *r == relative_path) else {
+                    continue;
+                };
+
+                self.variant = Some(name.to_string());
+                let keep_going = self.emit_entry(&files[i])?;
+                self.variant = None;
+                if !keep_going {
+                    return Ok(());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Give each of `files` not yet numbered the next index.
+    fn number_files<'p>(&mut self, files: impl IntoIterator<Item = &'p PathBuf>) {
+        for file in files {
+            self.index_of(file);
+        }
+    }
+
+    /// The index of `path`, numbering it now if it was not found by the
+    /// walk, as test files pulled in by `with_tests` may not be.
+    fn index_of(&mut self, path: &Path) -> usize {
+        let next = self.first_index + self.indices.len();
+        *self.indices.entry(path.to_path_buf()).or_insert(next)
+    }
+
+    /// Output one collected file or empty directory, returning false once
+    /// the collection has to stop.
+    fn emit_entry(&mut self, file: &Path) -> io::Result<bool> {
+        if self.should_stop() {
+            return Ok(false);
+        }
+
+        self.maybe_write_file_map()?;
+        self.files_emitted += 1;
+        if let Some(reason) = self.skipped.get(file).cloned() {
+            self.write_skipped(file, &reason)?;
+        } else if let Some(target) = self.symlinks.get(file).cloned() {
+            self.write_symlink(file, &target)?;
+        } else if self.fs.is_dir(file) {
+            self.print_path(file, EMPTY_DIR_STUB, false)?;
+        } else {
+            self.emit_file(file, self.options.with_tests)?;
+        }
+
+        (self.progress)(Progress {
+            path: file,
+            files_done: self.files_emitted,
+            files_found: self.files_found,
+        });
+
+        Ok(true)
+    }
+
+    /// Walk `dir` unless it leads back to a directory already being walked,
+    /// as a symlink or NTFS junction to an ancestor does.
+    fn walk_directory(&mut self, dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+        let canonical = self.fs.canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+        if self.walk_stack.contains(&canonical) {
+            self.warn(format!(
+                "Warning: not following {}, which loops back to {}",
+                self.renderer.render(dir),
+                self.renderer.render(&canonical)
+            ))?;
+            return Ok(());
+        }
+        if self.options.follow_symlinks && !self.visited.insert(canonical.clone()) {
+            return self.warn_visited(dir, &canonical);
+        }
+        if self.walk_stack.len() >= MAX_WALK_DEPTH {
+            return self.warn(format!(
+                "Warning: not walking into {}, which is more than {} directories deep",
+                self.renderer.render(dir),
+                MAX_WALK_DEPTH
+            ));
+        }
+
+        // Rules read while walking `dir` stop applying once we leave it
+        self.walk_stack.push(canonical);
+        let rules_before = self.gitignore_rules.len();
+        let result = self.walk_entries(dir, files);
+        self.gitignore_rules.truncate(rules_before);
+        self.walk_stack.pop();
+        result
+    }
+
+    fn walk_entries(&mut self, dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+        let options = self.options;
+
+        if !options.ignore_gitignore {
+            let rules = self.read_gitignore(dir)?;
+            self.gitignore_rules.extend(rules);
+        }
+
+        let (mut entries, excluded): (Vec<PathBuf>, Vec<PathBuf>) =
+            self.fs.read_dir(dir)?.into_iter().partition(|path| self.include_entry(path));
+
+        // A directory with nothing left after filtering is listed as a stub
+        if entries.is_empty() && options.include_empty_dirs && options.symbol.is_none() && !options.todos {
+            files.push(dir.to_path_buf());
+            return Ok(());
+        }
+
+        // Ignored entries stay in their place to be shown as skipped
+        if options.show_skipped {
+            for path in excluded {
+                if let Some(reason) = self.ignore_reason(&path) {
+                    self.skipped.insert(path.clone(), reason);
+                    entries.push(path);
+                }
+            }
+        }
+
+        // Sort entries by name
+        entries.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+
+        for path in entries {
+            if self.should_stop() {
+                self.walk_incomplete = true;
+                return Ok(());
+            }
+
+            if self.skipped.contains_key(&path) {
+                files.push(path);
+                continue;
+            }
+
+            // Reading a placeholder would download it
+            if !options.hydrate_placeholders && self.fs.is_placeholder(&path) {
+                self.placeholders += 1;
+                self.count_skip(SKIP_PLACEHOLDER);
+                continue;
+            }
+
+            // Followed links are walked like what they lead to; a dangling one is still listed
+            let link = self.fs.read_link(&path).filter(|_| !options.follow_symlinks || !self.fs.exists(&path));
+            if self.fs.is_dir(&path) && link.is_none() {
+                self.walk_directory(&path, files)?;
+            } else if self.fs.is_file(&path) || link.is_some() {
+                // Check extensions; a link to a directory has none to check
+                if !self.fs.is_dir(&path) && !self.has_listed_extension(&path) {
+                    continue;
+                }
+
+                if let Some(target) = link {
+                    self.symlinks.insert(path.clone(), target);
+                } else if options.follow_symlinks {
+                    let canonical = self.fs.canonicalize(&path).unwrap_or_else(|_| path.clone());
+                    if !self.visited.insert(canonical.clone()) {
+                        self.warn_visited(&path, &canonical)?;
+                        continue;
+                    }
+                }
+                files.push(path);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn include_entry(&self, path: &Path) -> bool {
+        self.check_entry(path, None)
+    }
+
+    /// Why `path` is left out, when it is by a .gitignore or --ignore rule.
+    /// Built-in exclusions such as hidden names are not worth a mention.
+    fn ignore_reason(&self, path: &Path) -> Option<String> {
+        let mut trace = Vec::new();
+        self.check_entry(path, Some(&mut trace));
+        let check = trace.iter().find(|check| check.excludes)?;
+        match check.source {
+            RuleSource::Default => None,
+            _ => Some(format!("ignored by {} ({})", check.source.describe(&self.renderer), check.rule)),
+        }
+    }
+
+    /// Run the filter rules over `path`. Without a trace this stops at the
+    /// first rule that excludes it; with one, every active rule is consulted
+    /// and recorded along with where it came from.
+    fn check_entry(&self, path: &Path, mut trace: Option<&mut Vec<RuleCheck>>) -> bool {
+        let options = self.options;
+        let name = path.file_name().unwrap_or_default();
+        let basename = names::name_bytes(name);
+        let is_dir = self.fs.is_dir(path);
+
+        let tracing = trace.is_some();
+        let mut included = true;
+        // Returns whether to stop, which is only ever early without a trace
+        let mut consult = |check: RuleCheck| -> bool {
+            let excludes = check.excludes;
+            included &= !excludes;
+            match &mut trace {
+                Some(trace) => {
+                    trace.push(check);
+                    false
+                }
+                None => excludes,
+            }
+        };
+
+        // Check if hidden
+        if !options.include_hidden
+            && consult(RuleCheck::new(RuleSource::Default, "hidden names starting with .", basename.starts_with(b".")))
+        {
+            return false;
+        }
+
+        // Check this tool's own artifacts
+        if consult(RuleCheck::new(RuleSource::Default, TOOL_DIR, name == TOOL_DIR && is_dir)) {
+            return false;
+        }
+        if !options.excluded_paths.is_empty()
+            && consult(RuleCheck::new(
+                RuleSource::Default,
+                "output files",
+                options.excluded_paths.contains(&absolute_path(self.fs, path)),
+            ))
+        {
+            return false;
+        }
+
+        // Check OS junk files
+        if !options.include_junk
+            && consult(RuleCheck::new(
+                RuleSource::Default,
+                "OS junk and editor swap files",
+                self.fs.is_file(path) && is_junk(name),
+            ))
+        {
+            return false;
+        }
+
+        // Check gitignore rules; the last one matching decides
+        if !options.ignore_gitignore {
+            let rules = &self.gitignore_rules;
+            let deciding = rules.iter().rposition(|rule| rule.matches(path, is_dir));
+            let ignored = deciding.is_some_and(|i| !rules[i].negated);
+            for (i, rule) in rules.iter().enumerate() {
+                let decides = deciding == Some(i);
+                if !decides && !tracing {
+                    continue;
+                }
+                let check = RuleCheck {
+                    source: RuleSource::Gitignore { file: rule.file.clone(), line: rule.line },
+                    rule: rule.text.clone(),
+                    matched: decides || rule.matches(path, is_dir),
+                    excludes: decides && ignored,
+                };
+                if consult(check) {
+                    return false;
+                }
+            }
+        }
+
+        // Directories are walked for the checked out files they may hold
+        if let Some(sparse) = self.sparse_checkouts.iter().rev().find(|sparse| path.starts_with(&sparse.base)) {
+            if !is_dir {
+                let rule = sparse.deciding_rule(path, false);
+                let text = rule.map_or("no pattern matches", |rule| rule.text.as_str());
+                if consult(RuleCheck::new(RuleSource::SparseCheckout, text, !rule.is_some_and(|rule| !rule.negated))) {
+                    return false;
+                }
+            }
+        }
+
+        // Check ignore patterns
+        if !is_dir || !options.ignore_files_only {
+            for (pattern, text) in self.ignore_patterns.iter().zip(&options.ignore_patterns) {
+                if consult(RuleCheck::new(RuleSource::IgnoreOption, text, pattern.matches(path, is_dir))) {
+                    return false;
+                }
+            }
+        }
+
+        included
+    }
+
+    fn read_gitignore(&self, path: &Path) -> io::Result<Vec<GitignoreRule>> {
+        self.read_rules(&path.join(".gitignore"), path)
+    }
+
+    fn read_rules(&self, file: &Path, base: &Path) -> io::Result<Vec<GitignoreRule>> {
+        if !self.fs.is_file(file) {
+            return Ok(Vec::new());
+        }
+
+        let content = self.fs.read(file)?;
+        let rules = String::from_utf8_lossy(&content)
+            .lines()
+            .enumerate()
+            .filter_map(|(i, line)| GitignoreRule::parse(line, base, file, i + 1))
+            .collect();
+
+        Ok(rules)
+    }
+
+    /// The rules git applies to the whole repository holding `dir`, as seen
+    /// from `dir`: the global excludes file, then `.git/info/exclude`, each
+    /// outranked by the next. Nothing applies outside a repository, and
+    /// rules already loaded for `dir` are not read again.
+    fn repository_rules(&self, dir: &Path) -> io::Result<Vec<GitignoreRule>> {
+        let Some((root, prefix)) = self.repository(dir) else {
+            return Ok(Vec::new());
+        };
+
+        let files = self.options.global_excludes_file.iter().cloned().chain([root.join(".git/info/exclude")]);
+        let mut rules = Vec::new();
+        for file in files {
+            if self.gitignore_rules.iter().any(|rule| rule.file == file && rule.base == dir) {
+                continue;
+            }
+            for mut rule in self.read_rules(&file, dir)? {
+                rule.prefix = prefix.clone();
+                rules.push(rule);
+            }
+        }
+        Ok(rules)
+    }
+
+    /// Read the sparse checkout patterns of the repository holding `dir`,
+    /// when it has sparse checkout turned on. `git sparse-checkout` sets
+    /// that in `.git/config.worktree` when the repository has per-worktree
+    /// config, and in `.git/config` otherwise.
+    fn read_sparse_checkout(&mut self, dir: &Path) -> io::Result<()> {
+        if self.sparse_checkouts.iter().any(|sparse| sparse.base == dir) {
+            return Ok(());
+        }
+        let Some((root, prefix)) = self.repository(dir) else {
+            return Ok(());
+        };
+        let file = root.join(".git/info/sparse-checkout");
+        if !self.fs.is_file(&file) {
+            return Ok(());
+        }
+        let mut enabled = None;
+        for config in [root.join(".git/config.worktree"), root.join(".git/config")] {
+            if enabled.is_none() && self.fs.is_file(&config) {
+                enabled = sparse_checkout_setting(&String::from_utf8_lossy(&self.fs.read(&config)?));
+            }
+        }
+        if enabled == Some(true) {
+            let rules = self.read_rules(&file, dir)?;
+            self.sparse_checkouts.push(SparseCheckout { base: dir.to_path_buf(), prefix, rules });
+        }
+        Ok(())
+    }
+
+    /// The top of the git repository holding `dir` and where `dir` sits
+    /// below it.
+    fn repository(&self, dir: &Path) -> Option<(PathBuf, PathBuf)> {
+        let lookup = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        let canonical = self.fs.canonicalize(lookup).ok()?;
+        let root = canonical.ancestors().find(|ancestor| self.fs.exists(&ancestor.join(".git")))?;
+        let prefix = canonical.strip_prefix(root).unwrap_or(Path::new(""));
+        Some((root.to_path_buf(), prefix.to_path_buf()))
+    }
+
+    /// Write every rule consulted for `path` as if it were reached by walking
+    /// down from the top of the path, and the resulting decision.
+    fn explain(&mut self, path: &Path) -> io::Result<()> {
+        let options = self.options;
+        let dirs: Vec<&Path> = path.ancestors().skip(1).collect();
+
+        let top = dirs.last().copied().unwrap_or(Path::new(""));
+        if options.respect_sparse {
+            self.read_sparse_checkout(top)?;
+        }
+        if !options.ignore_gitignore {
+            let rules = self.repository_rules(top)?;
+            self.gitignore_rules.extend(rules);
+        }
+
+        // Rules from each directory apply below it, once the directory itself passed
+        let mut excluded_dir = None;
+        for dir in dirs.into_iter().rev() {
+            let is_walked = dir.file_name().is_some();
+            if is_walked && excluded_dir.is_none() {
+                let mut trace = Vec::new();
+                if !self.check_entry(dir, Some(&mut trace)) {
+                    excluded_dir = Some((dir.to_path_buf(), trace));
+                }
+            }
+            if !options.ignore_gitignore {
+                let rules = self.read_gitignore(dir)?;
+                self.gitignore_rules.extend(rules);
+            }
+        }
+
+        let mut trace = Vec::new();
+        let mut included = self.check_entry(path, Some(&mut trace));
+        if self.fs.is_file(path) && !options.extensions.is_empty() {
+            let ext = names::name_bytes(path.extension().unwrap_or_default());
+            let matched = !options.extensions.iter().any(|e| e.as_bytes() == &ext[..]);
+            trace.push(RuleCheck::new(RuleSource::ExtensionOption, &options.extensions.join(", "), matched));
+            included &= !matched;
+        }
+
+        writeln!(self.out, "Rules consulted for {}:", self.renderer.render(path))?;
+        for check in &trace {
+            let status = if check.matched { "match   " } else { "no match" };
+            writeln!(self.out, "  {}  {}: {}", status, check.source.describe(&self.renderer), check.rule)?;
+        }
+
+        let first_match = |trace: &[RuleCheck]| {
+            trace
+                .iter()
+                .find(|check| check.excludes)
+                .map(|check| format!("{} ({})", check.source.describe(&self.renderer), check.rule))
+                .unwrap_or_default()
+        };
+        match excluded_dir {
+            Some((dir, dir_trace)) => writeln!(
+                self.out,
+                "Decision: excluded, because its directory {} is excluded by {}",
+                self.renderer.render(&dir),
+                first_match(&dir_trace)
+            ),
+            None if !included => writeln!(self.out, "Decision: excluded by {}", first_match(&trace)),
+            None => writeln!(self.out, "Decision: included"),
+        }
+    }
+
+    /// How far up test counterparts of `path` are looked for: the directory
+    /// being walked, or for a file given by itself the repository holding it,
+    /// else the current directory when the path is relative, else its parent.
+    fn counterpart_root(&self, path: &Path) -> PathBuf {
+        let input = self.current_input.as_deref().unwrap_or(path);
+        if self.fs.is_dir(input) {
+            return input.to_path_buf();
+        }
+        let dir = input.parent().unwrap_or(Path::new(""));
+        if let Some((_, prefix)) = self.repository(dir) {
+            if let Some(root) = dir.ancestors().nth(prefix.components().count()) {
+                return root.to_path_buf();
+            }
+        }
+        if input.is_relative() { PathBuf::new() } else { dir.to_path_buf() }
+    }
+
+    /// Whether `path`, found below `root` without walking there, passes the
+    /// filters the walk would have applied on its way down from `root`.
+    fn admits(&mut self, root: &Path, path: &Path) -> io::Result<bool> {
+        let rules_before = self.gitignore_rules.len();
+        let dirs: Vec<&Path> = path.ancestors().skip(1).take_while(|dir| dir.starts_with(root)).collect();
+
+        let mut admitted = true;
+        for dir in dirs.into_iter().rev() {
+            if dir != root && !self.include_entry(dir) {
+                admitted = false;
+                break;
+            }
+            if !self.options.ignore_gitignore {
+                let rules = self.read_gitignore(dir)?;
+                self.gitignore_rules.extend(rules);
+            }
+        }
+        admitted = admitted && self.include_entry(path) && self.has_listed_extension(path);
+
+        self.gitignore_rules.truncate(rules_before);
+        Ok(admitted)
+    }
+
+    /// Whether `path` has one of the `extensions` asked for, if any were.
+    fn has_listed_extension(&self, path: &Path) -> bool {
+        let extensions = &self.options.extensions;
+        let ext = names::name_bytes(path.extension().unwrap_or_default());
+        extensions.is_empty() || extensions.iter().any(|e| e.as_bytes() == &ext[..])
+    }
+
+    /// Read and print a single file, followed by its test counterparts when
+    /// `with_tests` is set. Files already printed in this run are skipped so a
+    /// test pulled in early is not repeated when the walk reaches it.
+    fn emit_file(&mut self, path: &Path, with_tests: bool) -> io::Result<()> {
+        let options = self.options;
+
+        if with_tests && !self.seen.insert(path.to_path_buf()) {
+            return Ok(());
+        }
+
+        let started = Instant::now();
+        let read = self.read_text(path);
+        let read_time = self.record(Phase::Read, started);
+        let mut content = match read {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData && options.binary == BinaryFiles::Summary => {
+                let summary = binary_summary(path, &self.fs.read(path)?, options.binary_preview);
+                return self.print_path(path, &summary, false);
+            }
+            Err(e) if e.kind() == io::ErrorKind::InvalidData && options.binary == BinaryFiles::Error => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} is not UTF-8 text", self.renderer.render(path)),
+                ));
+            }
+            Err(e) => {
+                self.count_skip(if e.kind() == io::ErrorKind::InvalidData { SKIP_BINARY } else { SKIP_UNREADABLE });
+                self.warn_skipped(path, &e)?;
+                if options.show_skipped {
+                    let reason = match e.kind() {
+                        io::ErrorKind::InvalidData => "not UTF-8 text".to_string(),
+                        _ => format!("could not be read: {}", e),
+                    };
+                    self.write_skipped(path, &reason)?;
+                }
+                return Ok(());
+            }
+        };
+
+        let started = Instant::now();
+        if options.nbconvert && path.extension().is_some_and(|extension| extension == "ipynb") {
+            if let Some(converted) = notebook::convert(&content, options.nb_strip_output) {
+                content = converted;
+            }
+        }
+        // Files are picked by what they hold before anything is taken out of them
+        let unwanted = options.symbol.as_ref().is_some_and(|symbol| !symbol.matches(self.fs, path, &content))
+            || (options.todos && !todo_marker().is_match(&content));
+        if unwanted {
+            self.record(Phase::Transform, started);
+            return Ok(());
+        }
+        if options.redact_secrets {
+            if let Some((redacted, counts)) = secrets::redact(&content) {
+                content = redacted;
+                let counts: Vec<String> = counts.iter().map(|(kind, count)| format!("{} {}", count, kind)).collect();
+                self.warn(format!("Redacted secrets in {}: {}", self.renderer.render(path), counts.join(", ")))?;
+            }
+        }
+        if options.strip_comments || self.strip_comments.contains(path) {
+            if let Some(stripped) = comments::strip(path, &content) {
+                content = stripped;
+            }
+        }
+        if options.strip_blank_lines {
+            content = strip_blank_lines(&content);
+        }
+        if options.expand_tabs {
+            let width = editorconfig::tab_width(self.fs, path).unwrap_or(editorconfig::DEFAULT_TAB_WIDTH);
+            content = editorconfig::expand_tabs(&content, width);
+        }
+        if !options.annotations.is_empty() {
+            content = annotate(&content, &options.annotations);
+        }
+
+        let lines = self.line_range(path);
+        let todo_context = options.todo_context.filter(|_| options.todos);
+        let sliced = match (&options.symbol, lines, todo_context) {
+            (_, Some((start, end)), _) => Some(slice_lines(&content, start, end)),
+            (Some(SymbolFilter { regex, context: Some(context), .. }), None, _) => {
+                Some(slice_around_matches(&content, regex, *context))
+            }
+            (_, None, Some(context)) => Some(slice_around_matches(&content, todo_marker(), context)),
+            _ => None,
+        };
+
+        // A whole component is written block by block, each like a slice
+        let blocks = if options.split_sfc && sliced.is_none() { sfc::split(path, &content) } else { None };
+        let pieces: Vec<(Option<Section>, Option<Vec<KeptLine>>)> = match blocks {
+            Some(blocks) => blocks
+                .into_iter()
+                .map(|block| {
+                    let kept = block.lines.into_iter().map(Some).collect();
+                    (Some((block.name, block.language)), Some(kept))
+                })
+                .collect(),
+            None => vec![(None, sliced)],
+        };
+
+        let mut outputs = Vec::new();
+        for (section, sliced) in pieces {
+            // Sliced output carries the original line numbers itself
+            let cxml_lines = options.cxml_lines && options.format == OutputFormat::Cxml;
+            let (output, line_numbers) = match sliced {
+                Some(kept) if cxml_lines => (Cow::Owned(line_elements(&kept, options.cxml_strict)), false),
+                Some(kept) => (Cow::Owned(join_kept(&kept, content.lines().count(), options.line_numbers)), false),
+                None if cxml_lines => {
+                    let kept: Vec<KeptLine> =
+                        content.lines().enumerate().map(|(i, line)| Some((i + 1, line))).collect();
+                    (Cow::Owned(line_elements(&kept, options.cxml_strict)), false)
+                }
+                None => (Cow::Borrowed(content.as_str()), options.line_numbers),
+            };
+            // Numbered before cutting, so a tail keeps the numbers it has in the file
+            let truncate = self.tail_lines.get(path).map(|&lines| Truncate::Tail(lines)).or(options.truncate);
+            let (output, line_numbers) = match truncate {
+                Some(truncate) if output.lines().count() > truncate.lines() => {
+                    let numbered = if line_numbers { Cow::Owned(add_line_numbers(&output)) } else { output };
+                    (Cow::Owned(truncate_lines(&numbered, truncate)), false)
+                }
+                _ => (output, line_numbers),
+            };
+            outputs.push((section, output, line_numbers));
+        }
+        let transform_time = self.record(Phase::Transform, started);
+
+        let started = Instant::now();
+        let bytes: usize = outputs.iter().map(|(_, output, _)| output.len()).sum();
+        let tokens = stats::estimate_tokens(bytes);
+        self.record(Phase::Tokenize, started);
+
+        let started = Instant::now();
+        for (section, output, line_numbers) in outputs {
+            self.section = section;
+            let printed = self.print_path(path, &output, line_numbers);
+            self.section = None;
+            printed?;
+        }
+        let write_time = self.record(Phase::Write, started);
+
+        let label = self.profile.is_some().then(|| header_path(&self.renderer, &self.label(path)));
+        if let (Some(profile), Some(label)) = (&mut self.profile, label) {
+            profile.files.push(FileTiming {
+                path: label,
+                bytes: bytes as u64,
+                tokens: tokens as u64,
+                read: read_time,
+                transform: transform_time,
+                write: write_time,
+            });
+        }
+
+        if options.mention_excluded {
+            self.included.insert(imports::normalize(path));
+            for file in imports::imported_files(self.fs, path, &content) {
+                if !self.imported.contains(&file) {
+                    self.imported.push(file);
+                }
+            }
+        }
+
+        if with_tests {
+            let root = self.counterpart_root(path);
+            for test_path in test_counterparts(self.fs, &root, path) {
+                if self.seen.contains(&test_path) || !self.admits(&root, &test_path)? {
+                    continue;
+                }
+                self.seen.insert(test_path.clone());
+                self.emit_file(&test_path, false)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The one-line stand-in for a file that was left out. JSON output only
+    /// holds documents, so it has none.
+    fn write_skipped(&mut self, path: &Path, reason: &str) -> io::Result<()> {
+        let label = self.label(path);
+        match self.options.format {
+            OutputFormat::Cxml => {
+                let source = escape_attribute(&self.renderer.render(&label));
+                self.write_output(&format!("<skipped source=\"{}\" reason=\"{}\"/>", source, escape_attribute(reason)))
+            }
+            OutputFormat::Default | OutputFormat::Markdown => {
+                let line = format!("{} (skipped: {})", header_path(&self.renderer, &label), reason);
+                self.write_output(&line)
+            }
+            OutputFormat::Json => Ok(()),
+        }
+    }
+
+    /// The one-line entry for a link that was not followed. JSON output
+    /// only holds documents, so has none.
+    fn write_symlink(&mut self, path: &Path, target: &Path) -> io::Result<()> {
+        let label = self.label(path);
+        match self.options.format {
+            OutputFormat::Cxml => {
+                let source = escape_attribute(&self.renderer.render(&label));
+                let target = escape_attribute(&self.renderer.render(target));
+                self.write_output(&format!("<symlink source=\"{}\" target=\"{}\"/>", source, target))
+            }
+            OutputFormat::Default | OutputFormat::Markdown => {
+                let line = format!("{} -> {}", header_path(&self.renderer, &label), self.renderer.render(target));
+                self.write_output(&line)
+            }
+            OutputFormat::Json => Ok(()),
+        }
+    }
+
+    fn report_over_budget(&mut self) -> io::Result<()> {
+        let bytes: usize = self.over_budget.iter().map(|(_, bytes)| bytes).sum();
+        let mut report = format!(
+            "Warning: left out {} file{} ({}) to stay within the budget of {}:",
+            self.over_budget.len(),
+            if self.over_budget.len() == 1 { "" } else { "s" },
+            stats::format_size(bytes as u64),
+            stats::format_size(self.options.max_bytes.unwrap_or_default() as u64)
+        );
+        for (path, bytes) in &self.over_budget {
+            let path = header_path(&self.renderer, &self.label(path));
+            report.push_str(&format!("\n  {} ({})", path, stats::format_size(*bytes as u64)));
+        }
+        self.warn(report)
+    }
+
+    /// The content of `path` as UTF-8, decompressed with `decompress`,
+    /// reduced to its text with `extract` and transcoded from the encoding
+    /// it is in.
+    fn read_text(&self, path: &Path) -> io::Result<String> {
+        let mut content = self.fs.read(path)?;
+        if self.options.decompress && decompress::is_compressed(path) {
+            content = decompress::decompress(path, &content)?;
+        }
+        if self.options.extract && extract::is_extractable(path) {
+            content = extract::extract(path, &content)?;
+        }
+        encoding::decode(content, self.options.lossy)
+    }
+
+    fn warn_visited(&mut self, path: &Path, canonical: &Path) -> io::Result<()> {
+        self.warn(format!(
+            "Warning: not following {}, which leads to {}, already included",
+            self.renderer.render(path),
+            self.renderer.render(canonical)
+        ))
+    }
+
+    fn warn_skipped(&mut self, path: &Path, e: &io::Error) -> io::Result<()> {
+        if e.kind() == io::ErrorKind::InvalidData {
+            self.warn(format!("Warning: Skipping file {} due to UnicodeDecodeError", self.renderer.render(path)))
+        } else {
+            self.warn(format!("Warning: Skipping file {} due to error: {}", self.renderer.render(path), e))
+        }
+    }
+
+    /// Count the time since `started` towards `phase` with `profile`,
+    /// returning it.
+    fn record(&mut self, phase: Phase, started: Instant) -> Duration {
+        let elapsed = started.elapsed();
+        if let Some(profile) = &mut self.profile {
+            profile.add(phase, elapsed);
+        }
+        elapsed
+    }
+
+    fn count_skip(&mut self, reason: &'static str) {
+        match self.skip_counts.iter_mut().find(|(counted, _)| *counted == reason) {
+            Some((_, count)) => *count += 1,
+            None => self.skip_counts.push((reason, 1)),
+        }
+    }
+
+    /// Write a warning to `err`, keeping it for the diagnostics section
+    /// with `embed_warnings`.
+    fn warn(&mut self, message: String) -> io::Result<()> {
+        writeln!(self.err, "{}", message)?;
+        if self.options.embed_warnings {
+            self.diagnostics.push(message);
+        }
+        Ok(())
+    }
+
+    fn print_path(&mut self, path: &Path, content: &str, line_numbers: bool) -> io::Result<()> {
+        let options = self.options;
+        let Some(max_bytes) = options.max_bytes else {
+            return self.print_document(path, content, line_numbers);
+        };
+
+        // Render the document aside to charge its full size, header and all,
+        // and take it back if that goes past the budget
+        let indexed = self.indices.contains_key(path);
+        let (wrote_document, pending_json, bytes_since_map, file_stats) =
+            (self.wrote_document, self.pending_json.clone(), self.bytes_since_map, self.file_stats.len());
+        self.held = Some(String::new());
+        let result = self.print_document(path, content, line_numbers);
+        let held = self.held.take().unwrap_or_default();
+        result?;
+        let size = match (&self.pending_json, options.template.is_none() && options.format == OutputFormat::Json) {
+            // The document itself is held back until the next one, with a comma
+            (Some(document), true) => document.len() + 2,
+            _ => held.len(),
+        };
+        if self.budget_used + size > max_bytes {
+            if !indexed {
+                self.indices.remove(path);
+            }
+            self.wrote_document = wrote_document;
+            self.pending_json = pending_json;
+            self.bytes_since_map = bytes_since_map;
+            self.file_stats.truncate(file_stats);
+            self.over_budget.push((path.to_path_buf(), size));
+            self.count_skip(SKIP_OVER_BUDGET);
+            if options.show_skipped {
+                self.write_skipped(path, "over the size budget")?;
+            }
+            return Ok(());
+        }
+        self.budget_used += size;
+        self.out.write_all(held.as_bytes())
+    }
+
+    fn print_document(&mut self, path: &Path, content: &str, line_numbers: bool) -> io::Result<()> {
+        let options = self.options;
+        if let Some(separator) = &options.doc_separator {
+            if self.wrote_document && options.format.allows_separator() {
+                self.write_output(separator)?;
+            }
+        }
+        self.wrote_document = true;
+        self.block_hash = options.cache_blocks.then(|| content_hash(content.as_bytes()));
+        if options.stats.is_some() {
+            let mut label = header_path(&self.renderer, &self.label(path));
+            if let Some((name, _)) = &self.section {
+                label.push_str(&format!(" ({})", name));
+            }
+            self.file_stats.push(FileStats {
+                path: label,
+                bytes: content.len() as u64,
+                lines: content.lines().count() as u64,
+            });
+        }
+
+        if let Some(template) = &options.template {
+            return self.print_with_template(template, path, content, line_numbers);
+        }
+        let result = match self.options.format {
+            OutputFormat::Cxml => self.print_as_xml(path, content, line_numbers),
+            OutputFormat::Markdown => self.print_as_markdown(path, content, line_numbers),
+            OutputFormat::Default => self.print_default(path, content, line_numbers),
+            OutputFormat::Json => self.print_as_json(path, content, line_numbers),
+        };
+        self.block_hash = None;
+        result
+    }
+
+    fn print_with_template(
+        &mut self,
+        template: &Template,
+        path: &Path,
+        content: &str,
+        line_numbers: bool,
+    ) -> io::Result<()> {
+        let index = self.index_of(path);
+
+        let content = if line_numbers { add_line_numbers(content) } else { content.to_string() };
+        let label = header_path(&self.renderer, &self.label(path));
+        let extension = path.extension().map(|e| e.to_string_lossy()).unwrap_or_default();
+        let rendered = template.render_file(&FileValues {
+            path: &label,
+            content: content.strip_suffix('\n').unwrap_or(&content),
+            index,
+            extension: &extension,
+            lang: self.language(path).unwrap_or(""),
+        });
+        self.write_output(&rendered)
+    }
+
+    fn print_default(&mut self, path: &Path, content: &str, line_numbers: bool) -> io::Result<()> {
+        self.write_output(&self.header(path))?;
+        self.write_output("---")?;
+
+        let output_content = if line_numbers {
+            add_line_numbers(content)
+        } else {
+            content.to_string()
+        };
+
+        self.write_output(&output_content)?;
+        self.write_output("")?;
+        self.write_output("---")?;
+
+        Ok(())
+    }
+
+    fn print_as_xml(&mut self, path: &Path, content: &str, line_numbers: bool) -> io::Result<()> {
+        let index = self.index_of(path);
+
+        // A block's position is not part of it, so it can be cached on its own
+        let mut attributes = match &self.block_hash {
+            Some(hash) => format!("hash=\"{}\"", hash),
+            None => format!("index=\"{}\"", index),
+        };
+        if let Some(lang) = self.language(path) {
+            attributes.push_str(&format!(" language=\"{}\"", lang));
+        }
+        if let Some((name, _)) = &self.section {
+            attributes.push_str(&format!(" section=\"{}\"", name));
+        }
+        if let Some((start, end)) = self.line_range(path) {
+            attributes.push_str(&format!(" lines=\"{}-{}\"", start, end));
+        }
+        if let Some(variant) = &self.variant {
+            attributes.push_str(&format!(" variant=\"{}\"", escape_attribute(variant)));
+        }
+        for (key, value) in self.attributes(path) {
+            attributes.push_str(&format!(" {}=\"{}\"", key, escape_attribute(value)));
+        }
+        self.write_output(&format!("<document {}>", attributes))?;
+        let label = self.label(path);
+        let mut source = self.renderer.render(&label);
+        if self.options.cxml_strict {
+            source = escape_text(&source);
+        }
+        if names::is_utf8(&label) {
+            self.write_output(&format!("<source>{}</source>", source))?;
+        } else {
+            self.write_output(&format!("<source escaped=\"true\">{}</source>", source))?;
+        }
+        self.write_output("<document_content>")?;
+
+        let output_content = if line_numbers {
+            add_line_numbers(content)
+        } else {
+            content.to_string()
+        };
+        // Line elements escape their own text
+        let output_content = if self.options.cxml_strict && !self.options.cxml_lines {
+            cdata(&output_content)
+        } else {
+            output_content
+        };
+
+        self.write_output(&output_content)?;
+        self.write_output("</document_content>")?;
+        self.write_output("</document>")?;
+
+        Ok(())
+    }
+
+    fn print_as_markdown(&mut self, path: &Path, content: &str, line_numbers: bool) -> io::Result<()> {
+        let lang = self.language(path).unwrap_or("").to_string();
+
+        // Determine number of backticks needed
+        let mut backticks = "```".to_string();
+        while content.contains(&backticks) {
+            backticks.push('`');
+        }
+
+        self.write_output(&self.header(path))?;
+        self.write_output(&format!("{}{}", backticks, lang))?;
+
+        let output_content = if line_numbers {
+            add_line_numbers(content)
+        } else {
+            content.to_string()
+        };
+
+        self.write_output(&output_content)?;
+        self.write_output(&backticks)?;
+
+        Ok(())
+    }
+
+    fn print_as_json(&mut self, path: &Path, content: &str, line_numbers: bool) -> io::Result<()> {
+        let output_content = if line_numbers {
+            add_line_numbers(content)
+        } else {
+            content.to_string()
+        };
+        let mut document = serde_json::json!({
+            "path": self.renderer.render(&self.label(path)),
+            "content": output_content,
+            "size": output_content.len(),
+            "extension": path.extension().map(|extension| extension.to_string_lossy()),
+            "language": self.language(path),
+            "schema_version": crate::SCHEMA_VERSION,
+            "tool_version": env!("CARGO_PKG_VERSION"),
+        });
+        if let Some((name, _)) = &self.section {
+            document["section"] = serde_json::json!(name);
+        }
+
+        if let Some(previous) = self.pending_json.replace(document.to_string()) {
+            self.write_output(&format!("{},", previous))?;
+        }
+
+        Ok(())
+    }
+
+    /// The lines of `path` to output, when not all of them.
+    fn line_range(&self, path: &Path) -> Option<(usize, usize)> {
+        let settings = self.options.path_settings.get(path);
+        self.line_ranges.get(path).copied().or_else(|| settings.and_then(|settings| settings.lines))
+    }
+
+    /// The path line of the default and Markdown formats, followed by the
+    /// line range and variant when there are any.
+    fn header(&self, path: &Path) -> String {
+        let mut header = self.header_label(path);
+        if let Some((start, end)) = self.line_range(path) {
+            header.push_str(&format!(" (lines {}-{})", start, end));
+        }
+        if let Some(variant) = &self.variant {
+            header.push_str(&format!(" (variant: {})", variant));
+        }
+        if let Some((name, _)) = &self.section {
+            header.push_str(&format!(" ({})", name));
+        }
+        if let Some(hash) = &self.block_hash {
+            header.push_str(&format!(" (hash: {})", hash));
+        }
+        let attributes = self.attributes(path);
+        if !attributes.is_empty() {
+            let pairs: Vec<String> = attributes.iter().map(|(key, value)| format!("{}: {}", key, value)).collect();
+            header.push_str(&format!(" ({})", pairs.join(", ")));
+        }
+        header
+    }
+
+    /// The language of `path`, or of the component block being written.
+    fn language(&self, path: &Path) -> Option<&str> {
+        match &self.section {
+            Some((_, language)) => Some(language.as_str()).filter(|language| !language.is_empty()),
+            None => detect_language(path, &self.ext_to_lang),
+        }
+    }
+
+    /// Custom attributes of the file, or else of the input it was found under.
+    fn attributes(&self, path: &Path) -> &'a [(String, String)] {
+        let settings = &self.options.path_settings;
+        [Some(path), self.current_input.as_deref()]
+            .into_iter()
+            .flatten()
+            .filter_map(|key| settings.get(key))
+            .map(|settings| &settings.attributes[..])
+            .find(|attributes| !attributes.is_empty())
+            .unwrap_or_default()
+    }
+
+    /// The label of `path` in document headers and file maps, shortened to
+    /// `max_path_length`.
+    fn header_label(&self, path: &Path) -> String {
+        let label = self.label(path);
+        let Some(max) = self.options.max_path_length else {
+            return header_path(&self.renderer, &label);
+        };
+        let mut header = abbreviate_path(&self.renderer, &label, max);
+        if !names::is_utf8(&label) {
+            header.push_str(" [non-UTF-8 name]");
+        }
+        header
+    }
+
+    /// The path a document is shown under.
+    fn label(&self, path: &Path) -> PathBuf {
+        if let Some(label) = self.options.path_settings.get(path).and_then(|settings| settings.label.as_ref()) {
+            return PathBuf::from(label);
+        }
+        for (dir, name) in &self.options.root_names {
+            if let Ok(relative) = path.strip_prefix(dir) {
+                return name.join(relative);
+            }
+        }
+        let Some(root) = &self.options.label_root else {
+            return path.to_path_buf();
+        };
+        let absolute = imports::normalize(&absolute_path(self.fs, path));
+        match absolute.strip_prefix(root) {
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) => path.to_path_buf(),
+        }
+    }
+
+    fn write_output(&mut self, content: &str) -> io::Result<()> {
+        self.bytes_since_map += content.len() + 1;
+        if let Some(held) = &mut self.held {
+            held.push_str(content);
+            held.push('\n');
+            return Ok(());
+        }
+        writeln!(self.out, "{}", content)
+    }
+}
+
+fn initialize_ext_to_lang() -> HashMap<String, &'static str> {
+    let mut map = HashMap::new();
+    map.insert("py".to_string(), "python");
+    map.insert("c".to_string(), "c");
+    map.insert("cpp".to_string(), "cpp");
+    map.insert("java".to_string(), "java");
+    map.insert("js".to_string(), "javascript");
+    map.insert("ts".to_string(), "typescript");
+    map.insert("html".to_string(), "html");
+    map.insert("css".to_string(), "css");
+    map.insert("xml".to_string(), "xml");
+    map.insert("json".to_string(), "json");
+    map.insert("yaml".to_string(), "yaml");
+    map.insert("yml".to_string(), "yaml");
+    map.insert("sh".to_string(), "bash");
+    map.insert("rb".to_string(), "ruby");
+    map.insert("rs".to_string(), "rust");
+    map.insert("go".to_string(), "go");
+    map.insert("diff".to_string(), "diff");
+    map
+}
+
+fn detect_language(path: &Path, ext_to_lang: &HashMap<String, &'static str>) -> Option<&'static str> {
+    let extension = path.extension()?.to_string_lossy().to_string();
+    ext_to_lang.get(&extension).copied()
+}
+
+/// Absolute form of `path` with symlinks in its directory resolved, usable
+/// for files that have not been created yet.
+pub(crate) fn absolute_path(fs: &dyn FileSystem, path: &Path) -> PathBuf {
+    let parent = match path.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    };
+    match (fs.canonicalize(parent), path.file_name()) {
+        (Ok(parent), Some(name)) => parent.join(name),
+        _ => path.to_path_buf(),
+    }
+}
+
+fn is_junk(name: &OsStr) -> bool {
+    static PATTERNS: OnceLock<Vec<NamePattern>> = OnceLock::new();
+    PATTERNS
+        .get_or_init(|| JUNK_PATTERNS.iter().filter_map(|p| NamePattern::new(p)).collect())
+        .iter()
+        .any(|pattern| pattern.matches(name))
+}
+
+fn add_line_numbers(content: &str) -> String {
+    let lines: Vec<&str> = content.lines().collect();
+    let padding = lines.len().to_string().len();
+
+    lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| format!("{:width$}  {}", i + 1, line, width = padding))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// The path line used by the default and Markdown formats. Names that are
+/// not valid UTF-8 are escaped and labelled so the reader knows why.
+fn header_path(renderer: &PathRenderer, path: &Path) -> String {
+    let rendered = renderer.render(path);
+    if names::is_utf8(path) {
+        rendered
+    } else {
+        format!("{} [non-UTF-8 name]", rendered)
+    }
+}
+
+/// `path` rendered with the directories after its first component replaced
+/// by `…`, keeping as many of the last ones as fit in `max` characters. The
+/// file name is always kept, so the result may still be longer.
+fn abbreviate_path(renderer: &PathRenderer, path: &Path, max: usize) -> String {
+    let rendered = renderer.render(path);
+    let components: Vec<&std::ffi::OsStr> = path.components().map(|component| component.as_os_str()).collect();
+    if rendered.chars().count() <= max || components.len() <= 2 {
+        return rendered;
+    }
+
+    let shortened = |kept: usize| {
+        let mut shortened = PathBuf::from(components[0]);
+        shortened.push("…");
+        shortened.extend(&components[components.len() - kept..]);
+        renderer.render(&shortened)
+    };
+    let mut kept = 1;
+    while kept + 2 < components.len() && shortened(kept + 1).chars().count() <= max {
+        kept += 1;
+    }
+    shortened(kept)
+}
+
+/// Split `sizes` into at most `parts` consecutive, non-empty ranges whose
+/// totals are as close to equal as the order allows.
+fn split_by_size(sizes: &[u64], parts: usize) -> Vec<std::ops::Range<usize>> {
+    let parts = parts.clamp(1, sizes.len().max(1));
+    let total: u64 = sizes.iter().sum();
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    let mut running = 0;
+
+    for part in 1..parts {
+        let target = total * part as u64 / parts as u64;
+        let mut end = start + 1;
+        running += sizes[start];
+        // Take another file while the boundary lands nearer the target, and
+        // leave at least one file for each remaining part
+        while end < sizes.len() - (parts - part) && 2 * running + sizes[end] <= 2 * target {
+            running += sizes[end];
+            end += 1;
+        }
+        ranges.push(start..end);
+        start = end;
+    }
+    ranges.push(start..sizes.len());
+    ranges
+}
+
+/// Reorder files so they are taken one at a time from each top-level
+/// directory under `root` in turn, instead of exhausting the first directory
+/// before moving on. Files directly inside `root` form their own group.
+fn interleave_by_top_level_dir(root: &Path, files: Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut groups: Vec<(PathBuf, Vec<PathBuf>)> = Vec::new();
+
+    for file in files {
+        let relative = file.strip_prefix(root).unwrap_or(&file);
+        let mut components = relative.components();
+        let first = components.next();
+        let key = match (first, components.next()) {
+            (Some(dir), Some(_)) => PathBuf::from(dir.as_os_str()),
+            _ => PathBuf::new(),
+        };
+
+        match groups.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, group)) => group.push(file),
+            None => groups.push((key, vec![file])),
+        }
+    }
+
+    let mut iterators: Vec<_> = groups.into_iter().map(|(_, group)| group.into_iter()).collect();
+    let mut interleaved = Vec::new();
+    loop {
+        let before = interleaved.len();
+        for iterator in iterators.iter_mut() {
+            interleaved.extend(iterator.next());
+        }
+        if interleaved.len() == before {
+            return interleaved;
+        }
+    }
+}
+
+/// Selects files mentioning a symbol as a whole word, which covers both its
+/// definition and its references. A ctags file adds the indexed definition
+/// files to the word matches; an LSIF dump already records every definition
+/// and reference, so it replaces word matching entirely.
+pub struct SymbolFilter {
+    regex: Regex,
+    context: Option<usize>,
+    indexed_files: HashSet<PathBuf>,
+    match_words: bool,
+}
+
+impl SymbolFilter {
+    /// Build a filter for `name`, reading the optional ctags and LSIF indexes
+    /// from `fs`. With `context`, output is sliced to the matching lines plus
+    /// that many lines either side.
+    pub fn new(
+        name: &str,
+        context: Option<usize>,
+        tags: Option<&Path>,
+        lsif: Option<&Path>,
+        fs: &dyn FileSystem,
+    ) -> io::Result<Self> {
+        let regex = Regex::new(&format!(r"\b{}\b", regex::escape(name)))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        let mut indexed_files = HashSet::new();
+        if let Some(tags) = tags {
+            indexed_files.extend(symbol_index::ctags_definitions(fs, tags, name)?);
+        }
+        if let Some(lsif) = lsif {
+            indexed_files.extend(symbol_index::lsif_occurrences(fs, lsif, name)?);
+        }
+
+        Ok(SymbolFilter {
+            regex,
+            context,
+            indexed_files,
+            match_words: lsif.is_none(),
+        })
+    }
+
+    fn matches(&self, fs: &dyn FileSystem, path: &Path, content: &str) -> bool {
+        (self.match_words && self.regex.is_match(content))
+            || self.indexed_files.contains(&symbol_index::normalize(fs, path))
+    }
+}
+
+/// `content` without its blank lines.
+fn strip_blank_lines(content: &str) -> String {
+    content.split_inclusive('\n').filter(|line| !line.trim().is_empty()).collect()
+}
+
+/// Append the labels of every annotation matching a line to it, keeping
+/// the line count so ranges and line numbers still refer to the file.
+fn annotate(content: &str, annotations: &[(Regex, String)]) -> String {
+    let mut output = String::with_capacity(content.len());
+    for line in content.split_inclusive('\n') {
+        let (text, ending) = match line.strip_suffix('\n') {
+            Some(text) => (text, "\n"),
+            None => (line, ""),
+        };
+        let labels: Vec<&str> = annotations
+            .iter()
+            .filter(|(regex, _)| regex.is_match(text))
+            .map(|(_, label)| label.as_str())
+            .collect();
+        output.push_str(text);
+        if !labels.is_empty() {
+            output.push_str("  <-- ");
+            output.push_str(&labels.join(", "));
+        }
+        output.push_str(ending);
+    }
+    output
+}
+
+/// A TODO, FIXME or HACK marker inside a comment, recognized by a comment
+/// leader (`//`, `#`, `/*`, `*`, `--`, `;` or `<!--`) earlier on the line.
+fn todo_marker() -> &'static Regex {
+    static MARKER: OnceLock<Regex> = OnceLock::new();
+    MARKER.get_or_init(|| Regex::new(r"(?m)(?://|#|/\*|^\s*\*|--|;|<!--).*\b(?:TODO|FIXME|HACK)\b").unwrap())
+}
+
+/// A line kept from a file with its 1-based number, or `None` where lines
+/// were left out.
+type KeptLine<'c> = Option<(usize, &'c str)>;
+
+/// The name and language of a block of a single-file component.
+type Section = (String, String);
+
+/// Keep only the lines within `context` lines of a match, with a gap for
+/// each run of lines left out.
+fn slice_around_matches<'c>(content: &'c str, regex: &Regex, context: usize) -> Vec<KeptLine<'c>> {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut keep = vec![false; lines.len()];
+
+    for (i, line) in lines.iter().enumerate() {
+        if regex.is_match(line) {
+            let start = i.saturating_sub(context);
+            let end = (i + context).min(lines.len() - 1);
+            keep[start..=end].iter_mut().for_each(|k| *k = true);
+        }
+    }
+
+    let mut output = Vec::new();
+    let mut in_gap = false;
+    for (i, line) in lines.iter().enumerate() {
+        if !keep[i] {
+            in_gap = true;
+            continue;
+        }
+        if in_gap {
+            output.push(None);
+        }
+        in_gap = false;
+        output.push(Some((i + 1, *line)));
+    }
+    if in_gap {
+        output.push(None);
+    }
+
+    output
+}
+
+/// Kept lines as text, each gap written as a `...` line. With
+/// `line_numbers` the lines are prefixed with their numbers in the original
+/// file of `total` lines.
+fn join_kept(lines: &[KeptLine], total: usize, line_numbers: bool) -> String {
+    let padding = total.to_string().len();
+    lines
+        .iter()
+        .map(|line| match line {
+            None => "...".to_string(),
+            Some((number, line)) if line_numbers => format!("{:width$}  {}", number, line, width = padding),
+            Some((_, line)) => line.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Kept lines as `<line n="12">` elements for `cxml_lines`, each gap
+/// written as a `...` line. With `escape` the text of each line is escaped
+/// for `cxml_strict`.
+fn line_elements(lines: &[KeptLine], escape: bool) -> String {
+    lines
+        .iter()
+        .map(|line| match line {
+            None => "...".to_string(),
+            Some((number, line)) if escape => format!("<line n=\"{}\">{}</line>", number, escape_text(line)),
+            Some((number, line)) => format!("<line n=\"{}\">{}</line>", number, line),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// The stand-in for a binary file: its size and content type, then a hex
+/// dump of up to `preview` leading bytes, sixteen to a line.
+fn binary_summary(path: &Path, content: &[u8], preview: usize) -> String {
+    let mut summary =
+        format!("[binary file: {}, {}]", stats::format_size(content.len() as u64), mime::sniff(path, content));
+    for (i, chunk) in content[..preview.min(content.len())].chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let text: String = chunk.iter().map(|&byte| if byte.is_ascii_graphic() { byte as char } else { '.' }).collect();
+        summary.push_str(&format!("\n{:08x}  {:<47}  |{}|", i * 16, hex.join(" "), text));
+    }
+    summary
+}
+
+/// An include glob, and whether it is matched against the whole path below
+/// the input rather than the name: a glob with a `/` spans the path, and a
+/// leading `/` only anchors it.
+fn include_pattern(pattern: &str) -> (Option<NamePattern>, bool) {
+    let pattern = &names::normalize_separators(pattern, false);
+    match pattern.strip_prefix('/') {
+        Some(anchored) => (NamePattern::new_path(anchored), true),
+        None => (NamePattern::new_path(pattern), pattern.contains('/')),
+    }
+}
+
+fn matches_include_pattern((pattern, spans_path): &(Option<NamePattern>, bool), root: &Path, file: &Path) -> bool {
+    match pattern {
+        Some(pattern) if *spans_path => pattern.matches_bytes(relative_text(root, file).as_bytes()),
+        Some(pattern) => pattern.matches(file.file_name().unwrap_or_default()),
+        None => false,
+    }
+}
+
+/// The lines of `text` that `truncate` keeps, with a line in place of the
+/// rest saying how many there were.
+fn truncate_lines(text: &str, truncate: Truncate) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let cut = lines.len().saturating_sub(truncate.lines());
+    let plural = if cut == 1 { "" } else { "s" };
+    match truncate {
+        Truncate::Head(count) => format!("{}\n... truncated ({} more line{})", lines[..count].join("\n"), cut, plural),
+        Truncate::Tail(_) => format!("... truncated ({} earlier line{})\n{}", cut, plural, lines[cut..].join("\n")),
+    }
+}
+
+/// The path of `file` below the input `root`, written with `/`. A file
+/// given directly is written as given.
+fn relative_text(root: &Path, file: &Path) -> String {
+    let relative = file.strip_prefix(root).ok().filter(|relative| relative.file_name().is_some()).unwrap_or(file);
+    relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Whether `path` falls in the `fraction` of paths sampled with `seed`. The
+/// choice depends on nothing else, so it is the same in every run and on
+/// every machine.
+fn in_sample(path: &Path, seed: u64, fraction: f64) -> bool {
+    // Components are joined with `/` so Windows paths hash the same
+    let mut key = seed.to_le_bytes().to_vec();
+    for component in path.components() {
+        key.extend_from_slice(&names::name_bytes(component.as_os_str()));
+        key.push(b'/');
+    }
+
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in key {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    // FNV's low bits are poorly mixed; finish with the splitmix64 finalizer
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^= hash >> 31;
+
+    ((hash >> 11) as f64 / (1u64 << 53) as f64) < fraction
+}
+
+/// A 64-bit FNV-1a hash of `content` in hex. It only has to tell versions
+/// of a file apart, and it is stable across platforms and releases.
+pub(crate) fn content_hash(content: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in content {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+fn escape_attribute(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('"', "&quot;")
+}
+
+/// `text` escaped to be the text of an element.
+fn escape_text(text: &str) -> String {
+    xml_chars(text).replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// `content` as a CDATA section. A `]]>` in it is split across two
+/// sections, as it would end the section early.
+fn cdata(content: &str) -> String {
+    format!("<![CDATA[{}]]>", xml_chars(content).replace("]]>", "]]]]><![CDATA[>"))
+}
+
+/// `text` with the control characters XML can't hold, even escaped,
+/// replaced by U+FFFD.
+fn xml_chars(text: &str) -> Cow<'_, str> {
+    let invalid = |c: char| (c < ' ' && !matches!(c, '\t' | '\n' | '\r')) || matches!(c, '\u{fffe}' | '\u{ffff}');
+    if text.contains(invalid) {
+        Cow::Owned(text.replace(invalid, "\u{fffd}"))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+/// The lines from `start` to `end` (1-based, inclusive) of `content`.
+fn slice_lines(content: &str, start: usize, end: usize) -> Vec<KeptLine<'_>> {
+    content.lines().enumerate().skip(start - 1).take(end + 1 - start).map(|(i, line)| Some((i + 1, line))).collect()
+}
+
+/// The files among `files` that look like entry points, together with the
+/// files they import that are also among `files`, in their original order.
+fn entry_points(fs: &dyn FileSystem, files: Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut selected = HashSet::new();
+    for file in files.iter().filter(|file| is_entry_point(file)) {
+        selected.insert(imports::normalize(file));
+        if let Ok(content) = fs.read_to_string(file) {
+            selected.extend(imports::imported_files(fs, file, &content));
+        }
+    }
+
+    files.into_iter().filter(|file| selected.contains(&imports::normalize(file))).collect()
+}
+
+fn is_entry_point(path: &Path) -> bool {
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let has_extension = path.extension().is_some();
+    (has_extension && ENTRY_POINT_STEMS.contains(&stem.as_str())) || path.file_name() == Some(OsStr::new("lib.rs"))
+}
+
+/// Return the existing conventional test files for a source file:
+/// `tests/foo.rs` for Rust, `foo_test.go` for Go, `foo.spec.ts` /
+/// `foo.test.ts` for JavaScript and TypeScript, and `test_foo.py` /
+/// `foo_test.py` for Python. Test files have no counterparts themselves,
+/// and none are looked for above `root`.
+fn test_counterparts(fs: &dyn FileSystem, root: &Path, path: &Path) -> Vec<PathBuf> {
+    let stem = path.file_stem().unwrap_or_default();
+    let ext = path.extension().unwrap_or_default().to_string_lossy().to_string();
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let name = |prefix: &str, suffix: &str| -> OsString {
+        let mut name = OsString::from(prefix);
+        name.push(stem);
+        name.push(suffix);
+        name
+    };
+
+    if is_test_file(path) {
+        return Vec::new();
+    }
+
+    let mut candidates = Vec::new();
+    match ext.as_str() {
+        "rs" => {
+            // Integration tests live in a `tests/` directory next to `src/`,
+            // so look in every ancestor up to the root rather than only the parent.
+            for ancestor in dir.ancestors().take_while(|ancestor| ancestor.starts_with(root)) {
+                candidates.push(ancestor.join("tests").join(name("", ".rs")));
+            }
+        }
+        "go" => candidates.push(dir.join(name("", "_test.go"))),
+        "js" | "jsx" | "ts" | "tsx" | "mjs" | "cjs" => {
+            candidates.push(dir.join(name("", &format!(".spec.{}", ext))));
+            candidates.push(dir.join(name("", &format!(".test.{}", ext))));
+        }
+        "py" => {
+            candidates.push(dir.join(name("test_", ".py")));
+            candidates.push(dir.join(name("", "_test.py")));
+            for ancestor in dir.ancestors().take_while(|ancestor| ancestor.starts_with(root)) {
+                candidates.push(ancestor.join("tests").join(name("test_", ".py")));
+            }
+        }
+        _ => {}
+    }
+
+    let mut found: Vec<PathBuf> = Vec::new();
+    for candidate in candidates {
+        if candidate != path && fs.is_file(&candidate) && !found.contains(&candidate) {
+            found.push(candidate);
+        }
+    }
+    found
+}
+
+fn is_test_file(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let in_tests_dir = path
+        .parent()
+        .and_then(|p| p.file_name())
+        .is_some_and(|p| p == "tests");
+
+    in_tests_dir
+        || name.starts_with("test_")
+        || name.ends_with("_test.go")
+        || name.ends_with("_test.py")
+        || name.contains(".spec.")
+        || name.contains(".test.")
+}
@@ -0,0 +1,222 @@
+// Removing comments for --strip-comments and --strip-comments-for. Each
+// language's comment and string syntax is known well enough for a single
+// scan to leave comment markers inside strings alone. Lines left empty by
+// the removal are dropped; lines that were blank to begin with stay, unless
+// --strip-blank-lines drops them too.
+use std::path::Path;
+
+/// How comments and strings are written in a family of languages.
+struct Syntax {
+    line: &'static [&'static str],
+    block: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+    /// `'` starts a character literal only when it closes right away, as a
+    /// Rust lifetime also starts with one.
+    char_literals: bool,
+    /// Line comments only start a word, as `$#` in a shell script is not one.
+    word_comments: bool,
+    /// A string on its own line where a docstring can be is a comment too.
+    docstrings: bool,
+}
+
+const C_LIKE: Syntax = Syntax {
+    line: &["//"],
+    block: Some(("/*", "*/")),
+    quotes: &['"', '\''],
+    char_literals: true,
+    word_comments: false,
+    docstrings: false,
+};
+
+const JAVASCRIPT: Syntax = Syntax { quotes: &['"', '\'', '`'], char_literals: false, ..C_LIKE };
+
+const CSS: Syntax = Syntax { line: &[], char_literals: false, ..C_LIKE };
+
+const HASH: Syntax = Syntax {
+    line: &["#"],
+    block: None,
+    quotes: &['"', '\''],
+    char_literals: false,
+    word_comments: true,
+    docstrings: false,
+};
+
+const PYTHON: Syntax = Syntax { docstrings: true, ..HASH };
+
+// `'` is common in plain YAML and TOML values
+const CONFIG: Syntax = Syntax { quotes: &['"'], ..HASH };
+
+const SQL: Syntax = Syntax { line: &["--"], block: Some(("/*", "*/")), quotes: &['\''], ..HASH };
+
+const LUA: Syntax = Syntax { line: &["--"], block: Some(("--[[", "]]")), quotes: &['"', '\''], ..HASH };
+
+const HASKELL: Syntax = Syntax { line: &["--"], block: Some(("{-", "-}")), quotes: &['"'], ..HASH };
+
+// Text between tags is full of apostrophes, so quotes are not tracked
+const MARKUP: Syntax = Syntax { line: &[], block: Some(("<!--", "-->")), quotes: &[], ..HASH };
+
+const EXTENSIONS: &[(&str, &Syntax)] = &[
+    ("c", &C_LIKE),
+    ("h", &C_LIKE),
+    ("cc", &C_LIKE),
+    ("cpp", &C_LIKE),
+    ("cxx", &C_LIKE),
+    ("hpp", &C_LIKE),
+    ("java", &C_LIKE),
+    ("go", &C_LIKE),
+    ("rs", &C_LIKE),
+    ("cs", &C_LIKE),
+    ("kt", &C_LIKE),
+    ("scala", &C_LIKE),
+    ("swift", &C_LIKE),
+    ("dart", &C_LIKE),
+    ("js", &JAVASCRIPT),
+    ("jsx", &JAVASCRIPT),
+    ("mjs", &JAVASCRIPT),
+    ("cjs", &JAVASCRIPT),
+    ("ts", &JAVASCRIPT),
+    ("tsx", &JAVASCRIPT),
+    ("css", &CSS),
+    ("py", &PYTHON),
+    ("sh", &HASH),
+    ("bash", &HASH),
+    ("zsh", &HASH),
+    ("rb", &HASH),
+    ("pl", &HASH),
+    ("r", &HASH),
+    ("yaml", &CONFIG),
+    ("yml", &CONFIG),
+    ("toml", &CONFIG),
+    ("sql", &SQL),
+    ("lua", &LUA),
+    ("hs", &HASKELL),
+    ("html", &MARKUP),
+    ("htm", &MARKUP),
+    ("xml", &MARKUP),
+    ("svg", &MARKUP),
+];
+
+/// Files known by name rather than extension.
+const NAMES: &[(&str, &Syntax)] = &[("Makefile", &HASH), ("Dockerfile", &HASH)];
+
+/// `content` without its comments, or `None` when the language of `path`
+/// is not known.
+pub(crate) fn strip(path: &Path, content: &str) -> Option<String> {
+    let name = path.file_name()?.to_str()?;
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+    let syntax = NAMES
+        .iter()
+        .find(|(known, _)| *known == name)
+        .or_else(|| EXTENSIONS.iter().find(|(known, _)| *known == extension))
+        .map(|(_, syntax)| *syntax)?;
+    Some(Scanner::new(syntax).run(content))
+}
+
+struct Scanner<'s> {
+    syntax: &'s Syntax,
+    output: String,
+    line: String,
+    /// Whether a comment was taken out of `line`.
+    removed: bool,
+    /// The last character of code before `line`, to tell where a docstring
+    /// can be.
+    last_code: Option<char>,
+}
+
+impl<'s> Scanner<'s> {
+    fn new(syntax: &'s Syntax) -> Self {
+        Scanner { syntax, output: String::new(), line: String::new(), removed: false, last_code: None }
+    }
+
+    fn run(mut self, content: &str) -> String {
+        let syntax = self.syntax;
+        let mut rest = content;
+        while let Some(c) = rest.chars().next() {
+            if c == '\n' {
+                self.end_line(true);
+                rest = &rest[1..];
+            } else if let Some((open, close)) = syntax.block.filter(|(open, _)| rest.starts_with(open)) {
+                rest = self.skip_comment(&rest[open.len()..], close);
+            } else if syntax.line.iter().any(|marker| rest.starts_with(marker)) && self.at_word_start() {
+                rest = &rest[rest.find('\n').unwrap_or(rest.len())..];
+                self.removed = true;
+            } else if let Some(quote) = syntax.docstrings.then(|| triple_quote(rest)).flatten() {
+                let end = rest[3..].find(quote).map_or(rest.len(), |i| i + 6);
+                if self.line.trim().is_empty() && matches!(self.last_code, None | Some(':')) {
+                    rest = self.skip_comment(&rest[3..], quote);
+                } else {
+                    self.line.push_str(&rest[..end]);
+                    rest = &rest[end..];
+                }
+            } else if syntax.quotes.contains(&c) {
+                let end = self.string_end(rest, c);
+                self.line.push_str(&rest[..end]);
+                rest = &rest[end..];
+            } else {
+                self.line.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+        if !self.line.is_empty() || self.removed {
+            self.end_line(false);
+        }
+        self.output
+    }
+
+    // Skip past `close`, ending each line the comment spans
+    fn skip_comment<'c>(&mut self, text: &'c str, close: &str) -> &'c str {
+        self.removed = true;
+        let end = text.find(close).map_or(text.len(), |i| i + close.len());
+        for _ in text[..end].matches('\n') {
+            self.end_line(true);
+            self.removed = true;
+        }
+        &text[end..]
+    }
+
+    fn end_line(&mut self, newline: bool) {
+        let line = std::mem::take(&mut self.line);
+        let kept = if self.removed { line.trim_end() } else { line.as_str() };
+        if !(self.removed && kept.trim().is_empty()) {
+            self.output.push_str(kept);
+            if newline {
+                self.output.push('\n');
+            }
+        }
+        if let Some(c) = kept.trim_end().chars().last() {
+            self.last_code = Some(c);
+        }
+        self.removed = false;
+    }
+
+    fn at_word_start(&self) -> bool {
+        !self.syntax.word_comments || self.line.chars().last().map_or(true, char::is_whitespace)
+    }
+
+    // Where the string starting at `text` ends, after its closing quote. A
+    // `'` that is not a character literal is just itself.
+    fn string_end(&self, text: &str, quote: char) -> usize {
+        if quote == '\'' && self.syntax.char_literals {
+            let mut chars = text.char_indices().skip(1);
+            return match (chars.next(), chars.next()) {
+                (Some((_, '\\')), _) => text[2..].find('\'').map_or(1, |i| i + 3),
+                (Some(_), Some((i, '\''))) => i + 1,
+                _ => 1,
+            };
+        }
+        let mut escaped = false;
+        for (i, c) in text.char_indices().skip(1) {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                c if c == quote => return i + 1,
+                _ => {}
+            }
+        }
+        text.len()
+    }
+}
+
+fn triple_quote(text: &str) -> Option<&'static str> {
+    ["\"\"\"", "'''"].into_iter().find(|quote| text.starts_with(quote))
+}
@@ -0,0 +1,325 @@
+// Defaults for the command line from the project's `.files-to-prompt.toml`
+// and from `FILES_TO_PROMPT_*` environment variables. Each setting is named
+// after a long option and turned back into arguments placed before the ones
+// given, so flags always win.
+use crate::error::Context;
+use crate::init::CONFIG_FILE;
+use crate::vfs::FileSystem;
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use std::ffi::OsString;
+use std::io;
+use std::path::Path;
+
+/// Prefix of the environment variables setting options, as in
+/// `FILES_TO_PROMPT_LINE_NUMBERS=1`.
+pub const ENV_PREFIX: &str = "FILES_TO_PROMPT_";
+
+/// The table of `.files-to-prompt.toml` that is applied.
+const PROFILE_TABLE: &str = "profile.default";
+
+/// The table read from a `pyproject.toml` without a configuration file.
+const PYPROJECT_TABLE: &str = "tool.files-to-prompt";
+
+/// Options choosing between alternatives, so a flag for one of them
+/// replaces a configured other.
+const ALTERNATIVES: &[&[&str]] = &[&["cxml", "markdown", "json", "template"]];
+
+/// A TOML value, as far as options need them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    String(String),
+    Number(String),
+    Bool(bool),
+    Array(Vec<Value>),
+}
+
+/// One option set by the configuration or the environment, and where, for
+/// error messages.
+struct Setting {
+    name: String,
+    value: Value,
+    origin: String,
+}
+
+/// The arguments to put before the command line: the settings of the
+/// nearest configuration file above `dir`, unless `--no-config` is given,
+/// overridden by the environment, leaving out any option the command line
+/// sets or conflicts with.
+pub(crate) fn default_args(
+    command: &Command,
+    matches: &ArgMatches,
+    dir: &Path,
+    fs: &dyn FileSystem,
+) -> io::Result<Vec<OsString>> {
+    let mut settings = if matches.get_flag("no-config") { Vec::new() } else { config_settings(dir, fs)? };
+    for setting in env_settings(command) {
+        settings.retain(|configured| configured.name != setting.name);
+        settings.push(setting);
+    }
+
+    let given: Vec<&Arg> = command
+        .get_arguments()
+        .filter(|arg| matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine))
+        .collect();
+
+    let mut args = Vec::new();
+    for setting in settings {
+        let arg = find_arg(command, &setting.name)
+            .ok_or_else(|| invalid(&setting.origin, format!("unknown option '{}'", setting.name)))?;
+        let overridden = given.iter().any(|other| {
+            other.get_id() == arg.get_id()
+                || command.get_arg_conflicts_with(arg).contains(other)
+                || command.get_arg_conflicts_with(other).contains(&arg)
+                || alternatives(arg, other)
+        });
+        if !overridden {
+            args.extend(arguments(arg, &setting)?);
+        }
+    }
+    Ok(args)
+}
+
+// The `[profile.default]` table of `.files-to-prompt.toml`, or failing that
+// the `[tool.files-to-prompt]` table of `pyproject.toml`, in the nearest
+// directory with either
+fn config_settings(dir: &Path, fs: &dyn FileSystem) -> io::Result<Vec<Setting>> {
+    let dir = fs.canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+    for ancestor in dir.ancestors() {
+        for (name, table) in [(CONFIG_FILE, PROFILE_TABLE), ("pyproject.toml", PYPROJECT_TABLE)] {
+            let path = ancestor.join(name);
+            if !fs.is_file(&path) {
+                continue;
+            }
+            let text = fs.read_to_string(&path).context("read", &path)?;
+            let entries = parse(&text).map_err(|message| invalid(&path.display().to_string(), message))?;
+            if name == CONFIG_FILE || entries.iter().any(|(entry_table, _, _)| entry_table == table) {
+                return Ok(settings_in(entries, table, &path));
+            }
+        }
+    }
+    Ok(Vec::new())
+}
+
+fn settings_in(entries: Vec<(String, String, Value)>, table: &str, path: &Path) -> Vec<Setting> {
+    entries
+        .into_iter()
+        .filter(|(entry_table, _, _)| entry_table == table)
+        .map(|(_, name, value)| Setting { name, value, origin: path.display().to_string() })
+        .collect()
+}
+
+// FILES_TO_PROMPT_LINE_NUMBERS=1 sets --line-numbers; options taking
+// several values are separated by commas
+fn env_settings(command: &Command) -> Vec<Setting> {
+    let mut settings = Vec::new();
+    for arg in command.get_arguments() {
+        let Some(long) = arg.get_long() else {
+            continue;
+        };
+        let variable = format!("{}{}", ENV_PREFIX, long.to_uppercase().replace('-', "_"));
+        let Ok(text) = std::env::var(&variable) else {
+            continue;
+        };
+
+        let value = if !arg.get_action().takes_values() {
+            match text.to_lowercase().as_str() {
+                "1" | "true" | "yes" | "on" => Value::Bool(true),
+                "" | "0" | "false" | "no" | "off" => Value::Bool(false),
+                _ => Value::String(text),
+            }
+        } else if matches!(arg.get_action(), ArgAction::Append) {
+            Value::Array(text.split(',').map(|part| Value::String(part.to_string())).collect())
+        } else {
+            Value::String(text)
+        };
+        settings.push(Setting { name: long.to_string(), value, origin: variable });
+    }
+    settings
+}
+
+// Settings may be spelled with underscores, as `line_numbers`
+fn find_arg<'c>(command: &'c Command, name: &str) -> Option<&'c Arg> {
+    let name = name.replace('_', "-");
+    command.get_arguments().find(|arg| arg.get_long() == Some(name.as_str()))
+}
+
+fn alternatives(arg: &Arg, other: &Arg) -> bool {
+    let (Some(long), Some(other_long)) = (arg.get_long(), other.get_long()) else {
+        return false;
+    };
+    ALTERNATIVES.iter().any(|group| group.contains(&long) && group.contains(&other_long))
+}
+
+fn arguments(arg: &Arg, setting: &Setting) -> io::Result<Vec<OsString>> {
+    let long = arg.get_long().unwrap_or_default();
+    let wrong_type = |expected: &str| invalid(&setting.origin, format!("'{}' must be {}", setting.name, expected));
+
+    if !arg.get_action().takes_values() {
+        return match setting.value {
+            Value::Bool(true) => Ok(vec![format!("--{}", long).into()]),
+            Value::Bool(false) => Ok(Vec::new()),
+            _ => Err(wrong_type("true or false")),
+        };
+    }
+
+    let values = match &setting.value {
+        Value::Array(values) if matches!(arg.get_action(), ArgAction::Append) => values.iter().collect(),
+        Value::Array(_) => return Err(wrong_type("a single value")),
+        value => vec![value],
+    };
+    values
+        .into_iter()
+        .map(|value| match value {
+            Value::String(text) | Value::Number(text) => Ok(format!("--{}={}", long, text).into()),
+            _ => Err(wrong_type("a string or number, or a list of them")),
+        })
+        .collect()
+}
+
+fn invalid(origin: &str, message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", origin, message))
+}
+
+/// The `key = value` entries of a TOML document with the table each is in,
+/// such as `profile.default`. Only strings, numbers, booleans and arrays of
+/// them are understood, which is all options take.
+fn parse(text: &str) -> Result<Vec<(String, String, Value)>, String> {
+    let mut entries = Vec::new();
+    let mut table = String::new();
+    let mut lines = text.lines().enumerate();
+
+    while let Some((index, line)) = lines.next() {
+        let at_line = |message: String| format!("line {}: {}", index + 1, message);
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let header = header.strip_suffix(']').ok_or_else(|| at_line("unclosed table header".to_string()))?;
+            let parts: Vec<&str> = header.split('.').map(|part| part.trim().trim_matches('"')).collect();
+            table = parts.join(".");
+            continue;
+        }
+
+        let (key, value) = line.split_once('=').ok_or_else(|| at_line("expected `key = value`".to_string()))?;
+        let key = key.trim().trim_matches('"').to_string();
+        let mut value = value.trim().to_string();
+        // Arrays may go on over several lines
+        while bracket_depth(&value) > 0 {
+            let (_, next) = lines.next().ok_or_else(|| at_line(format!("unclosed array for '{}'", key)))?;
+            value.push('\n');
+            value.push_str(strip_comment(next));
+        }
+
+        let mut rest = value.as_str();
+        let parsed = parse_value(&mut rest).map_err(at_line)?;
+        if !rest.trim().is_empty() {
+            return Err(at_line(format!("unexpected '{}' after the value of '{}'", rest.trim(), key)));
+        }
+        entries.push((table.clone(), key, parsed));
+    }
+    Ok(entries)
+}
+
+fn parse_value(text: &mut &str) -> Result<Value, String> {
+    *text = text.trim_start();
+    if let Some(rest) = text.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    *text = &rest[i + 1..];
+                    return Ok(Value::String(value));
+                }
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('r') => value.push('\r'),
+                    Some(c @ ('"' | '\\')) => value.push(c),
+                    other => return Err(format!("unsupported escape '\\{}'", other.unwrap_or(' '))),
+                },
+                c => value.push(c),
+            }
+        }
+        Err("unclosed string".to_string())
+    } else if let Some(rest) = text.strip_prefix('\'') {
+        let end = rest.find('\'').ok_or("unclosed string")?;
+        *text = &rest[end + 1..];
+        Ok(Value::String(rest[..end].to_string()))
+    } else if let Some(rest) = text.strip_prefix('[') {
+        *text = rest;
+        let mut values = Vec::new();
+        loop {
+            *text = text.trim_start();
+            if let Some(rest) = text.strip_prefix(']') {
+                *text = rest;
+                return Ok(Value::Array(values));
+            }
+            values.push(parse_value(text)?);
+            *text = text.trim_start();
+            if let Some(rest) = text.strip_prefix(',') {
+                *text = rest;
+            } else if !text.starts_with(']') {
+                return Err("expected ',' or ']' in array".to_string());
+            }
+        }
+    } else if let Some(rest) = text.strip_prefix("true") {
+        *text = rest;
+        Ok(Value::Bool(true))
+    } else if let Some(rest) = text.strip_prefix("false") {
+        *text = rest;
+        Ok(Value::Bool(false))
+    } else {
+        let end = text.find(|c: char| !(c.is_ascii_alphanumeric() || "+-._".contains(c))).unwrap_or(text.len());
+        if end == 0 || !text.starts_with(|c: char| c.is_ascii_digit() || c == '+' || c == '-') {
+            return Err(format!("unsupported value '{}'", text.lines().next().unwrap_or_default()));
+        }
+        let number = text[..end].to_string();
+        *text = &text[end..];
+        Ok(Value::Number(number))
+    }
+}
+
+// A `#` outside a string starts a comment
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..i],
+            _ => {}
+        }
+        escaped = false;
+    }
+    line
+}
+
+fn bracket_depth(text: &str) -> isize {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    for c in text.chars() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '[') => depth += 1,
+            (None, ']') => depth -= 1,
+            _ => {}
+        }
+        escaped = false;
+    }
+    depth
+}
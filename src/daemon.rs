@@ -0,0 +1,193 @@
+// `files-to-prompt daemon`: a long-running process answering collection
+// requests over a Unix socket, so repeated runs from an editor reuse the
+// file contents and directory listings it has already read. Each request
+// and response is one line of JSON.
+use serde_json::json;
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A collection to run: the command line, without the program name, the
+/// directory it was given in, and what was piped to it, if anything.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Request {
+    pub cwd: PathBuf,
+    pub args: Vec<String>,
+    pub stdin: Option<Vec<u8>>,
+}
+
+/// What a collection wrote and the exit status it would have had.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Response {
+    pub status: i32,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// `files-to-prompt.sock` in the user's runtime directory, or failing
+/// that in a directory of the temp directory private to the user.
+pub(crate) fn default_socket() -> PathBuf {
+    let dir = std::env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from).or_else(private_dir);
+    dir.unwrap_or_else(std::env::temp_dir).join("files-to-prompt.sock")
+}
+
+/// `files-to-prompt-<uid>` in the temp directory. Being named after the
+/// user, another user can only get in the way by creating it first, which
+/// `check_private` catches.
+#[cfg(unix)]
+fn private_dir() -> Option<PathBuf> {
+    // SAFETY: getuid has no preconditions and cannot fail
+    let uid = unsafe { libc::getuid() };
+    Some(std::env::temp_dir().join(format!("files-to-prompt-{}", uid)))
+}
+
+#[cfg(not(unix))]
+fn private_dir() -> Option<PathBuf> {
+    None
+}
+
+/// Fail unless `dir`, the directory of the default socket, is a real
+/// directory of this user's that nobody else can enter, as anyone who can
+/// could put their own socket there.
+#[cfg(unix)]
+fn check_private(dir: &Path) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = std::fs::symlink_metadata(dir)?;
+    // SAFETY: getuid has no preconditions and cannot fail
+    let uid = unsafe { libc::getuid() };
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is not a directory only this user can use; remove it or pass --socket", dir.display()),
+        ));
+    }
+    Ok(())
+}
+
+/// Listen on `socket` and answer each request with `handle`, one at a
+/// time, until the process is killed.
+#[cfg(unix)]
+pub(crate) fn serve(socket: &Path, mut handle: impl FnMut(Request) -> Response) -> io::Result<()> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::fs::DirBuilderExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    let dir = socket.parent().filter(|dir| Some(dir.to_path_buf()) == private_dir());
+    if let Some(dir) = dir {
+        match std::fs::DirBuilder::new().mode(0o700).create(dir) {
+            Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+            _ => check_private(dir)?,
+        }
+    }
+
+    // A socket left behind by a daemon that has gone can be replaced
+    if socket.exists() {
+        if UnixStream::connect(socket).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("a daemon is already listening on {}", socket.display()),
+            ));
+        }
+        std::fs::remove_file(socket)?;
+    }
+    // Whoever can connect can read what this user can, so the socket is
+    // created with no access for others rather than restricted after.
+    // SAFETY: umask has no preconditions and cannot fail
+    let umask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(socket);
+    // SAFETY: as above
+    unsafe { libc::umask(umask) };
+    let listener = listener?;
+    eprintln!("Serving requests on {}", socket.display());
+
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| {
+            let mut line = String::new();
+            BufReader::new(&stream).read_line(&mut line)?;
+            let response = handle(parse_request(&line)?);
+            let response = json!({"status": response.status, "stdout": response.stdout, "stderr": response.stderr});
+            writeln!(&stream, "{}", response)
+        });
+        if let Err(e) = result {
+            eprintln!("Warning: request failed: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Have the daemon listening on `socket` run the command line `args`
+/// from the current directory, with `stdin` as its piped input.
+#[cfg(unix)]
+pub(crate) fn request(socket: &Path, args: &[OsString], stdin: Option<&[u8]>) -> io::Result<Response> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+
+    if let Some(dir) = socket.parent().filter(|dir| Some(dir.to_path_buf()) == private_dir()) {
+        check_private(dir)?;
+    }
+    let stream = UnixStream::connect(socket).map_err(|e| {
+        io::Error::new(e.kind(), format!("no daemon is listening on {}: {}", socket.display(), e))
+    })?;
+    let cwd = std::env::current_dir()?;
+    let args: Vec<String> = args.iter().map(|arg| arg.to_string_lossy().into_owned()).collect();
+    // Bytes rather than a string, as piped paths need not be UTF-8
+    writeln!(&stream, "{}", json!({"cwd": cwd.to_string_lossy(), "args": args, "stdin": stdin}))?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    parse_response(&line)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn serve(_socket: &Path, _handle: impl FnMut(Request) -> Response) -> io::Result<()> {
+    Err(unsupported())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn request(_socket: &Path, _args: &[OsString], _stdin: Option<&[u8]>) -> io::Result<Response> {
+    Err(unsupported())
+}
+
+#[cfg(not(unix))]
+fn unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "the daemon needs Unix domain sockets")
+}
+
+#[cfg(unix)]
+fn parse_request(line: &str) -> io::Result<Request> {
+    let value: serde_json::Value = serde_json::from_str(line).map_err(invalid)?;
+    let cwd = value["cwd"].as_str().ok_or_else(|| invalid("request without a cwd"))?;
+    let args = value["args"]
+        .as_array()
+        .ok_or_else(|| invalid("request without args"))?
+        .iter()
+        .map(|arg| arg.as_str().map(str::to_string).ok_or_else(|| invalid("args must be strings")))
+        .collect::<io::Result<Vec<String>>>()?;
+    let stdin = match value["stdin"].as_array() {
+        Some(bytes) => Some(
+            bytes
+                .iter()
+                .map(|byte| byte.as_u64().and_then(|byte| u8::try_from(byte).ok()).ok_or_else(|| invalid("stdin must be bytes")))
+                .collect::<io::Result<Vec<u8>>>()?,
+        ),
+        None => None,
+    };
+    Ok(Request { cwd: PathBuf::from(cwd), args, stdin })
+}
+
+#[cfg(unix)]
+fn parse_response(line: &str) -> io::Result<Response> {
+    let value: serde_json::Value = serde_json::from_str(line).map_err(invalid)?;
+    let text = |key: &str| value[key].as_str().unwrap_or_default().to_string();
+    Ok(Response {
+        status: value["status"].as_i64().ok_or_else(|| invalid("response without a status"))? as i32,
+        stdout: text("stdout"),
+        stderr: text("stderr"),
+    })
+}
+
+#[cfg(unix)]
+fn invalid(message: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
@@ -0,0 +1,54 @@
+// Reading compressed files for --decompress. This needs the `gzip` command
+// installed for .gz files and `zstd` for .zst files; a file whose command
+// can't be run is skipped with a warning.
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Compressed extensions and the command that writes their content to
+/// stdout.
+const DECOMPRESSORS: &[(&str, &str)] = &[("gz", "gzip"), ("zst", "zstd")];
+
+/// Whether `path` has an extension `decompress` understands.
+pub(crate) fn is_compressed(path: &Path) -> bool {
+    decompressor(path).is_some()
+}
+
+/// The decompressed bytes of `content`, read from `path`.
+pub(crate) fn decompress(path: &Path, content: &[u8]) -> io::Result<Vec<u8>> {
+    let program = decompressor(path).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a compressed file", path.display()))
+    })?;
+    pipe(program, &["-d", "-c"], content)
+}
+
+/// The stdout of `program` run with `args`, given `input` on stdin.
+fn pipe(program: &str, args: &[&str], input: &[u8]) -> io::Result<Vec<u8>> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("could not run {}: {}", program, e)))?;
+
+    // Written from another thread so a full stdout pipe cannot stall both sides
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = input.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+
+    let mut output = Vec::new();
+    child.stdout.take().expect("stdout is piped").read_to_end(&mut output)?;
+    let status = child.wait()?;
+    let written = writer.join().unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::Other, "writer panicked")));
+    if !status.success() {
+        return Err(io::Error::new(io::ErrorKind::Other, format!("{} failed with {}", program, status)));
+    }
+    written?;
+    Ok(output)
+}
+
+fn decompressor(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    DECOMPRESSORS.iter().find(|(known, _)| *known == extension).map(|(_, program)| *program)
+}
@@ -0,0 +1,158 @@
+// Minimal EditorConfig lookup for the tab width used by --expand-tabs
+use crate::vfs::FileSystem;
+use regex::Regex;
+use std::path::Path;
+
+pub const DEFAULT_TAB_WIDTH: usize = 8;
+
+/// Find the tab width EditorConfig assigns to `path`, using `tab_width` or
+/// a numeric `indent_size`. Files closer to `path` take precedence, and the
+/// search stops at a file declaring `root = true`.
+pub fn tab_width(fs: &dyn FileSystem, path: &Path) -> Option<usize> {
+    let absolute = fs.canonicalize(path).ok()?;
+    let mut width = None;
+
+    for dir in absolute.parent()?.ancestors() {
+        let Ok(content) = fs.read_to_string(&dir.join(".editorconfig")) else {
+            continue;
+        };
+        let relative = absolute.strip_prefix(dir).ok()?.to_string_lossy().replace('\\', "/");
+        let (is_root, found) = parse(&content, &relative);
+
+        // Nearer files were read first and win
+        if width.is_none() {
+            width = found;
+        }
+        if is_root {
+            break;
+        }
+    }
+
+    width
+}
+
+/// Replace tabs with spaces up to the next multiple of `width` on each line.
+pub fn expand_tabs(content: &str, width: usize) -> String {
+    let width = width.max(1);
+    let mut output = String::with_capacity(content.len());
+    let mut column = 0;
+
+    for c in content.chars() {
+        match c {
+            '\t' => {
+                let spaces = width - column % width;
+                output.push_str(&" ".repeat(spaces));
+                column += spaces;
+            }
+            '\n' => {
+                output.push(c);
+                column = 0;
+            }
+            _ => {
+                output.push(c);
+                column += 1;
+            }
+        }
+    }
+
+    output
+}
+
+// Returns whether the file is a root file and the width it sets for `relative`
+fn parse(content: &str, relative: &str) -> (bool, Option<usize>) {
+    let mut is_root = false;
+    let mut in_matching_section = false;
+    let mut tab_width = None;
+    let mut indent_size = None;
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            in_matching_section = section_matches(section, relative);
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_lowercase();
+        let value = value.trim().to_lowercase();
+
+        if key == "root" {
+            is_root = value == "true";
+        } else if in_matching_section && key == "tab_width" {
+            tab_width = value.parse().ok();
+        } else if in_matching_section && key == "indent_size" {
+            indent_size = value.parse().ok();
+        }
+    }
+
+    (is_root, tab_width.or(indent_size))
+}
+
+fn section_matches(section: &str, relative: &str) -> bool {
+    // Globs without a slash match the file name in any directory
+    let glob = if section.contains('/') {
+        section.trim_start_matches('/').to_string()
+    } else {
+        format!("**/{}", section)
+    };
+
+    Regex::new(&format!("^{}$", glob_to_regex(&glob)))
+        .map(|re| re.is_match(relative))
+        .unwrap_or(false)
+}
+
+// Translate EditorConfig glob syntax: `*` stays within a path segment, `**`
+// crosses segments, `**/` may match no directories, and `{a,b}` lists
+// alternatives.
+fn glob_to_regex(glob: &str) -> String {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut regex = String::new();
+    let mut depth = 0;
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                if chars.get(i + 2) == Some(&'/') {
+                    regex.push_str("(?:.*/)?");
+                    i += 3;
+                } else {
+                    regex.push_str(".*");
+                    i += 2;
+                }
+                continue;
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '{' => {
+                depth += 1;
+                regex.push_str("(?:");
+            }
+            '}' if depth > 0 => {
+                depth -= 1;
+                regex.push(')');
+            }
+            ',' if depth > 0 => regex.push('|'),
+            '[' => match chars[i..].iter().position(|&c| c == ']') {
+                Some(end) => {
+                    let class: String = chars[i + 1..i + end].iter().collect();
+                    let class = class.strip_prefix('!').map(|c| format!("^{}", c)).unwrap_or(class);
+                    regex.push_str(&format!("[{}]", class.replace('\\', "\\\\")));
+                    i += end + 1;
+                    continue;
+                }
+                None => regex.push_str("\\["),
+            },
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+
+    regex
+}
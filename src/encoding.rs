@@ -0,0 +1,81 @@
+// Reading files that are not UTF-8. UTF-16 is recognised by its byte order
+// mark, and content that is UTF-8 apart from a few stray bytes is kept as
+// UTF-8. Other text is taken to be in the legacy
+// encoding chardetng guesses for it, such as Windows-1252 or Shift_JIS.
+// Anything else is binary.
+use chardetng::EncodingDetector;
+use encoding_rs::UTF_8;
+use std::io;
+
+/// `content` as text: UTF-8 as it is, UTF-16 with a byte order mark and
+/// legacy encodings transcoded. Other content, including UTF-8 with bytes
+/// that do not belong, is an `InvalidData` error, unless `lossy` is set,
+/// when the bytes that are not UTF-8 are replaced by U+FFFD.
+pub(crate) fn decode(content: Vec<u8>, lossy: bool) -> io::Result<String> {
+    let content = match String::from_utf8(content) {
+        Ok(text) => return Ok(text),
+        Err(e) => e.into_bytes(),
+    };
+    if let Some(text) = decode_utf16(&content, lossy) {
+        return text;
+    }
+    if !mostly_utf8(&content) && looks_like_text(&content) {
+        let mut detector = EncodingDetector::new();
+        detector.feed(&content, true);
+        let encoding = detector.guess(None, false);
+        if encoding != UTF_8 {
+            if let Some(text) = encoding.decode_without_bom_handling_and_without_replacement(&content) {
+                return Ok(text.into_owned());
+            }
+        }
+    }
+    if lossy {
+        return Ok(String::from_utf8_lossy(&content).into_owned());
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidData, "stream did not contain valid UTF-8"))
+}
+
+// None without a byte order mark followed by whole code units; a mark
+// alone is too little to tell from binary
+fn decode_utf16(content: &[u8], lossy: bool) -> Option<io::Result<String>> {
+    if content.len() <= 2 || content.len() % 2 != 0 {
+        return None;
+    }
+    let to_unit: fn([u8; 2]) -> u16 = match content {
+        [0xff, 0xfe, ..] => u16::from_le_bytes,
+        [0xfe, 0xff, ..] => u16::from_be_bytes,
+        _ => return None,
+    };
+    let units: Vec<u16> = content[2..].chunks(2).map(|pair| to_unit([pair[0], pair[1]])).collect();
+    if lossy {
+        return Some(Ok(String::from_utf16_lossy(&units)));
+    }
+    Some(String::from_utf16(&units).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))
+}
+
+// At least as many well-formed multi-byte characters as stray bytes; a
+// legacy encoding rarely happens to form many
+fn mostly_utf8(content: &[u8]) -> bool {
+    let (mut characters, mut invalid) = (0, 0);
+    let mut rest = content;
+    while !rest.is_empty() {
+        let (valid, skip) = match std::str::from_utf8(rest) {
+            Ok(text) => (text, rest.len()),
+            Err(e) => {
+                let valid = std::str::from_utf8(&rest[..e.valid_up_to()]).unwrap_or_default();
+                invalid += 1;
+                (valid, e.valid_up_to() + e.error_len().unwrap_or(rest.len() - e.valid_up_to()))
+            }
+        };
+        characters += valid.chars().filter(|c| !c.is_ascii()).count();
+        rest = &rest[skip..];
+    }
+    characters >= invalid
+}
+
+// Some ASCII and no NUL or other control bytes. Binary data of any length
+// almost always has control bytes, so this is mainly for a handful of bytes
+fn looks_like_text(content: &[u8]) -> bool {
+    content.iter().any(|byte| byte.is_ascii_graphic())
+        && !content.iter().any(|&byte| byte < 0x20 && !matches!(byte, b'\t' | b'\n' | b'\r' | b'\x0c' | b'\x1b'))
+}
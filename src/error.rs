@@ -0,0 +1,42 @@
+// I/O errors that say what was being done and to which path. On its own an
+// `io::Error` only says something like "No such file or directory", which
+// leaves the user guessing which of several files is meant.
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// An I/O error together with the operation that failed, such as `create`
+/// or `read`, and the path it failed on. It travels inside an `io::Error`
+/// of the same kind, so callers can keep returning `io::Result`.
+#[derive(Debug)]
+pub struct PathError {
+    pub operation: &'static str,
+    pub path: PathBuf,
+    source: io::Error,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "could not {} {}: {}", self.operation, self.path.display(), self.source)
+    }
+}
+
+impl Error for PathError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Adds the operation and path to the error of an `io::Result`.
+pub trait Context<T> {
+    fn context(self, operation: &'static str, path: &Path) -> io::Result<T>;
+}
+
+impl<T> Context<T> for io::Result<T> {
+    fn context(self, operation: &'static str, path: &Path) -> io::Result<T> {
+        self.map_err(|source| {
+            io::Error::new(source.kind(), PathError { operation, path: path.to_path_buf(), source })
+        })
+    }
+}
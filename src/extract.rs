@@ -0,0 +1,217 @@
+// Plain text from documents for --extract, all in process: HTML and the
+// XML inside Word documents are reduced to their text here, and PDFs are
+// read with pdf-extract.
+use regex::Regex;
+use std::io::{self, Cursor, Read};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// The document formats `extract` understands.
+#[derive(Clone, Copy)]
+enum Kind {
+    Html,
+    Pdf,
+    Docx,
+}
+
+const EXTENSIONS: &[(&str, Kind)] =
+    &[("html", Kind::Html), ("htm", Kind::Html), ("xhtml", Kind::Html), ("pdf", Kind::Pdf), ("docx", Kind::Docx)];
+
+/// Elements that start a new line of text.
+const BLOCKS: &[&str] = &[
+    "address", "article", "aside", "dd", "div", "dt", "figcaption", "figure", "footer", "form", "header", "hr", "li",
+    "main", "nav", "section", "title", "tr",
+];
+
+/// Elements set apart from the text around them by a blank line.
+const PARAGRAPHS: &[&str] = &["blockquote", "dl", "h1", "h2", "h3", "h4", "h5", "h6", "ol", "p", "pre", "table", "ul"];
+
+/// Whether `path` has an extension `extract` understands.
+pub(crate) fn is_extractable(path: &Path) -> bool {
+    kind(path).is_some()
+}
+
+/// The text of the document `content`, read from `path`.
+pub(crate) fn extract(path: &Path, content: &[u8]) -> io::Result<Vec<u8>> {
+    let text = match kind(path) {
+        Some(Kind::Html) => html_text(&String::from_utf8_lossy(content)),
+        Some(Kind::Pdf) => pdf_text(content)?,
+        Some(Kind::Docx) => docx_text(content)?,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a document text can be extracted from", path.display()),
+            ))
+        }
+    };
+    Ok(tidy(&text).into_bytes())
+}
+
+fn kind(path: &Path) -> Option<Kind> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    EXTENSIONS.iter().find(|(known, _)| *known == extension).map(|(_, kind)| *kind)
+}
+
+/// The text of an HTML page: scripts and styles are left out, blocks start
+/// new lines, list items are marked with `- ` and headings with `#`. Whitespace is collapsed
+/// as a browser would, except inside `<pre>`.
+fn html_text(html: &str) -> String {
+    static HIDDEN: OnceLock<Regex> = OnceLock::new();
+    static TAG: OnceLock<Regex> = OnceLock::new();
+    static SPACE: OnceLock<Regex> = OnceLock::new();
+    let hidden = HIDDEN.get_or_init(|| {
+        Regex::new(r"(?is)<!--.*?-->|<(script|style|noscript|template)\b.*?</(script|style|noscript|template)\s*>")
+            .unwrap()
+    });
+    let tag = TAG.get_or_init(|| Regex::new(r"(?s)<(/?)([a-zA-Z][a-zA-Z0-9]*)[^>]*>|<[!?][^>]*>").unwrap());
+    let space = SPACE.get_or_init(|| Regex::new(r"\s+").unwrap());
+
+    let html = hidden.replace_all(html, "");
+    let mut text = String::new();
+    let mut pre = 0usize;
+    let mut last = 0;
+    for cap in tag.captures_iter(&html) {
+        let whole = cap.get(0).unwrap();
+        let between = decode_entities(&html[last..whole.start()]);
+        if pre > 0 {
+            text.push_str(&between);
+        } else {
+            push_collapsed(&mut text, &space.replace_all(&between, " "));
+        }
+        last = whole.end();
+
+        let Some(name) = cap.get(2).map(|name| name.as_str().to_lowercase()) else {
+            continue;
+        };
+        let closing = &cap[1] == "/";
+        if name == "pre" {
+            pre = if closing { pre.saturating_sub(1) } else { pre + 1 };
+        }
+        if name == "br" {
+            text.push('\n');
+        } else if PARAGRAPHS.contains(&name.as_str()) {
+            end_line(&mut text);
+            if !text.ends_with("\n\n") {
+                text.push('\n');
+            }
+        } else if BLOCKS.contains(&name.as_str()) {
+            end_line(&mut text);
+        } else if (name == "td" || name == "th") && !closing {
+            // Cells after the first in a row are separated by a tab
+            let row_start = text.trim_end_matches(' ').ends_with('\n');
+            text.truncate(text.trim_end_matches(' ').len());
+            if !row_start && !text.is_empty() {
+                text.push('\t');
+            }
+        }
+        if !closing {
+            match name.as_str() {
+                "li" => text.push_str("- "),
+                "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                    text.push_str(&"#".repeat(name[1..].parse().unwrap_or(1)));
+                    text.push(' ');
+                }
+                _ => {}
+            }
+        }
+    }
+    push_collapsed(&mut text, &space.replace_all(&decode_entities(&html[last..]), " "));
+    text
+}
+
+fn end_line(text: &mut String) {
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+// Collapsed whitespace at the start of a line is left out
+fn push_collapsed(text: &mut String, collapsed: &str) {
+    if text.is_empty() || text.ends_with('\n') {
+        text.push_str(collapsed.trim_start());
+    } else {
+        text.push_str(collapsed);
+    }
+}
+
+/// The text of a PDF. pdf-extract panics on some malformed or unusual
+/// files, which only costs us that one file.
+fn pdf_text(content: &[u8]) -> io::Result<String> {
+    match panic::catch_unwind(AssertUnwindSafe(|| pdf_extract::extract_text_from_mem(content))) {
+        Ok(Ok(text)) => Ok(text),
+        Ok(Err(e)) => Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+        Err(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "the PDF could not be read")),
+    }
+}
+
+/// The text of a Word document, one line to a paragraph, from the
+/// `word/document.xml` inside it.
+fn docx_text(content: &[u8]) -> io::Result<String> {
+    static SKIPPED: OnceLock<Regex> = OnceLock::new();
+    static BREAK: OnceLock<Regex> = OnceLock::new();
+    static TAG: OnceLock<Regex> = OnceLock::new();
+    // Field codes and deleted revisions are not part of the visible text
+    let skipped =
+        SKIPPED.get_or_init(|| Regex::new(r"(?s)<w:(instrText|delText)\b[^>]*>.*?</w:(instrText|delText)>").unwrap());
+    let line_break = BREAK.get_or_init(|| Regex::new(r"</w:p>|<w:(br|cr)\b[^>]*/>").unwrap());
+    let tag = TAG.get_or_init(|| Regex::new(r"<[^>]*>").unwrap());
+
+    let invalid = |e: zip::result::ZipError| io::Error::new(io::ErrorKind::InvalidData, e);
+    let mut archive = zip::ZipArchive::new(Cursor::new(content)).map_err(invalid)?;
+    let mut xml = Vec::new();
+    archive.by_name("word/document.xml").map_err(invalid)?.read_to_end(&mut xml)?;
+    let xml = String::from_utf8_lossy(&xml).into_owned();
+
+    let xml = skipped.replace_all(&xml, "");
+    let xml = line_break.replace_all(&xml, "\n");
+    let xml = xml.replace("<w:tab/>", "\t");
+    Ok(decode_entities(&tag.replace_all(&xml, "")))
+}
+
+/// `text` with character references and the common named entities
+/// replaced by the characters they stand for.
+fn decode_entities(text: &str) -> String {
+    static ENTITY: OnceLock<Regex> = OnceLock::new();
+    let entity = ENTITY.get_or_init(|| Regex::new(r"&(#[0-9]+|#[xX][0-9a-fA-F]+|[a-zA-Z]+);").unwrap());
+    entity
+        .replace_all(text, |cap: &regex::Captures| {
+            let name = &cap[1];
+            let c = match name {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                _ if name.starts_with("#x") || name.starts_with("#X") => {
+                    u32::from_str_radix(&name[2..], 16).ok().and_then(char::from_u32)
+                }
+                _ if name.starts_with('#') => name[1..].parse().ok().and_then(char::from_u32),
+                _ => None,
+            };
+            c.map_or_else(|| cap[0].to_string(), String::from)
+        })
+        .into_owned()
+}
+
+/// `text` without trailing spaces, runs of blank lines or a form feed
+/// between pages, ending in a single newline.
+fn tidy(text: &str) -> String {
+    let mut tidied = String::new();
+    let mut blank = false;
+    for line in text.replace('\u{c}', "\n").lines().map(str::trim_end) {
+        if line.trim().is_empty() {
+            blank = !tidied.is_empty();
+            continue;
+        }
+        if blank {
+            tidied.push('\n');
+            blank = false;
+        }
+        tidied.push_str(line);
+        tidied.push('\n');
+    }
+    tidied
+}
+
@@ -0,0 +1,132 @@
+// Fetching pull requests from the GitHub API. This needs the `curl` command
+// installed, which makes the requests.
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+const API: &str = "https://api.github.com";
+
+/// A pull request named by a `https://github.com/OWNER/REPO/pull/N` URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PullRequest {
+    pub owner: String,
+    pub repo: String,
+    pub number: u64,
+}
+
+impl PullRequest {
+    /// Parse a pull request URL, ignoring any trailing tab such as `/files`,
+    /// query string or fragment.
+    pub fn from_url(url: &str) -> Option<Self> {
+        let rest = url
+            .strip_prefix("https://github.com/")
+            .or_else(|| url.strip_prefix("http://github.com/"))?;
+        let rest = rest.split(['?', '#']).next().unwrap_or_default();
+
+        let mut parts = rest.split('/');
+        let owner = parts.next().filter(|part| !part.is_empty())?;
+        let repo = parts.next().filter(|part| !part.is_empty())?;
+        if parts.next() != Some("pull") {
+            return None;
+        }
+        let number = parts.next()?.parse().ok()?;
+
+        Some(PullRequest {
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+            number,
+        })
+    }
+
+    /// The path the diff is shown under in the output.
+    pub fn label(&self) -> String {
+        format!("{}-pull-{}.diff", self.repo, self.number)
+    }
+
+    fn api_url(&self) -> String {
+        format!("{}/repos/{}/{}/pulls/{}", API, self.owner, self.repo, self.number)
+    }
+}
+
+/// API access, authenticated with `GITHUB_TOKEN` or `GH_TOKEN` when set.
+pub struct Client {
+    token: Option<String>,
+}
+
+impl Client {
+    pub fn from_env() -> Self {
+        let token = std::env::var("GITHUB_TOKEN")
+            .or_else(|_| std::env::var("GH_TOKEN"))
+            .ok()
+            .filter(|token| !token.is_empty());
+        Client { token }
+    }
+
+    /// The pull request as a unified diff.
+    pub fn diff(&self, pr: &PullRequest) -> io::Result<String> {
+        let body = self.get(&pr.api_url(), "application/vnd.github.diff")?;
+        String::from_utf8(body).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// The repository (`owner/name`) and commit of the pull request's head.
+    pub fn head(&self, pr: &PullRequest) -> io::Result<(String, String)> {
+        let body = self.get(&pr.api_url(), "application/vnd.github+json")?;
+        let json: serde_json::Value =
+            serde_json::from_slice(&body).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        match (json["head"]["repo"]["full_name"].as_str(), json["head"]["sha"].as_str()) {
+            (Some(repo), Some(sha)) => Ok((repo.to_string(), sha.to_string())),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no head commit in the response for pull request #{}", pr.number),
+            )),
+        }
+    }
+
+    /// The contents of `path` in `repo` at commit `sha`.
+    pub fn file(&self, repo: &str, sha: &str, path: &str) -> io::Result<Vec<u8>> {
+        let url = format!("{}/repos/{}/contents/{}?ref={}", API, repo, encode_path(path), sha);
+        self.get(&url, "application/vnd.github.raw")
+    }
+
+    fn get(&self, url: &str, accept: &str) -> io::Result<Vec<u8>> {
+        // The token goes in through stdin so it never shows up in `ps`
+        let mut child = Command::new("curl")
+            .args(["--silent", "--show-error", "--fail", "--location"])
+            .args(["--header", &format!("Accept: {}", accept)])
+            .args(["--header", "@-", url])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| io::Error::new(e.kind(), format!("could not run curl to fetch {}: {}", url, e)))?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            if let Some(token) = &self.token {
+                writeln!(stdin, "Authorization: Bearer {}", token)?;
+            }
+        }
+
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            let message = String::from_utf8_lossy(&output.stderr);
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("fetching {} failed: {}", url, message.trim()),
+            ));
+        }
+
+        Ok(output.stdout)
+    }
+}
+
+// Percent-encode everything but unreserved characters and the separators
+fn encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
@@ -0,0 +1,140 @@
+// Finding the local files a source file imports, for --mention-excluded.
+// Only relative imports that resolve to an existing file are followed;
+// anything from a package registry or the standard library is ignored.
+use crate::vfs::FileSystem;
+use regex::Regex;
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+
+const JS_EXTENSIONS: &[&str] = &["js", "jsx", "ts", "tsx", "mjs", "cjs"];
+
+/// The existing files `path` imports, normalized and in the order the
+/// imports appear.
+pub(crate) fn imported_files(fs: &dyn FileSystem, path: &Path, content: &str) -> Vec<PathBuf> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+
+    let candidates = match extension {
+        "py" => python_candidates(dir, content),
+        "rs" => rust_candidates(path, dir, content),
+        "c" | "h" | "cc" | "cpp" | "hpp" => include_candidates(dir, content),
+        e if JS_EXTENSIONS.contains(&e) => js_candidates(dir, content),
+        _ => Vec::new(),
+    };
+
+    let mut files: Vec<PathBuf> = Vec::new();
+    for choices in candidates {
+        let Some(file) = choices.into_iter().map(|c| normalize(&c)).find(|c| fs.is_file(c)) else {
+            continue;
+        };
+        if !files.contains(&file) && file != normalize(path) {
+            files.push(file);
+        }
+    }
+    files
+}
+
+/// Resolve `.` and `..` components without touching the filesystem.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if matches!(normalized.components().next_back(), Some(Component::Normal(_))) {
+                    normalized.pop();
+                } else {
+                    normalized.push("..");
+                }
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+// Each import gives a list of places it may live, tried in order
+fn python_candidates(dir: &Path, content: &str) -> Vec<Vec<PathBuf>> {
+    static FROM: OnceLock<Regex> = OnceLock::new();
+    static IMPORT: OnceLock<Regex> = OnceLock::new();
+    let from = FROM.get_or_init(|| Regex::new(r"(?m)^\s*from\s+(\.*)([\w.]*)\s+import\s+([\w, ]+)").unwrap());
+    let import = IMPORT.get_or_init(|| Regex::new(r"(?m)^\s*import\s+([\w.]+)").unwrap());
+
+    let module = |base: &Path, name: &str| {
+        let stem = base.join(name.replace('.', "/"));
+        vec![stem.with_extension("py"), stem.join("__init__.py")]
+    };
+
+    let mut candidates = Vec::new();
+    for cap in from.captures_iter(content) {
+        let dots = cap[1].len();
+        let base = if dots == 0 {
+            dir.to_path_buf()
+        } else {
+            (1..dots).fold(dir.to_path_buf(), |base, _| base.join(".."))
+        };
+
+        if cap[2].is_empty() {
+            // `from . import a, b` names sibling modules
+            for name in cap[3].split(',').map(str::trim).filter(|name| !name.is_empty()) {
+                candidates.push(module(&base, name));
+            }
+        } else {
+            candidates.push(module(&base, &cap[2]));
+        }
+    }
+    for cap in import.captures_iter(content) {
+        candidates.push(module(dir, &cap[1]));
+    }
+    candidates
+}
+
+fn js_candidates(dir: &Path, content: &str) -> Vec<Vec<PathBuf>> {
+    static SPECIFIER: OnceLock<Regex> = OnceLock::new();
+    let specifier = SPECIFIER
+        .get_or_init(|| Regex::new(r#"(?:\bfrom|\bimport|\brequire\()\s*['"](\.{1,2}/[^'"]+)['"]"#).unwrap());
+
+    specifier
+        .captures_iter(content)
+        .map(|cap| {
+            let target = dir.join(&cap[1]);
+            let mut choices = vec![target.clone()];
+            for extension in JS_EXTENSIONS {
+                choices.push(PathBuf::from(format!("{}.{}", target.display(), extension)));
+            }
+            for extension in JS_EXTENSIONS {
+                choices.push(target.join(format!("index.{}", extension)));
+            }
+            choices
+        })
+        .collect()
+}
+
+fn rust_candidates(path: &Path, dir: &Path, content: &str) -> Vec<Vec<PathBuf>> {
+    static MOD: OnceLock<Regex> = OnceLock::new();
+    let declaration = MOD.get_or_init(|| Regex::new(r"(?m)^\s*(?:pub(?:\([^)]*\))?\s+)?mod\s+(\w+)\s*;").unwrap());
+
+    // Modules declared in foo.rs live in foo/, except for the crate roots and mod.rs
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+    let module_dir = match stem {
+        "lib" | "main" | "mod" => dir.to_path_buf(),
+        _ => dir.join(stem),
+    };
+
+    declaration
+        .captures_iter(content)
+        .map(|cap| {
+            vec![
+                module_dir.join(format!("{}.rs", &cap[1])),
+                module_dir.join(&cap[1]).join("mod.rs"),
+            ]
+        })
+        .collect()
+}
+
+fn include_candidates(dir: &Path, content: &str) -> Vec<Vec<PathBuf>> {
+    static INCLUDE: OnceLock<Regex> = OnceLock::new();
+    let include = INCLUDE.get_or_init(|| Regex::new(r#"(?m)^\s*#\s*include\s*"([^"]+)""#).unwrap());
+
+    include.captures_iter(content).map(|cap| vec![dir.join(&cap[1])]).collect()
+}
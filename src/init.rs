@@ -0,0 +1,117 @@
+// The starter configuration written by `files-to-prompt init`
+use crate::suggest::{self, Suggestion};
+use crate::vfs::FileSystem;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Component, Path, PathBuf};
+
+/// Name of the project configuration file.
+pub const CONFIG_FILE: &str = ".files-to-prompt.toml";
+
+/// How many languages and directories are listed in the summary comments.
+const SUMMARY_LENGTH: usize = 5;
+
+/// A configuration for the project at `root`, whose included files are
+/// `files`: a summary of what was found, then a default profile ignoring
+/// everything `suggest-ignores` would.
+pub fn starter_config(root: &Path, files: &[PathBuf], fs: &dyn FileSystem) -> String {
+    let suggestions = suggest::suggest_ignores(files, fs);
+    let mut config = String::new();
+
+    config.push_str("# Starter configuration for files-to-prompt, written by `files-to-prompt init`.\n");
+    config.push_str("# Each setting mirrors the command line option of the same name.\n");
+    config.push_str("#\n");
+
+    // Only what is left after the suggested ignores says what the project is
+    let ignored: Vec<&PathBuf> = suggestions.iter().flat_map(|suggestion| &suggestion.files).collect();
+    let kept: Vec<PathBuf> = files.iter().filter(|file| !ignored.contains(file)).cloned().collect();
+
+    let languages = languages(&kept);
+    if !languages.is_empty() {
+        let listed: Vec<String> = languages
+            .iter()
+            .take(SUMMARY_LENGTH)
+            .map(|(extension, count)| format!("{} ({} file{})", extension, count, if *count == 1 { "" } else { "s" }))
+            .collect();
+        writeln!(config, "# Languages found: {}", listed.join(", ")).unwrap();
+    }
+
+    let directories = largest_directories(root, files, fs);
+    if !directories.is_empty() {
+        let listed: Vec<String> = directories
+            .iter()
+            .take(SUMMARY_LENGTH)
+            .map(|(name, files, bytes)| format!("{} ({})", name, suggest::describe_removed(*files, *bytes)))
+            .collect();
+        writeln!(config, "# Largest directories: {}", listed.join(", ")).unwrap();
+    }
+
+    config.push_str("\n[profile.default]\n");
+    if !languages.is_empty() {
+        let extensions: Vec<String> = languages.iter().take(SUMMARY_LENGTH).map(|(e, _)| quote(e)).collect();
+        config.push_str("# Uncomment to only include the main languages:\n");
+        writeln!(config, "# extension = [{}]", extensions.join(", ")).unwrap();
+    }
+    write_ignores(&mut config, &suggestions);
+
+    config
+}
+
+fn write_ignores(config: &mut String, suggestions: &[Suggestion]) {
+    if suggestions.is_empty() {
+        config.push_str("ignore = []\n");
+        return;
+    }
+
+    config.push_str("ignore = [\n");
+    let mut reason = None;
+    for suggestion in suggestions {
+        if reason != Some(suggestion.reason) {
+            reason = Some(suggestion.reason);
+            writeln!(config, "    # {}", suggestion.reason.describe()).unwrap();
+        }
+        writeln!(config, "    {},", quote(suggestion.ignore_flag())).unwrap();
+    }
+    config.push_str("]\n");
+}
+
+/// File extensions by how many files have them, most common first.
+fn languages(files: &[PathBuf]) -> Vec<(String, usize)> {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for file in files {
+        if let Some(extension) = file.extension() {
+            *counts.entry(extension.to_string_lossy().to_lowercase()).or_default() += 1;
+        }
+    }
+
+    let mut languages: Vec<(String, usize)> = counts.into_iter().collect();
+    languages.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    languages
+}
+
+/// Top-level directories under `root` with their file counts and sizes,
+/// largest first.
+fn largest_directories(root: &Path, files: &[PathBuf], fs: &dyn FileSystem) -> Vec<(String, usize, u64)> {
+    let mut sizes: BTreeMap<String, (usize, u64)> = BTreeMap::new();
+    for file in files {
+        let relative = file.strip_prefix(root).unwrap_or(file);
+        let mut components = relative.components().filter(|c| matches!(c, Component::Normal(_)));
+        let (Some(top), Some(_)) = (components.next(), components.next()) else {
+            continue;
+        };
+
+        let entry = sizes.entry(top.as_os_str().to_string_lossy().into_owned()).or_default();
+        entry.0 += 1;
+        entry.1 += fs.read(file).map(|content| content.len() as u64).unwrap_or(0);
+    }
+
+    let mut directories: Vec<(String, usize, u64)> =
+        sizes.into_iter().map(|(name, (files, bytes))| (name, files, bytes)).collect();
+    directories.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(&b.0)));
+    directories
+}
+
+// A TOML basic string
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -0,0 +1,87 @@
+// Picking the files worth including from a project's language mix, for
+// --auto-extensions. The dominant source languages are kept along with the
+// configuration and documentation around them; assets and data are not.
+use crate::suggest::LOCKFILES;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Source languages and the extensions their files use.
+const LANGUAGES: &[(&str, &[&str])] = &[
+    ("rust", &["rs"]),
+    ("python", &["py", "pyi"]),
+    ("javascript", &["js", "jsx", "mjs", "cjs"]),
+    ("typescript", &["ts", "tsx", "mts", "cts"]),
+    ("go", &["go"]),
+    ("java", &["java"]),
+    ("kotlin", &["kt", "kts"]),
+    ("c", &["c", "h"]),
+    ("cpp", &["cc", "cpp", "cxx", "hh", "hpp", "hxx"]),
+    ("csharp", &["cs"]),
+    ("ruby", &["rb"]),
+    ("php", &["php"]),
+    ("swift", &["swift"]),
+    ("scala", &["scala"]),
+    ("elixir", &["ex", "exs"]),
+    ("haskell", &["hs"]),
+    ("lua", &["lua"]),
+    ("dart", &["dart"]),
+    ("shell", &["sh", "bash", "zsh"]),
+    ("web", &["html", "css", "scss", "vue", "svelte"]),
+];
+
+/// Configuration and documentation kept whatever the languages are.
+const CONFIG_AND_DOCS_EXTENSIONS: &[&str] = &["md", "rst", "txt", "toml", "yaml", "yml", "json", "ini", "cfg"];
+
+/// Build and container files known by name rather than extension.
+const CONFIG_NAMES: &[&str] = &["Makefile", "Dockerfile", "Justfile", "Gemfile", "Rakefile"];
+
+/// A language is dominant with at least this share of the source files.
+const DOMINANT_SHARE: f64 = 0.1;
+
+/// The source languages among `files` with how many files each has,
+/// most common first.
+pub(crate) fn language_mix(files: &[PathBuf]) -> Vec<(&'static str, usize)> {
+    let mut counts: BTreeMap<&'static str, usize> = BTreeMap::new();
+    for file in files {
+        if let Some(language) = language(file) {
+            *counts.entry(language).or_default() += 1;
+        }
+    }
+
+    let mut mix: Vec<(&'static str, usize)> = counts.into_iter().collect();
+    mix.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+    mix
+}
+
+/// The languages in `mix` worth including: the most common one and any
+/// other with a real share of the files.
+pub(crate) fn dominant(mix: &[(&'static str, usize)]) -> Vec<&'static str> {
+    let total: usize = mix.iter().map(|(_, count)| count).sum();
+    mix.iter()
+        .enumerate()
+        .filter(|(i, (_, count))| *i == 0 || *count as f64 >= total as f64 * DOMINANT_SHARE)
+        .map(|(_, (language, _))| *language)
+        .collect()
+}
+
+/// Whether `path` is source in one of `languages`, or configuration or
+/// documentation other than a lockfile.
+pub(crate) fn is_selected(path: &Path, languages: &[&str]) -> bool {
+    if language(path).is_some_and(|language| languages.contains(&language)) {
+        return true;
+    }
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    if LOCKFILES.contains(&name) {
+        return false;
+    }
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+    CONFIG_AND_DOCS_EXTENSIONS.contains(&extension.as_str()) || CONFIG_NAMES.contains(&name)
+}
+
+fn language(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    LANGUAGES
+        .iter()
+        .find(|(_, extensions)| extensions.contains(&extension.as_str()))
+        .map(|(language, _)| *language)
+}
@@ -1,4 +1,47 @@
 // Export the cli module
+pub mod apply;
 pub mod cli;
+pub mod collect;
+pub mod error;
+pub mod github;
+pub mod init;
+pub mod patch;
+pub mod picker;
+pub mod profile;
+pub mod stats;
+pub mod suggest;
+pub mod template;
+pub mod vfs;
 
-// No need to re-export run if the module itself is public
\ No newline at end of file
+mod clipboard;
+mod comments;
+mod config;
+mod daemon;
+mod decompress;
+mod editorconfig;
+mod encoding;
+mod extract;
+mod imports;
+mod languages;
+mod manifest;
+mod mime;
+mod names;
+mod notebook;
+mod remote;
+mod secrets;
+mod sfc;
+mod symbol_index;
+mod timestamp;
+mod tree;
+
+pub use collect::{
+    collect, collect_split, collect_with, entries, explain_rules, list_files, render, BinaryFiles, BudgetOrder, FileEntry,
+    Formatter, Options, OutputFormat, PathSettings, Progress, Scorer, SortOrder, SymbolFilter, Truncate, Walker,
+};
+pub use template::Template;
+
+/// Version of the layout of machine-readable outputs. It is only bumped when
+/// a field is removed or changes meaning; new fields may appear in any release.
+pub const SCHEMA_VERSION: u32 = 1;
+
+// No need to re-export run if the module itself is public
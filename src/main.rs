@@ -1,9 +1,23 @@
 // src/main.rs
-use files_to_prompt::cli;
+use files_to_prompt::cli::{self, Exit};
+use std::io;
+
+/// Exit code for a reader that went away, as a shell reports a process
+/// killed by SIGPIPE (128 + 13).
+const BROKEN_PIPE_EXIT: i32 = 141;
 
 fn main() {
-    if let Err(err) = cli::run() {
-        eprintln!("Error: {}", err);
-        std::process::exit(1);
+    match cli::run() {
+        Ok(()) => {}
+        // Piped into `head` or a reader that quit: nothing left to say
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => std::process::exit(BROKEN_PIPE_EXIT),
+        Err(err) => match err.get_ref().and_then(|inner| inner.downcast_ref::<Exit>()) {
+            // Whatever failed has said why
+            Some(Exit(code)) => std::process::exit(*code),
+            None => {
+                eprintln!("Error: {}", err);
+                std::process::exit(1);
+            }
+        },
     }
-}
\ No newline at end of file
+}
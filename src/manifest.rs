@@ -0,0 +1,88 @@
+// Selection manifests: the files a run included, each with its size, a
+// hash of its content and when it was modified, written by --write-manifest
+// and read back by --exact-manifest to build the same prompt from exactly
+// the same files.
+use crate::collect::content_hash;
+use crate::error::Context;
+use crate::names;
+use crate::timestamp;
+use crate::vfs::FileSystem;
+use serde_json::{json, Value};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Write a manifest of `files`, leaving out directories. Modification times
+/// are in UTC unless `local_time` is set, and only recorded, not checked.
+pub(crate) fn write(files: &[PathBuf], fs: &dyn FileSystem, local_time: bool, out: &mut dyn Write) -> io::Result<()> {
+    let mut entries = Vec::new();
+    for file in files.iter().filter(|file| fs.is_file(file)) {
+        let content = fs.read(file)?;
+        let mut entry = json!({
+            "path": names::display_path(file),
+            "size": content.len(),
+            "hash": content_hash(&content),
+        });
+        // A name that is not UTF-8 is only shown escaped in `path`; its
+        // bytes are kept in hex to find the file again
+        if !names::is_utf8(file) {
+            let bytes = names::name_bytes(file.as_os_str());
+            entry["path_bytes"] = json!(bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<String>());
+        }
+        if let Some(modified) = fs.modified(file) {
+            entry["modified"] = json!(timestamp::format(modified, local_time));
+        }
+        entries.push(entry);
+    }
+    let manifest = json!({
+        "schema_version": crate::SCHEMA_VERSION,
+        "tool_version": env!("CARGO_PKG_VERSION"),
+        "hash_algorithm": "fnv1a-64",
+        "files": entries,
+    });
+    writeln!(out, "{}", serde_json::to_string_pretty(&manifest).unwrap_or_default())
+}
+
+/// The files listed in the manifest at `path`, in order, failing with every
+/// file that is missing or no longer has the content it had.
+pub(crate) fn read_exact(path: &Path, fs: &dyn FileSystem) -> io::Result<Vec<PathBuf>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), message));
+    let manifest: Value = serde_json::from_str(&fs.read_to_string(path).context("read", path)?).map_err(|e| invalid(e.to_string()))?;
+    // Manifests written before the shared schema version called it `version`
+    let version = manifest.get("schema_version").or(manifest.get("version")).and_then(Value::as_u64);
+    if version != Some(u64::from(crate::SCHEMA_VERSION)) {
+        return Err(invalid(format!("expected a manifest of schema version {}", crate::SCHEMA_VERSION)));
+    }
+    let entries = manifest["files"].as_array().ok_or_else(|| invalid("expected a list of files".to_string()))?;
+
+    let mut files = Vec::new();
+    let mut problems = Vec::new();
+    for entry in entries {
+        let (Some(name), Some(hash)) = (entry["path"].as_str(), entry["hash"].as_str()) else {
+            return Err(invalid("expected each file to have a path and a hash".to_string()));
+        };
+        let file = match entry["path_bytes"].as_str() {
+            Some(hex) => {
+                let bytes = from_hex(hex).ok_or_else(|| invalid(format!("{} has invalid path_bytes", name)))?;
+                names::path_from_bytes(&bytes)
+            }
+            None => PathBuf::from(name),
+        };
+        match fs.read(&file) {
+            Ok(content) if content_hash(&content) == hash => {}
+            Ok(_) => problems.push(format!("{} has changed", name)),
+            Err(_) => problems.push(format!("{} is missing", name)),
+        }
+        files.push(file);
+    }
+    if !problems.is_empty() {
+        return Err(invalid(format!("the files no longer match: {}", problems.join(", "))));
+    }
+    Ok(files)
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
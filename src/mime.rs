@@ -0,0 +1,115 @@
+// Content types for --mime, sniffed from a file's leading bytes before its
+// extension is considered, so extensionless scripts and misnamed files are
+// typed by what they hold.
+use std::path::Path;
+
+/// Signatures of binary formats, checked against the start of the content.
+const MAGIC: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"\xfd7zXZ\x00", "application/x-xz"),
+    (b"\x7fELF", "application/x-executable"),
+    (b"\x00asm", "application/wasm"),
+];
+
+/// Text types known by extension. Other text is `text/plain`.
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("json", "application/json"),
+    ("xml", "application/xml"),
+    ("yaml", "application/yaml"),
+    ("yml", "application/yaml"),
+    ("toml", "application/toml"),
+    ("svg", "image/svg+xml"),
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("csv", "text/csv"),
+    ("md", "text/markdown"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("cjs", "text/javascript"),
+    ("ts", "text/x-typescript"),
+    ("py", "text/x-python"),
+    ("rb", "text/x-ruby"),
+    ("pl", "text/x-perl"),
+    ("sh", "text/x-shellscript"),
+    ("bash", "text/x-shellscript"),
+    ("rs", "text/x-rust"),
+    ("go", "text/x-go"),
+    ("java", "text/x-java"),
+    ("c", "text/x-c"),
+    ("h", "text/x-c"),
+    ("cpp", "text/x-c++"),
+];
+
+/// Interpreters named on a `#!` line and the type of the script they run.
+const INTERPRETERS: &[(&str, &str)] = &[
+    ("python", "text/x-python"),
+    ("node", "text/javascript"),
+    ("ruby", "text/x-ruby"),
+    ("perl", "text/x-perl"),
+    ("sh", "text/x-shellscript"),
+    ("bash", "text/x-shellscript"),
+    ("zsh", "text/x-shellscript"),
+    ("dash", "text/x-shellscript"),
+];
+
+/// The content type of `content`, read from `path`: a binary signature,
+/// then a `#!` line, then the extension, then a look at the text itself.
+pub(crate) fn sniff(path: &Path, content: &[u8]) -> &'static str {
+    if let Some((_, mime)) = MAGIC.iter().find(|(magic, _)| content.starts_with(magic)) {
+        return mime;
+    }
+    let Ok(text) = std::str::from_utf8(content) else {
+        return "application/octet-stream";
+    };
+    if text.contains('\0') {
+        return "application/octet-stream";
+    }
+
+    if let Some(mime) = text.strip_prefix("#!").and_then(interpreter_type) {
+        return mime;
+    }
+
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+    if let Some((_, mime)) = EXTENSIONS.iter().find(|(known, _)| *known == extension) {
+        return mime;
+    }
+
+    let start = text.trim_start();
+    if start.starts_with("<?xml") {
+        "application/xml"
+    } else if start.starts_with(['{', '[']) && serde_json::from_str::<serde_json::Value>(text).is_ok() {
+        "application/json"
+    } else {
+        "text/plain"
+    }
+}
+
+// `#!/usr/bin/python3` and `#!/usr/bin/env python3` both name python
+fn interpreter_type(line: &str) -> Option<&'static str> {
+    let line = line.lines().next().unwrap_or_default();
+    let mut words = line.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = words.find(|word| !word.starts_with('-'))?;
+    }
+
+    let name = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    INTERPRETERS.iter().find(|(known, _)| *known == name).map(|(_, mime)| *mime)
+}
+
+/// Whether `mime` matches `pattern`, which is a full type such as
+/// `application/json`, a family such as `text/*`, or `*/*`.
+pub(crate) fn matches(pattern: &str, mime: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some("*") => true,
+        Some(family) => mime.split('/').next().is_some_and(|prefix| prefix.eq_ignore_ascii_case(family)),
+        None => pattern.eq_ignore_ascii_case(mime),
+    }
+}
@@ -0,0 +1,265 @@
+// File names are kept as OsStr/Path everywhere and only converted for
+// display, so names that are not valid UTF-8 survive filtering intact.
+use regex::bytes::Regex;
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+
+/// The raw bytes of a file name as stored on disk. Platforms without byte
+/// access (Windows) fall back to the lossy UTF-8 conversion.
+pub fn name_bytes(name: &OsStr) -> Cow<'_, [u8]> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Cow::Borrowed(name.as_bytes())
+    }
+    #[cfg(not(unix))]
+    {
+        match name.to_string_lossy() {
+            Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+            Cow::Owned(s) => Cow::Owned(s.into_bytes()),
+        }
+    }
+}
+
+/// Build a path from raw bytes, e.g. paths read from stdin.
+pub fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        PathBuf::from(OsStr::from_bytes(bytes))
+    }
+    #[cfg(not(unix))]
+    {
+        PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+/// Whether `path` can be shown without loss.
+pub fn is_utf8(path: &Path) -> bool {
+    path.to_str().is_some()
+}
+
+/// Render a path for headers and messages. Bytes that are not valid UTF-8
+/// are written as `\xNN` escapes instead of U+FFFD, so two distinct names
+/// never render the same.
+pub fn display_path(path: &Path) -> String {
+    if let Some(s) = path.to_str() {
+        return s.to_string();
+    }
+
+    let bytes = name_bytes(path.as_os_str());
+    let mut output = String::new();
+    let mut rest: &[u8] = &bytes;
+
+    while !rest.is_empty() {
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                output.push_str(valid);
+                break;
+            }
+            Err(e) => {
+                let (valid, invalid) = rest.split_at(e.valid_up_to());
+                output.push_str(std::str::from_utf8(valid).unwrap_or_default());
+                let bad = e.error_len().unwrap_or(invalid.len());
+                for byte in &invalid[..bad] {
+                    output.push_str(&format!("\\x{:02X}", byte));
+                }
+                rest = &invalid[bad..];
+            }
+        }
+    }
+
+    output
+}
+
+/// Turns paths into the text shown in headers and messages: Windows
+/// verbatim prefixes such as `\\?\` are dropped, repeated separators are
+/// collapsed and, when `separator` is set, every separator is written as it.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PathRenderer {
+    pub separator: Option<char>,
+}
+
+impl PathRenderer {
+    pub fn render(&self, path: &Path) -> String {
+        let text = display_path(path);
+        let is_separator = |c: char| c == '/' || (cfg!(windows) && c == '\\');
+
+        // A UNC path keeps the two separators that start it
+        let (prefix, rest) = match strip_verbatim(&text) {
+            Some(stripped) => stripped,
+            None if text.starts_with("\\\\") && cfg!(windows) => ("\\\\".to_string(), &text[2..]),
+            None => (String::new(), &text[..]),
+        };
+
+        let mut rendered = prefix;
+        let mut previous_separator = false;
+        for c in rest.chars() {
+            if is_separator(c) {
+                if !previous_separator {
+                    rendered.push(c);
+                }
+                previous_separator = true;
+            } else {
+                rendered.push(c);
+                previous_separator = false;
+            }
+        }
+
+        if let Some(separator) = self.separator {
+            rendered = rendered.chars().map(|c| if is_separator(c) { separator } else { c }).collect();
+        }
+        rendered
+    }
+}
+
+// `\\?\C:\dir` is `C:\dir`, and `\\?\UNC\server\share` is `\\server\share`
+fn strip_verbatim(text: &str) -> Option<(String, &str)> {
+    if !cfg!(windows) {
+        return None;
+    }
+    if let Some(rest) = text.strip_prefix("\\\\?\\UNC\\") {
+        return Some(("\\\\".to_string(), rest));
+    }
+    text.strip_prefix("\\\\?\\").map(|rest| (String::new(), rest))
+}
+
+/// `glob` with each backslash written between directories, as on Windows,
+/// turned into `/`, so a pattern reads the same on every platform. With
+/// `escapes`, as in .gitignore, a backslash before a glob character, another
+/// backslash, `#`, `!` or a space still escapes it.
+pub fn normalize_separators(glob: &str, escapes: bool) -> Cow<'_, str> {
+    if !glob.contains('\\') {
+        return Cow::Borrowed(glob);
+    }
+    if !escapes {
+        return Cow::Owned(glob.replace('\\', "/"));
+    }
+    let mut normalized = String::with_capacity(glob.len());
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(&next)) if "*?[]\\#! ".contains(next) => {
+                normalized.push(c);
+                normalized.push(next);
+                chars.next();
+            }
+            ('\\', _) => normalized.push('/'),
+            _ => normalized.push(c),
+        }
+    }
+    Cow::Owned(normalized)
+}
+
+/// A shell-style glob (`*`, `?`, `[abc]`, `[!abc]`) matched against the raw
+/// bytes of a file name. Both the glob and UTF-8 names are compared in NFC,
+/// so `café` matches whether the name was stored precomposed or, as macOS
+/// tends to, decomposed.
+pub struct NamePattern {
+    regex: Regex,
+}
+
+impl NamePattern {
+    pub fn new(glob: &str) -> Option<Self> {
+        let glob: String = glob.nfc().collect();
+        Regex::new(&format!("^{}$", glob_to_regex(&glob, false)?))
+            .ok()
+            .map(|regex| NamePattern { regex })
+    }
+
+    /// A glob matched against a `/`-separated relative path, as in
+    /// .gitignore: `*`, `?` and classes stop at `/`, `**/` and `/**/` span
+    /// any number of directories, a trailing `/**` matches everything below,
+    /// and a backslash makes the next character literal.
+    pub fn new_path(glob: &str) -> Option<Self> {
+        let glob: String = glob.nfc().collect();
+        Regex::new(&format!("^{}$", glob_to_regex(&glob, true)?))
+            .ok()
+            .map(|regex| NamePattern { regex })
+    }
+
+    /// Like `new`, but an invalid glob matches every name.
+    pub fn new_or_match_all(glob: &str) -> Self {
+        NamePattern::new(glob).unwrap_or_else(|| NamePattern::new("*").unwrap())
+    }
+
+    pub fn matches(&self, name: &OsStr) -> bool {
+        self.matches_bytes(&name_bytes(name))
+    }
+
+    pub fn matches_bytes(&self, name: &[u8]) -> bool {
+        match std::str::from_utf8(name) {
+            Ok(s) if !is_nfc(s) => self.regex.is_match(s.nfc().collect::<String>().as_bytes()),
+            _ => self.regex.is_match(name),
+        }
+    }
+}
+
+// `*` and `?` also consume single bytes that are not valid UTF-8
+fn glob_to_regex(glob: &str, path: bool) -> Option<String> {
+    let chars: Vec<char> = glob.chars().collect();
+    let any = if path { "(?:[^/]|(?-u:[^/]))" } else { "(?:(?s:.)|(?s-u:.))" };
+    let mut regex = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '*' if path && chars.get(i + 1) == Some(&'*') => {
+                let at_start = i == 0 || chars[i - 1] == '/';
+                let at_end = i + 2 == chars.len();
+                if at_start && chars.get(i + 2) == Some(&'/') {
+                    // Zero or more leading directories
+                    regex.push_str("(?:(?s-u:.)*/)?");
+                    i += 2;
+                } else if at_start && at_end && i > 0 {
+                    regex.push_str("(?s-u:.)*");
+                    i += 1;
+                } else {
+                    regex.push_str(&format!("{}*", any));
+                    i += 1;
+                }
+            }
+            '*' => regex.push_str(&format!("{}*", any)),
+            '?' => regex.push_str(any),
+            '\\' if path && i + 1 < chars.len() => {
+                i += 1;
+                regex.push_str(&regex::escape(&chars[i].to_string()));
+            }
+            '[' => {
+                let mut end = i + 1;
+                if chars.get(end) == Some(&'!') {
+                    end += 1;
+                }
+                if chars.get(end) == Some(&']') {
+                    end += 1;
+                }
+                while end < chars.len() && chars[end] != ']' {
+                    end += 1;
+                }
+                if end >= chars.len() {
+                    return None;
+                }
+
+                let mut class: String = chars[i + 1..end].iter().collect();
+                if let Some(negated) = class.strip_prefix('!') {
+                    class = if path { format!("^/{}", negated) } else { format!("^{}", negated) };
+                }
+                regex.push('[');
+                for c in class.chars() {
+                    if matches!(c, '\\' | '[' | '&' | '~') {
+                        regex.push('\\');
+                    }
+                    regex.push(c);
+                }
+                regex.push(']');
+                i = end;
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+
+    Some(regex)
+}
@@ -0,0 +1,78 @@
+// Jupyter notebooks for --nbconvert. The JSON of a notebook spends most of
+// its size on metadata, so it is written as Markdown instead: markdown cells
+// as they are, code cells in fences labeled with the kernel's language, each
+// followed by its outputs unless --nb-strip-output drops them.
+use regex::Regex;
+use serde_json::Value;
+use std::sync::OnceLock;
+
+/// `content` as Markdown, or `None` when it is not a notebook in the
+/// nbformat 4 layout written by current versions of Jupyter.
+pub(crate) fn convert(content: &str, strip_output: bool) -> Option<String> {
+    let notebook: Value = serde_json::from_str(content).ok()?;
+    let cells = notebook["cells"].as_array()?;
+    let metadata = &notebook["metadata"];
+    let language = metadata["language_info"]["name"].as_str().or(metadata["kernelspec"]["language"].as_str());
+
+    let mut blocks = Vec::new();
+    for cell in cells {
+        let source = text(&cell["source"]);
+        match cell["cell_type"].as_str() {
+            Some("code") => {
+                if !source.trim().is_empty() {
+                    blocks.push(fenced(&source, language.unwrap_or("")));
+                }
+                let outputs = cell["outputs"].as_array().filter(|_| !strip_output);
+                let outputs: Vec<String> = outputs.into_iter().flatten().filter_map(output_text).collect();
+                if !outputs.is_empty() {
+                    blocks.push(format!("Output:\n\n{}", fenced(&outputs.join("\n"), "")));
+                }
+            }
+            _ if source.trim().is_empty() => {}
+            _ => blocks.push(source.trim_end().to_string()),
+        }
+    }
+    Some(blocks.join("\n\n") + "\n")
+}
+
+/// A multiline string from a notebook, stored either whole or as a list
+/// of lines.
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Array(lines) => lines.iter().filter_map(Value::as_str).collect(),
+        _ => String::new(),
+    }
+}
+
+/// What a model can read of one output of a code cell. Rich outputs
+/// without a plain text form, such as plots, are only named.
+fn output_text(output: &Value) -> Option<String> {
+    static ANSI: OnceLock<Regex> = OnceLock::new();
+    let text = match output["output_type"].as_str()? {
+        "stream" => text(&output["text"]),
+        "error" => {
+            // Tracebacks are colored with terminal escape codes
+            let ansi = ANSI.get_or_init(|| Regex::new(r"\x1b\[[0-9;]*[A-Za-z]").unwrap());
+            let traceback: Vec<&str> = output["traceback"].as_array()?.iter().filter_map(Value::as_str).collect();
+            ansi.replace_all(&traceback.join("\n"), "").into_owned()
+        }
+        _ => {
+            let data = output["data"].as_object()?;
+            match data.get("text/plain") {
+                Some(plain) => text(plain),
+                None => format!("[{} output]", data.keys().next()?),
+            }
+        }
+    };
+    let text = text.trim_end();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// `text` in a fenced code block with a fence longer than any run of
+/// backticks inside it.
+fn fenced(text: &str, language: &str) -> String {
+    let longest = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest.max(2) + 1);
+    format!("{}{}\n{}\n{}", fence, language, text.trim_end(), fence)
+}
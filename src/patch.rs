@@ -0,0 +1,35 @@
+// Reading the file list out of unified diffs for --from-patch
+use std::path::{Path, PathBuf};
+
+/// The files a unified diff leaves behind, in the order they appear. The
+/// `b/` prefix git adds to new names is stripped, and deleted files are skipped
+/// since they have no current contents.
+pub fn touched_files(patch: &str) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = Vec::new();
+
+    for line in patch.lines() {
+        let Some(target) = line.strip_prefix("+++ ") else {
+            continue;
+        };
+
+        // A tab separates the name from an optional timestamp
+        let target = target.split('\t').next().unwrap_or_default().trim_end();
+        if target == "/dev/null" {
+            continue;
+        }
+
+        let target = target.strip_prefix("b/").unwrap_or(target);
+        let path = PathBuf::from(target);
+        if !files.contains(&path) {
+            files.push(path);
+        }
+    }
+
+    files
+}
+
+/// Whether `path` looks like a patch file by its extension.
+pub fn is_patch_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "patch" || extension == "diff")
+}
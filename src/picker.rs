@@ -0,0 +1,238 @@
+// The file picker for --interactive. It draws the files that passed the
+// filters as a tree of checkboxes on the terminal, through crossterm so it
+// works on Windows consoles as well as Unix terminals. It talks to the
+// terminal itself rather than stdout, which stays free for the prompt.
+use crate::stats;
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// The terminal, when stdin and stdout may be redirected.
+const TERMINAL: &str = if cfg!(windows) { "CONOUT$" } else { "/dev/tty" };
+
+/// A file offered by the picker.
+pub struct Entry {
+    pub path: PathBuf,
+    /// The size of the file, counted towards the token estimate.
+    pub bytes: u64,
+}
+
+/// One line of the tree: a directory or a file, at a depth.
+enum Row {
+    Dir(PathBuf, usize),
+    File(usize, usize),
+}
+
+/// The keys the picker responds to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Key {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Toggle,
+    ToggleAll,
+    Done,
+    Cancel,
+    Other,
+}
+
+/// Let the user choose among `files` on the terminal. Returns whether each
+/// file was chosen, or `None` when the picker was cancelled.
+pub fn pick(files: &[Entry]) -> io::Result<Option<Vec<bool>>> {
+    let mut screen = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(TERMINAL)
+        .map_err(|_| io::Error::new(io::ErrorKind::Unsupported, "--interactive needs a terminal"))?;
+    let (width, height) = terminal::size().unwrap_or((80, 24));
+
+    terminal::enable_raw_mode()?;
+    // Restored even when drawing fails
+    let restore = Restore { screen: screen.try_clone()? };
+    execute!(screen, EnterAlternateScreen, Hide)?;
+    let mut events = std::iter::repeat_with(event::read);
+    let result = select(files, &mut events, &mut screen, height as usize, width as usize);
+    drop(restore);
+    result
+}
+
+/// Run the picker over `files`, taking key presses from `events` and
+/// drawing on a `height` by `width` `screen`. Every file starts out chosen.
+pub fn select(
+    files: &[Entry],
+    events: &mut dyn Iterator<Item = io::Result<Event>>,
+    screen: &mut dyn Write,
+    mut height: usize,
+    mut width: usize,
+) -> io::Result<Option<Vec<bool>>> {
+    let rows = tree(files);
+    let mut chosen = vec![true; files.len()];
+    let (mut cursor, mut top) = (0usize, 0usize);
+
+    loop {
+        let page = height.saturating_sub(2).max(1);
+        if cursor < top {
+            top = cursor;
+        } else if cursor >= top + page {
+            top = cursor + 1 - page;
+        }
+        draw(files, &rows, &chosen, cursor, top, page, width, screen)?;
+
+        let last = rows.len().saturating_sub(1);
+        let key = match events.next().transpose()? {
+            Some(Event::Key(press)) if press.kind != KeyEventKind::Release => key(press),
+            Some(Event::Resize(columns, lines)) => {
+                (width, height) = (columns as usize, lines as usize);
+                Key::Other
+            }
+            Some(_) => Key::Other,
+            None => Key::Cancel,
+        };
+        match key {
+            Key::Up => cursor = cursor.saturating_sub(1),
+            Key::Down => cursor = (cursor + 1).min(last),
+            Key::PageUp => cursor = cursor.saturating_sub(page),
+            Key::PageDown => cursor = (cursor + page).min(last),
+            Key::Home => cursor = 0,
+            Key::End => cursor = last,
+            Key::Toggle => {
+                let under: Vec<usize> = match rows.get(cursor) {
+                    Some(Row::Dir(dir, _)) => (0..files.len()).filter(|&i| files[i].path.starts_with(dir)).collect(),
+                    Some(Row::File(index, _)) => vec![*index],
+                    None => Vec::new(),
+                };
+                let all = under.iter().all(|&i| chosen[i]);
+                for i in under {
+                    chosen[i] = !all;
+                }
+            }
+            Key::ToggleAll => {
+                let all = chosen.iter().all(|&chosen| chosen);
+                chosen.iter_mut().for_each(|chosen| *chosen = !all);
+            }
+            Key::Done => return Ok(Some(chosen)),
+            Key::Cancel => return Ok(None),
+            Key::Other => {}
+        }
+    }
+}
+
+/// The rows of the tree of `files`, each directory once before the files
+/// below it, in the order the files were collected.
+fn tree(files: &[Entry]) -> Vec<Row> {
+    let mut rows = Vec::new();
+    let mut open: Vec<&Path> = Vec::new();
+    for (index, entry) in files.iter().enumerate() {
+        let mut dirs: Vec<&Path> =
+            entry.path.ancestors().skip(1).filter(|dir| !dir.as_os_str().is_empty()).collect();
+        dirs.reverse();
+        let common = open.iter().zip(&dirs).take_while(|(open, dir)| open == dir).count();
+        for (depth, dir) in dirs.iter().enumerate().skip(common) {
+            rows.push(Row::Dir(dir.to_path_buf(), depth));
+        }
+        rows.push(Row::File(index, dirs.len()));
+        open = dirs;
+    }
+    rows
+}
+
+#[allow(clippy::too_many_arguments)]
+fn draw(
+    files: &[Entry],
+    rows: &[Row],
+    chosen: &[bool],
+    cursor: usize,
+    top: usize,
+    page: usize,
+    width: usize,
+    mut screen: &mut dyn Write,
+) -> io::Result<()> {
+    queue!(&mut screen, MoveTo(0, 0), Clear(ClearType::All))?;
+    write!(screen, "{}\r\n", fit("Choose the files to include", width))?;
+
+    for (i, row) in rows.iter().enumerate().skip(top).take(page) {
+        let (name, depth, checked) = match row {
+            Row::Dir(dir, depth) => {
+                let under: Vec<bool> =
+                    files.iter().zip(chosen).filter(|(file, _)| file.path.starts_with(dir)).map(|(_, &c)| c).collect();
+                let checked = if under.iter().all(|&c| c) {
+                    "[x]"
+                } else if under.iter().any(|&c| c) {
+                    "[-]"
+                } else {
+                    "[ ]"
+                };
+                (format!("{}/", dir.file_name().unwrap_or(dir.as_os_str()).to_string_lossy()), *depth, checked)
+            }
+            Row::File(index, depth) => {
+                let path = &files[*index].path;
+                let name = path.file_name().unwrap_or(path.as_os_str()).to_string_lossy().into_owned();
+                (name, *depth, if chosen[*index] { "[x]" } else { "[ ]" })
+            }
+        };
+        let line = fit(&format!("{}{} {}", "  ".repeat(depth), checked, name), width);
+        if i == cursor {
+            queue!(&mut screen, SetAttribute(Attribute::Reverse))?;
+            write!(screen, "{}", line)?;
+            queue!(&mut screen, SetAttribute(Attribute::Reset))?;
+            write!(screen, "\r\n")?;
+        } else {
+            write!(screen, "{}\r\n", line)?;
+        }
+    }
+
+    let count = chosen.iter().filter(|&&chosen| chosen).count();
+    let bytes: u64 = files.iter().zip(chosen).filter(|(_, &chosen)| chosen).map(|(file, _)| file.bytes).sum();
+    let footer = format!(
+        "{} of {} files, {} tokens  |  space: toggle  a: all  enter: done  q: cancel",
+        count,
+        files.len(),
+        stats::format_tokens(stats::estimate_tokens(bytes as usize))
+    );
+    queue!(&mut screen, MoveTo(0, (page + 1) as u16))?;
+    write!(screen, "{}", fit(&footer, width))?;
+    screen.flush()
+}
+
+// Lines longer than the terminal would wrap and push the tree down
+fn fit(line: &str, width: usize) -> String {
+    line.chars().take(width).collect()
+}
+
+/// What a key press does. Arrow keys and the vi keys move the cursor.
+fn key(press: KeyEvent) -> Key {
+    match press.code {
+        KeyCode::Char('c') if press.modifiers.contains(KeyModifiers::CONTROL) => Key::Cancel,
+        KeyCode::Up | KeyCode::Char('k') => Key::Up,
+        KeyCode::Down | KeyCode::Char('j') => Key::Down,
+        KeyCode::PageUp => Key::PageUp,
+        KeyCode::PageDown => Key::PageDown,
+        KeyCode::Home | KeyCode::Char('g') => Key::Home,
+        KeyCode::End | KeyCode::Char('G') => Key::End,
+        KeyCode::Char(' ') => Key::Toggle,
+        KeyCode::Char('a') => Key::ToggleAll,
+        KeyCode::Enter => Key::Done,
+        KeyCode::Char('q') | KeyCode::Esc => Key::Cancel,
+        _ => Key::Other,
+    }
+}
+
+/// Puts the terminal back the way it was found.
+struct Restore {
+    screen: File,
+}
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        let _ = execute!(self.screen, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
@@ -0,0 +1,119 @@
+// Where the time of a collection run goes, as reported by --profile
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// How many of the slowest files the report lists.
+const SLOWEST_FILES: usize = 10;
+
+/// A stage of collecting a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Walking directories and filtering what they hold.
+    Walk,
+    /// Reading file contents.
+    Read,
+    /// Tab expansion, annotations, filters by content and slicing.
+    Transform,
+    /// Estimating the tokens of each document.
+    Tokenize,
+    /// Formatting and writing documents.
+    Write,
+}
+
+impl Phase {
+    const ALL: [Phase; 5] = [Phase::Walk, Phase::Read, Phase::Transform, Phase::Tokenize, Phase::Write];
+
+    fn name(self) -> &'static str {
+        match self {
+            Phase::Walk => "walk",
+            Phase::Read => "read",
+            Phase::Transform => "transform",
+            Phase::Tokenize => "tokenize",
+            Phase::Write => "write",
+        }
+    }
+}
+
+/// The time one file took in each phase after the walk.
+#[derive(Debug, Clone, Default)]
+pub struct FileTiming {
+    pub path: String,
+    pub bytes: u64,
+    pub tokens: u64,
+    pub read: Duration,
+    pub transform: Duration,
+    pub write: Duration,
+}
+
+impl FileTiming {
+    pub fn total(&self) -> Duration {
+        self.read + self.transform + self.write
+    }
+}
+
+/// Time spent in each phase over a run, and on each file.
+#[derive(Debug)]
+pub struct Profile {
+    start: Instant,
+    phases: [Duration; 5],
+    pub files: Vec<FileTiming>,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Profile { start: Instant::now(), phases: [Duration::ZERO; 5], files: Vec::new() }
+    }
+
+    /// Count `elapsed` towards `phase`.
+    pub fn add(&mut self, phase: Phase, elapsed: Duration) {
+        self.phases[phase as usize] += elapsed;
+    }
+
+    /// Write the report as one JSON object: the total time, the time in
+    /// each phase and the slowest files, all in milliseconds.
+    pub fn write_report(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut phases = serde_json::Map::new();
+        for phase in Phase::ALL {
+            phases.insert(phase.name().to_string(), milliseconds(self.phases[phase as usize]).into());
+        }
+
+        let mut slowest: Vec<&FileTiming> = self.files.iter().collect();
+        slowest.sort_by(|a, b| b.total().cmp(&a.total()).then_with(|| a.path.cmp(&b.path)));
+        let slowest: Vec<serde_json::Value> = slowest
+            .into_iter()
+            .take(SLOWEST_FILES)
+            .map(|file| {
+                serde_json::json!({
+                    "path": file.path,
+                    "bytes": file.bytes,
+                    "tokens": file.tokens,
+                    "read_ms": milliseconds(file.read),
+                    "transform_ms": milliseconds(file.transform),
+                    "write_ms": milliseconds(file.write),
+                    "total_ms": milliseconds(file.total()),
+                })
+            })
+            .collect();
+
+        let report = serde_json::json!({
+            "schema_version": crate::SCHEMA_VERSION,
+            "tool_version": env!("CARGO_PKG_VERSION"),
+            "total_ms": milliseconds(self.start.elapsed()),
+            "phases_ms": phases,
+            "files": self.files.len(),
+            "slowest_files": slowest,
+        });
+        writeln!(out, "{}", report)
+    }
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Milliseconds to the microsecond
+fn milliseconds(duration: Duration) -> f64 {
+    duration.as_micros() as f64 / 1000.0
+}
@@ -0,0 +1,114 @@
+// Remote git repositories given as paths. Each is cloned shallowly into a
+// temporary directory with the `git` command, collected like any local
+// directory, and removed again when the run is over.
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The commit of a repository to clone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Revision {
+    /// The remote's default branch.
+    Default,
+    /// The tip of a branch, or a tag.
+    Branch(String),
+    /// A commit, or any other ref `git fetch` accepts.
+    Commit(String),
+}
+
+/// A clone of a remote repository, deleted when dropped.
+pub(crate) struct Clone {
+    /// The working tree of the clone.
+    pub dir: PathBuf,
+    /// The name the files of the clone are shown under, from its URL.
+    pub name: PathBuf,
+}
+
+impl Drop for Clone {
+    fn drop(&mut self) {
+        if let Some(parent) = self.dir.parent() {
+            let _ = std::fs::remove_dir_all(parent);
+        }
+    }
+}
+
+/// Whether `path` is the URL of a git repository rather than a local path:
+/// `https://`, `ssh://`, `git://` and `file://` URLs, and the
+/// `user@host:path` form of ssh.
+pub(crate) fn is_repository_url(path: &str) -> bool {
+    const SCHEMES: &[&str] = &["https://", "http://", "ssh://", "git://", "git+ssh://", "file://"];
+    if SCHEMES.iter().any(|scheme| path.starts_with(scheme)) {
+        return true;
+    }
+    // `git@github.com:owner/repo.git`, but not a local `dir/a@b:c`
+    match (path.find('@'), path.find(':'), path.find('/')) {
+        (Some(at), Some(colon), slash) => at > 0 && at < colon && slash.map_or(true, |slash| slash > colon),
+        _ => false,
+    }
+}
+
+/// Clone `url` at `revision`, fetching only the one commit.
+pub(crate) fn clone(url: &str, revision: &Revision) -> io::Result<Clone> {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let parent = std::env::temp_dir().join(format!(
+        "files-to-prompt-clone-{}-{}",
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let name = repository_name(url);
+    std::fs::create_dir_all(&parent)?;
+    // Created now so a failed clone is cleaned up too
+    let clone = Clone { dir: parent.join(&name), name };
+    let dir = clone.dir.to_string_lossy().into_owned();
+
+    match revision {
+        Revision::Default => git(&["clone", "--quiet", "--depth", "1", "--", url, &dir], None)?,
+        Revision::Branch(branch) => git(
+            &["clone", "--quiet", "--depth", "1", "--single-branch", "--branch", branch, "--", url, &dir],
+            None,
+        )?,
+        Revision::Commit(commit) => {
+            git(&["init", "--quiet", &dir], None)?;
+            git(&["remote", "add", "origin", url], Some(&clone.dir))?;
+            // Servers that don't serve single commits by name, and
+            // abbreviated hashes, need the whole history
+            if git(&["fetch", "--quiet", "--depth", "1", "origin", commit], Some(&clone.dir)).is_ok() {
+                git(&["checkout", "--quiet", "FETCH_HEAD"], Some(&clone.dir))?;
+            } else {
+                git(&["fetch", "--quiet", "origin"], Some(&clone.dir))?;
+                git(&["checkout", "--quiet", commit], Some(&clone.dir))?;
+            }
+        }
+    }
+    Ok(clone)
+}
+
+/// The last part of `url`, without a `.git` extension.
+fn repository_name(url: &str) -> PathBuf {
+    let path = url.trim_end_matches('/');
+    let last = path.rsplit(['/', ':']).next().unwrap_or_default();
+    let name = last.strip_suffix(".git").unwrap_or(last);
+    PathBuf::from(if name.is_empty() || name == ".." { "repository" } else { name })
+}
+
+fn git(args: &[&str], dir: Option<&Path>) -> io::Result<()> {
+    let mut command = Command::new("git");
+    if let Some(dir) = dir {
+        command.current_dir(dir);
+    }
+    // A private repository without credentials fails instead of prompting
+    let output = command
+        .args(args)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .output()
+        .map_err(|e| io::Error::new(e.kind(), format!("could not run git: {}", e)))?;
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("git {} failed: {}", args.join(" "), message.trim()),
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,97 @@
+// Redacting secrets for --redact-secrets. Well-known credential formats are
+// found by their shape; anything else that looks like a key is found by the
+// entropy of a long quoted or assigned value. Each secret is replaced by a
+// `[REDACTED:<type>]` marker so the model still sees that something was there.
+use regex::{Captures, Regex};
+use std::sync::OnceLock;
+
+/// The patterns for the secrets with a known shape, by type. A pattern with
+/// a `secret` group only redacts that part of the match.
+const PATTERNS: &[(&str, &str)] = &[
+    ("private-key", r"-----BEGIN [A-Z0-9 ]*PRIVATE KEY( BLOCK)?-----(?s:.*?)-----END [A-Z0-9 ]*PRIVATE KEY( BLOCK)?-----"),
+    ("aws-access-key", r"\b(?:AKIA|ASIA|ABIA|ACCA)[0-9A-Z]{16}\b"),
+    (
+        "aws-secret-key",
+        r#"(?i)aws_?secret_?(?:access_?)?key["']?\s*[:=]\s*["']?(?P<secret>[A-Za-z0-9/+=]{40})\b"#,
+    ),
+    ("bearer-token", r"(?i)\bbearer\s+(?P<secret>[A-Za-z0-9\-._~+/]{16,}=*)"),
+];
+
+/// Quoted strings and assigned values long enough to be a key.
+const CANDIDATE: &str =
+    r#"["'`](?P<quoted>[A-Za-z0-9+/=_\-]{20,})["'`]|[:=][ \t]*(?P<assigned>[A-Za-z0-9+_\-][A-Za-z0-9+/=_\-]{19,})"#;
+
+/// `content` with the secrets in it replaced, and how many of each type
+/// there were, or `None` when nothing was found.
+pub(crate) fn redact(content: &str) -> Option<(String, Vec<(&'static str, usize)>)> {
+    static REGEXES: OnceLock<Vec<(&'static str, Regex)>> = OnceLock::new();
+    static CANDIDATES: OnceLock<Regex> = OnceLock::new();
+    let regexes = REGEXES.get_or_init(|| {
+        PATTERNS.iter().map(|(kind, pattern)| (*kind, Regex::new(pattern).unwrap())).collect()
+    });
+    let candidates = CANDIDATES.get_or_init(|| Regex::new(CANDIDATE).unwrap());
+
+    let mut redacted = content.to_string();
+    let mut counts = Vec::new();
+    for (kind, regex) in regexes {
+        let count = regex.find_iter(&redacted).count();
+        if count == 0 {
+            continue;
+        }
+        let marker = format!("[REDACTED:{}]", kind);
+        redacted = regex
+            .replace_all(&redacted, |cap: &Captures| match cap.name("secret") {
+                Some(secret) => {
+                    let whole = cap.get(0).unwrap();
+                    let (start, end) = (secret.start() - whole.start(), secret.end() - whole.start());
+                    format!("{}{}{}", &whole.as_str()[..start], marker, &whole.as_str()[end..])
+                }
+                None => marker.clone(),
+            })
+            .into_owned();
+        counts.push((*kind, count));
+    }
+
+    let mut count = 0;
+    redacted = candidates
+        .replace_all(&redacted, |cap: &Captures| {
+            let whole = cap.get(0).unwrap();
+            let value = cap.name("quoted").or(cap.name("assigned")).unwrap();
+            if !looks_random(value.as_str()) {
+                return whole.as_str().to_string();
+            }
+            count += 1;
+            let (start, end) = (value.start() - whole.start(), value.end() - whole.start());
+            format!("{}[REDACTED:high-entropy]{}", &whole.as_str()[..start], &whole.as_str()[end..])
+        })
+        .into_owned();
+    if count > 0 {
+        counts.push(("high-entropy", count));
+    }
+
+    (!counts.is_empty()).then_some((redacted, counts))
+}
+
+/// Whether `value` looks like a random key rather than a word, a path or
+/// a name: hex of 32 digits or more, or a mix of upper and lower case
+/// letters and digits, spread over enough different characters.
+fn looks_random(value: &str) -> bool {
+    let value = value.trim_end_matches('=');
+    if value.chars().all(|c| c.is_ascii_hexdigit()) {
+        return value.len() >= 32 && entropy(value) >= 3.0;
+    }
+    let mixed = value.contains(|c: char| c.is_ascii_uppercase())
+        && value.contains(|c: char| c.is_ascii_lowercase())
+        && value.contains(|c: char| c.is_ascii_digit());
+    mixed && entropy(value) >= 3.5
+}
+
+/// The Shannon entropy of the characters of `value`, in bits per character.
+fn entropy(value: &str) -> f64 {
+    let mut counts = [0usize; 128];
+    for byte in value.bytes() {
+        counts[(byte & 0x7f) as usize] += 1;
+    }
+    let length = value.len() as f64;
+    counts.iter().filter(|&&count| count > 0).map(|&count| count as f64 / length).map(|p| -p * p.log2()).sum()
+}
@@ -0,0 +1,163 @@
+// Splitting single-file components for --split-sfc. A Vue component is a
+// series of top-level blocks, usually <template>, <script> and <style>, each
+// in its own language. A Svelte component has <script> and <style> blocks,
+// and everything else is its markup. Blocks are found by their tags at the
+// start of a line, as they are written in practice.
+use regex::Regex;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// One block of a component, with its lines numbered as in the file.
+pub(crate) struct Block<'c> {
+    /// The tag of the block, followed by `setup` for Vue's `<script setup>`
+    /// and `module` for a Svelte module script. The markup of a Svelte
+    /// component is `markup`.
+    pub name: String,
+    /// The fence language of the content, from a `lang` attribute when
+    /// there is one.
+    pub language: String,
+    pub lines: Vec<(usize, &'c str)>,
+}
+
+/// Languages named by `lang` attributes, by the fence language they have.
+const LANGUAGES: &[(&str, &str)] = &[
+    ("ts", "typescript"),
+    ("js", "javascript"),
+    ("postcss", "css"),
+    ("styl", "stylus"),
+    ("md", "markdown"),
+    ("yml", "yaml"),
+];
+
+/// The blocks of `content` in file order, or `None` when `path` is not a
+/// `.vue` or `.svelte` file or no block is found in it.
+pub(crate) fn split<'c>(path: &Path, content: &'c str) -> Option<Vec<Block<'c>>> {
+    static OPEN: OnceLock<Regex> = OnceLock::new();
+    let open = OPEN.get_or_init(|| Regex::new(r"^<([a-zA-Z][\w-]*)([^>]*)>").unwrap());
+    let svelte = match path.extension()?.to_str()? {
+        "vue" => false,
+        "svelte" => true,
+        _ => return None,
+    };
+
+    let lines: Vec<&str> = content.lines().collect();
+    let mut blocks = Vec::new();
+    let mut markup = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let cap = open.captures(lines[i]).filter(|cap| !svelte || matches!(&cap[1], "script" | "style"));
+        let Some(cap) = cap.filter(|cap| !cap[2].ends_with('/')) else {
+            if svelte {
+                markup.push((i + 1, lines[i]));
+            }
+            i += 1;
+            continue;
+        };
+        let (tag, attributes) = (cap[1].to_string(), &cap[2]);
+        let rest = &lines[i][cap[0].len()..];
+        let Some((end, close)) = find_close(&tag, rest, &lines[i + 1..]) else {
+            // An unclosed block runs to the end
+            blocks.push(block(&tag, attributes, with_rest(i, rest, &lines[i + 1..])));
+            break;
+        };
+        let block_lines = if end == 0 {
+            vec![(i + 1, &rest[..close])]
+        } else {
+            let mut block_lines = with_rest(i, rest, &lines[i + 1..i + end]);
+            block_lines.push((i + end + 1, &lines[i + end][..close]));
+            block_lines
+        };
+        blocks.push(block(&tag, attributes, trim_blank(block_lines)));
+        i += end + 1;
+    }
+
+    blocks.retain(|block| !block.lines.is_empty());
+    let markup = trim_blank(markup);
+    if !markup.is_empty() {
+        let first = markup[0].0;
+        let at = blocks.iter().position(|block| block.lines.first().is_some_and(|(n, _)| *n > first));
+        let block = Block { name: "markup".to_string(), language: "html".to_string(), lines: markup };
+        blocks.insert(at.unwrap_or(blocks.len()), block);
+    }
+    (!blocks.is_empty()).then_some(blocks)
+}
+
+fn block<'c>(tag: &str, attributes: &str, lines: Vec<(usize, &'c str)>) -> Block<'c> {
+    static LANG: OnceLock<Regex> = OnceLock::new();
+    let lang = LANG.get_or_init(|| Regex::new(r#"\blang\s*=\s*["']?([\w-]+)"#).unwrap());
+    let language = match lang.captures(attributes) {
+        Some(cap) => {
+            let name = cap[1].to_lowercase();
+            LANGUAGES.iter().find(|(known, _)| *known == name).map_or(name, |(_, language)| language.to_string())
+        }
+        None => match tag {
+            "template" => "html",
+            "script" => "javascript",
+            "style" => "css",
+            _ => "",
+        }
+        .to_string(),
+    };
+
+    let words: Vec<&str> = attributes.split_whitespace().collect();
+    let name = if tag == "script" && words.contains(&"setup") {
+        "script setup".to_string()
+    } else if tag == "script" && (words.contains(&"module") || attributes.contains("context=\"module\"")) {
+        "script module".to_string()
+    } else {
+        tag.to_string()
+    };
+    Block { name, language, lines }
+}
+
+/// Where the block opened with `tag` closes: the number of lines after
+/// the opening one and the offset of the closing tag in that line. Nested
+/// blocks with the same tag, such as a `<template v-if>`, are skipped.
+fn find_close(tag: &str, rest: &str, following: &[&str]) -> Option<(usize, usize)> {
+    let (open, close) = (format!("<{}", tag), format!("</{}>", tag));
+    let mut depth = 1;
+    for (end, line) in [rest].iter().chain(following).enumerate() {
+        let mut at = 0;
+        loop {
+            let next_open = line[at..].find(&open).filter(|&i| {
+                let after = line[at + i + open.len()..].chars().next();
+                after.map_or(true, |c| c == '>' || c.is_whitespace())
+            });
+            let next_close = line[at..].find(&close);
+            match (next_open, next_close) {
+                (Some(o), Some(c)) if o < c => {
+                    depth += 1;
+                    at += o + open.len();
+                }
+                (_, Some(c)) => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some((end, at + c));
+                    }
+                    at += c + close.len();
+                }
+                (Some(o), None) => {
+                    depth += 1;
+                    at += o + open.len();
+                }
+                (None, None) => break,
+            }
+        }
+    }
+    None
+}
+
+// The text after an opening tag on its line, then the following lines
+fn with_rest<'c>(i: usize, rest: &'c str, following: &[&'c str]) -> Vec<(usize, &'c str)> {
+    let first = (!rest.trim().is_empty()).then_some((i + 1, rest));
+    first.into_iter().chain(following.iter().enumerate().map(|(j, line)| (i + j + 2, *line))).collect()
+}
+
+// Blank lines at either end of a block are left out
+fn trim_blank(mut lines: Vec<(usize, &str)>) -> Vec<(usize, &str)> {
+    while lines.last().is_some_and(|(_, line)| line.trim().is_empty()) {
+        lines.pop();
+    }
+    let start = lines.iter().position(|(_, line)| !line.trim().is_empty()).unwrap_or(lines.len());
+    lines.split_off(start)
+}
@@ -0,0 +1,147 @@
+// Size and token figures for the files in a prompt, as printed by --stats
+use std::io::{self, Write};
+use std::time::Duration;
+
+/// Output is measured in tokens at roughly this many bytes per token.
+pub const BYTES_PER_TOKEN: usize = 4;
+
+/// How the `--stats` report is laid out.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum StatsFormat {
+    /// Aligned columns with humanized figures, for reading in a terminal.
+    #[default]
+    Table,
+    Json,
+    Csv,
+}
+
+/// The size of one document in the output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileStats {
+    pub path: String,
+    pub bytes: u64,
+    pub lines: u64,
+}
+
+impl FileStats {
+    pub fn tokens(&self) -> u64 {
+        self.bytes / BYTES_PER_TOKEN as u64
+    }
+}
+
+/// What the report says about the run as a whole.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// How many files were left out for each reason, such as `not UTF-8
+    /// text`, in the order the reasons first came up.
+    pub skipped: Vec<(String, usize)>,
+    /// The time the run took.
+    pub elapsed: Duration,
+}
+
+/// Rough token count for `bytes` of output.
+pub fn estimate_tokens(bytes: usize) -> usize {
+    bytes / BYTES_PER_TOKEN
+}
+
+/// A token count such as `~128k`.
+pub fn format_tokens(tokens: usize) -> String {
+    if tokens >= 1000 {
+        format!("~{}k", tokens / 1000)
+    } else {
+        format!("~{}", tokens)
+    }
+}
+
+/// A size such as `512 B`, `12.5 KB` or `1.4 MB`.
+pub fn format_size(bytes: u64) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    } else if bytes >= 1024 {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    } else {
+        format!("{} B", bytes)
+    }
+}
+
+/// A count with its thousands grouped, such as `12,345`.
+pub fn group_thousands(n: u64) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+/// Write the report for `files` followed by the totals and the `summary`
+/// of the run. CSV has a row for each file and no room for the rest.
+pub fn write_stats(files: &[FileStats], summary: &Summary, format: StatsFormat, out: &mut dyn Write) -> io::Result<()> {
+    let bytes: u64 = files.iter().map(|file| file.bytes).sum();
+    let tokens: u64 = files.iter().map(FileStats::tokens).sum();
+    let lines: u64 = files.iter().map(|file| file.lines).sum();
+
+    match format {
+        StatsFormat::Table => {
+            let total = format!("Total ({} files)", group_thousands(files.len() as u64));
+            let rows: Vec<(String, String, String)> = files
+                .iter()
+                .map(|file| (file.path.clone(), format_size(file.bytes), format_tokens(file.tokens() as usize)))
+                .chain([(total, format_size(bytes), format_tokens(tokens as usize))])
+                .collect();
+
+            let path_width = rows.iter().map(|row| row.0.chars().count()).max().unwrap_or(0).max(4);
+            let size_width = rows.iter().map(|row| row.1.len()).max().unwrap_or(0).max(4);
+            writeln!(out, "{:<path_width$}  {:>size_width$}  Tokens", "Path", "Size")?;
+            for (path, size, tokens) in rows {
+                writeln!(out, "{:<path_width$}  {:>size_width$}  {:>6}", path, size, tokens)?;
+            }
+            writeln!(out, "Lines: {}", group_thousands(lines))?;
+            if !summary.skipped.is_empty() {
+                let skipped: Vec<String> = summary
+                    .skipped
+                    .iter()
+                    .map(|(reason, count)| format!("{} {}", group_thousands(*count as u64), reason))
+                    .collect();
+                writeln!(out, "Skipped: {}", skipped.join(", "))?;
+            }
+            writeln!(out, "Elapsed: {:.2}s", summary.elapsed.as_secs_f64())?;
+        }
+        StatsFormat::Json => {
+            let files: Vec<serde_json::Value> = files
+                .iter()
+                .map(|file| serde_json::json!({"path": file.path, "bytes": file.bytes, "tokens": file.tokens()}))
+                .collect();
+            let skipped: serde_json::Map<String, serde_json::Value> =
+                summary.skipped.iter().map(|(reason, count)| (reason.clone(), (*count).into())).collect();
+            let report = serde_json::json!({
+                "schema_version": crate::SCHEMA_VERSION,
+                "tool_version": env!("CARGO_PKG_VERSION"),
+                "files": files,
+                "total": {"files": files.len(), "bytes": bytes, "tokens": tokens, "lines": lines},
+                "skipped": skipped,
+                "elapsed_ms": summary.elapsed.as_millis() as u64,
+            });
+            writeln!(out, "{}", report)?;
+        }
+        StatsFormat::Csv => {
+            writeln!(out, "path,bytes,tokens")?;
+            for file in files {
+                writeln!(out, "{},{},{}", csv_field(&file.path), file.bytes, file.tokens())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,168 @@
+// Heuristics behind `files-to-prompt suggest-ignores`
+use crate::names;
+use crate::stats;
+use crate::vfs::FileSystem;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Directory names that almost always hold build output or vendored code.
+const GENERATED_DIRS: &[&str] = &[
+    "node_modules",
+    "dist",
+    "build",
+    "target",
+    "vendor",
+    "coverage",
+    "__pycache__",
+    ".next",
+    ".venv",
+    "venv",
+];
+
+pub(crate) const LOCKFILES: &[&str] = &[
+    "Cargo.lock",
+    "package-lock.json",
+    "yarn.lock",
+    "pnpm-lock.yaml",
+    "poetry.lock",
+    "Pipfile.lock",
+    "composer.lock",
+    "Gemfile.lock",
+    "go.sum",
+];
+
+const MINIFIED_SUFFIXES: &[&str] = &[".min.js", ".min.css", ".map"];
+
+/// Markers that code generators leave in the first lines of their output.
+const GENERATED_MARKERS: &[&str] = &["@generated", "DO NOT EDIT", "Code generated", "autogenerated"];
+
+const DATA_EXTENSIONS: &[&str] = &["csv", "tsv", "json", "jsonl", "ndjson", "sql", "xml", "log"];
+
+/// Data files above this size are suggested by extension.
+const LARGE_DATA_BYTES: u64 = 100 * 1024;
+
+/// Any file above this size is suggested by name.
+const HUGE_FILE_BYTES: u64 = 1024 * 1024;
+
+/// Why a pattern is suggested, in the order suggestions are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Reason {
+    GeneratedDirectory,
+    Lockfile,
+    Minified,
+    Binary,
+    GeneratedFile,
+    LargeData,
+    HugeFile,
+}
+
+impl Reason {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Reason::GeneratedDirectory => "Build output and dependency directories",
+            Reason::Lockfile => "Lockfiles",
+            Reason::Minified => "Minified bundles and source maps",
+            Reason::Binary => "Binary files",
+            Reason::GeneratedFile => "Files marked as generated",
+            Reason::LargeData => "Large data files",
+            Reason::HugeFile => "Very large files",
+        }
+    }
+}
+
+/// A proposed ignore pattern and the included files it would remove.
+#[derive(Debug, Clone)]
+pub struct Suggestion {
+    pub reason: Reason,
+    /// The pattern in .gitignore syntax; directories end in `/`.
+    pub pattern: String,
+    pub files: Vec<PathBuf>,
+    pub bytes: u64,
+}
+
+impl Suggestion {
+    /// The pattern as an `--ignore` value, which matches names without a
+    /// trailing slash.
+    pub fn ignore_flag(&self) -> &str {
+        self.pattern.trim_end_matches('/')
+    }
+}
+
+/// Propose ignore patterns for low-value content among `files`.
+pub fn suggest_ignores(files: &[PathBuf], fs: &dyn FileSystem) -> Vec<Suggestion> {
+    let mut suggestions: BTreeMap<(Reason, String), Suggestion> = BTreeMap::new();
+
+    for file in files {
+        let content = fs.read(file).unwrap_or_default();
+        let Some((reason, pattern)) = classify(file, &content) else {
+            continue;
+        };
+
+        let suggestion = suggestions
+            .entry((reason, pattern.clone()))
+            .or_insert_with(|| Suggestion {
+                reason,
+                pattern,
+                files: Vec::new(),
+                bytes: 0,
+            });
+        suggestion.files.push(file.clone());
+        suggestion.bytes += content.len() as u64;
+    }
+
+    suggestions.into_values().collect()
+}
+
+fn classify(path: &Path, content: &[u8]) -> Option<(Reason, String)> {
+    let name = names::display_path(Path::new(path.file_name()?));
+    let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+    let by_extension = || match &extension {
+        Some(extension) => format!("*.{}", extension),
+        None => name.clone(),
+    };
+
+    let parent = path.parent().unwrap_or_else(|| Path::new(""));
+    for component in parent.components() {
+        let component = component.as_os_str().to_string_lossy();
+        if GENERATED_DIRS.contains(&component.as_ref()) {
+            return Some((Reason::GeneratedDirectory, format!("{}/", component)));
+        }
+    }
+
+    if LOCKFILES.contains(&name.as_str()) {
+        return Some((Reason::Lockfile, name));
+    }
+
+    if let Some(suffix) = MINIFIED_SUFFIXES.iter().find(|suffix| name.ends_with(*suffix)) {
+        return Some((Reason::Minified, format!("*{}", suffix)));
+    }
+
+    let text = match std::str::from_utf8(content) {
+        Ok(text) if !text.contains('\0') => text,
+        _ => return Some((Reason::Binary, by_extension())),
+    };
+
+    let head = text.lines().take(5).collect::<Vec<_>>().join("\n");
+    if GENERATED_MARKERS.iter().any(|marker| head.contains(marker)) {
+        return Some((Reason::GeneratedFile, name));
+    }
+
+    let size = content.len() as u64;
+    if size > LARGE_DATA_BYTES
+        && extension
+            .as_deref()
+            .is_some_and(|extension| DATA_EXTENSIONS.contains(&extension))
+    {
+        return Some((Reason::LargeData, by_extension()));
+    }
+    if size > HUGE_FILE_BYTES {
+        return Some((Reason::HugeFile, name));
+    }
+
+    None
+}
+
+/// How many files and how much content a pattern covers, e.g. `3 files, 12.5 KB`.
+pub fn describe_removed(files: usize, bytes: u64) -> String {
+    format!("{} file{}, {}", stats::group_thousands(files as u64), if files == 1 { "" } else { "s" }, stats::format_size(bytes))
+}
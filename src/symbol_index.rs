@@ -0,0 +1,121 @@
+// Readers for prebuilt symbol indexes used by --symbol
+use crate::vfs::FileSystem;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Read a ctags `tags` file and return the files defining `symbol`.
+/// Relative file names are resolved against the directory holding the tags file.
+pub fn ctags_definitions(fs: &dyn FileSystem, tags_path: &Path, symbol: &str) -> io::Result<HashSet<PathBuf>> {
+    let content = fs.read_to_string(tags_path)?;
+    let base = tags_path.parent().unwrap_or_else(|| Path::new(""));
+    let mut files = HashSet::new();
+
+    for line in content.lines() {
+        if line.starts_with("!_TAG_") {
+            continue;
+        }
+
+        let mut fields = line.split('\t');
+        let (Some(name), Some(file)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+
+        if name == symbol {
+            files.insert(normalize(fs, &base.join(file)));
+        }
+    }
+
+    Ok(files)
+}
+
+/// Read an LSIF dump and return the files containing a definition or
+/// reference of `symbol`. Both the line-delimited and the JSON array
+/// encodings are accepted.
+pub fn lsif_occurrences(fs: &dyn FileSystem, lsif_path: &Path, symbol: &str) -> io::Result<HashSet<PathBuf>> {
+    let content = fs.read_to_string(lsif_path)?;
+    let invalid = |e: serde_json::Error| io::Error::new(io::ErrorKind::InvalidData, e);
+
+    let elements: Vec<Value> = if content.trim_start().starts_with('[') {
+        serde_json::from_str(&content).map_err(invalid)?
+    } else {
+        content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).map_err(invalid))
+            .collect::<io::Result<_>>()?
+    };
+
+    let mut documents: HashMap<String, String> = HashMap::new();
+    let mut matching_ranges: HashSet<String> = HashSet::new();
+    let mut contains: Vec<(String, Vec<String>)> = Vec::new();
+
+    for element in &elements {
+        let id = element.get("id").map(id_string).unwrap_or_default();
+        match (element["type"].as_str(), element["label"].as_str()) {
+            (Some("vertex"), Some("document")) => {
+                if let Some(uri) = element["uri"].as_str() {
+                    documents.insert(id, uri.to_string());
+                }
+            }
+            (Some("vertex"), Some("range")) if element["tag"]["text"].as_str() == Some(symbol) => {
+                matching_ranges.insert(id);
+            }
+            (Some("edge"), Some("contains")) => {
+                let in_vs = element["inVs"]
+                    .as_array()
+                    .map(|ids| ids.iter().map(id_string).collect())
+                    .unwrap_or_default();
+                contains.push((id_string(&element["outV"]), in_vs));
+            }
+            _ => {}
+        }
+    }
+
+    let mut files = HashSet::new();
+    for (document, ranges) in contains {
+        if let Some(uri) = documents.get(&document) {
+            if ranges.iter().any(|range| matching_ranges.contains(range)) {
+                files.insert(normalize(fs, &uri_to_path(uri)));
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// Canonicalize paths that exist so index entries compare equal to walked paths.
+pub fn normalize(fs: &dyn FileSystem, path: &Path) -> PathBuf {
+    fs.canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+// LSIF ids may be numbers or strings
+fn id_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn uri_to_path(uri: &str) -> PathBuf {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    PathBuf::from(String::from_utf8_lossy(&decoded).into_owned())
+}
@@ -0,0 +1,162 @@
+// User-defined output layouts for --template. A template is plain text with
+// `{{ name }}` placeholders, split into blocks by `{% header %}`,
+// `{% file %}` and `{% footer %}` lines:
+//
+//     {% header %}
+//     <files>
+//     {% file %}
+//     <file path="{{ path }}" lang="{{ lang }}">
+//     {{ content }}
+//     </file>
+//     {% footer %}
+//     </files>
+//
+// A template without block lines is all file block.
+
+/// A parsed template: the header and footer written once around the
+/// documents and the block written for each file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Template {
+    header: Vec<Part>,
+    file: Vec<Part>,
+    footer: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Value(Value),
+}
+
+/// What a placeholder in the file block stands for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Value {
+    Path,
+    Content,
+    Index,
+    Extension,
+    Lang,
+}
+
+impl Value {
+    const NAMES: &'static [(&'static str, Value)] = &[
+        ("path", Value::Path),
+        ("content", Value::Content),
+        ("index", Value::Index),
+        ("extension", Value::Extension),
+        ("lang", Value::Lang),
+    ];
+}
+
+/// What the placeholders of one file are filled with.
+pub(crate) struct FileValues<'a> {
+    pub path: &'a str,
+    pub content: &'a str,
+    pub index: usize,
+    pub extension: &'a str,
+    pub lang: &'a str,
+}
+
+impl Template {
+    /// Parse `text`, failing on unknown placeholders or blocks and on file
+    /// placeholders in the header or footer.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut template = Template::default();
+        let mut blocks: Vec<(&str, String)> = Vec::new();
+        let mut current = ("file", String::new());
+        let mut marked = false;
+
+        for line in text.split_inclusive('\n') {
+            let trimmed = line.trim();
+            let Some(name) = trimmed.strip_prefix("{%").and_then(|rest| rest.strip_suffix("%}")) else {
+                current.1.push_str(line);
+                continue;
+            };
+            let name = match name.trim() {
+                name @ ("header" | "file" | "footer") => name,
+                other => return Err(format!("unknown template block '{}'", other)),
+            };
+            if marked || !current.1.trim().is_empty() {
+                blocks.push(current);
+            }
+            marked = true;
+            current = (name, String::new());
+        }
+        blocks.push(current);
+
+        for (name, text) in blocks {
+            let parts = parse_parts(&text)?;
+            if name != "file" && parts.iter().any(|part| matches!(part, Part::Value(_))) {
+                return Err(format!("placeholders can only be used in the file block, not the {}", name));
+            }
+            match name {
+                "header" => template.header = parts,
+                "footer" => template.footer = parts,
+                _ => template.file = parts,
+            }
+        }
+        Ok(template)
+    }
+
+    /// The header text, without its final line break.
+    pub(crate) fn header(&self) -> Option<String> {
+        block_text(&self.header, None)
+    }
+
+    /// The footer text, without its final line break.
+    pub(crate) fn footer(&self) -> Option<String> {
+        block_text(&self.footer, None)
+    }
+
+    /// The file block filled in for one file, without its final line break.
+    pub(crate) fn render_file(&self, values: &FileValues) -> String {
+        block_text(&self.file, Some(values)).unwrap_or_default()
+    }
+}
+
+fn parse_parts(text: &str) -> Result<Vec<Part>, String> {
+    let mut parts = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let end = rest[start..].find("}}").ok_or("unclosed '{{' in template")? + start;
+        let name = rest[start + 2..end].trim();
+        let value = Value::NAMES
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map(|(_, value)| *value)
+            .ok_or_else(|| format!("unknown template placeholder '{}'", name))?;
+
+        if start > 0 {
+            parts.push(Part::Text(rest[..start].to_string()));
+        }
+        parts.push(Part::Value(value));
+        rest = &rest[end + 2..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Text(rest.to_string()));
+    }
+    Ok(parts)
+}
+
+// Blocks end with the line break before the next block line, which the
+// writer adds back; an empty block writes nothing at all
+fn block_text(parts: &[Part], values: Option<&FileValues>) -> Option<String> {
+    if parts.is_empty() {
+        return None;
+    }
+    let mut text = String::new();
+    for part in parts {
+        match (part, values) {
+            (Part::Text(literal), _) => text.push_str(literal),
+            (Part::Value(value), Some(values)) => match value {
+                Value::Path => text.push_str(values.path),
+                Value::Content => text.push_str(values.content),
+                Value::Index => text.push_str(&values.index.to_string()),
+                Value::Extension => text.push_str(values.extension),
+                Value::Lang => text.push_str(values.lang),
+            },
+            (Part::Value(_), None) => {}
+        }
+    }
+    Some(text.strip_suffix('\n').map(str::to_string).unwrap_or(text))
+}
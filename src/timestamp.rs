@@ -0,0 +1,58 @@
+// RFC 3339 timestamps for the metadata written with the output, such as the
+// command header and manifests. They are in UTC, so runs on different
+// machines and CI runners compare as text, unless --local-time asks for the
+// local time zone, whose rules chrono reads from the operating system.
+use chrono::{Local, Offset, TimeZone};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// `time` as an RFC 3339 timestamp, e.g. `2025-05-25T00:54:35Z`, or with
+/// `local` in the local time zone, e.g. `2025-05-25T02:54:35+02:00`. The
+/// local offset is the one in effect at `time`, so daylight saving time is
+/// accounted for.
+pub(crate) fn format(time: SystemTime, local: bool) -> String {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    };
+    let offset = if local { local_offset(secs) } else { None };
+    let zone = match offset {
+        Some(offset) => {
+            let sign = if offset < 0 { '-' } else { '+' };
+            format!("{}{:02}:{:02}", sign, offset.abs() / 3600, offset.abs() % 3600 / 60)
+        }
+        None => "Z".to_string(),
+    };
+
+    let local_secs = secs + offset.unwrap_or(0);
+    let (days, rem) = (local_secs.div_euclid(86400), local_secs.rem_euclid(86400));
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+        zone
+    )
+}
+
+// Civil-from-days conversion (Howard Hinnant's algorithm)
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
+
+/// The offset from UTC in seconds of the local time zone at `secs`.
+fn local_offset(secs: i64) -> Option<i64> {
+    let time = Local.timestamp_opt(secs, 0).single()?;
+    Some(time.offset().fix().local_minus_utc() as i64)
+}
@@ -0,0 +1,35 @@
+// The `tree`-style overview of the included files written by --tree
+use std::collections::BTreeMap;
+
+#[derive(Default)]
+struct Node {
+    children: BTreeMap<String, Node>,
+}
+
+/// Draw the files, each given as its path components, as an ASCII tree in
+/// the style of the `tree` command. Siblings are sorted by name.
+pub(crate) fn render_tree(files: &[Vec<String>]) -> String {
+    let mut root = Node::default();
+    for components in files {
+        let mut node = &mut root;
+        for component in components {
+            node = node.children.entry(component.clone()).or_default();
+        }
+    }
+
+    let mut lines = Vec::new();
+    for (name, node) in &root.children {
+        lines.push(name.clone());
+        draw(node, "", &mut lines);
+    }
+    lines.join("\n")
+}
+
+fn draw(node: &Node, prefix: &str, lines: &mut Vec<String>) {
+    let count = node.children.len();
+    for (i, (name, child)) in node.children.iter().enumerate() {
+        let last = i + 1 == count;
+        lines.push(format!("{}{}{}", prefix, if last { "└── " } else { "├── " }, name));
+        draw(child, &format!("{}{}", prefix, if last { "    " } else { "│   " }), lines);
+    }
+}
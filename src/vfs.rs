@@ -0,0 +1,523 @@
+// Filesystem access used by the collector, so it can run against the real
+// disk or an in-memory tree in tests and embedders.
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+pub trait FileSystem {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// The entries of a directory, as `path` joined with each entry name.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    fn is_file(&self, path: &Path) -> bool;
+
+    fn is_dir(&self, path: &Path) -> bool;
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    fn exists(&self, path: &Path) -> bool {
+        self.is_file(path) || self.is_dir(path)
+    }
+
+    /// The size of a file in bytes, ideally without reading it.
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        self.read(path).map(|content| content.len() as u64)
+    }
+
+    /// Whether `path` is a cloud placeholder whose contents would be
+    /// downloaded on access, such as a OneDrive file that is online only.
+    fn is_placeholder(&self, _path: &Path) -> bool {
+        false
+    }
+
+    /// The target of `path` as written in the link, when it is a symbolic
+    /// link.
+    fn read_link(&self, _path: &Path) -> Option<PathBuf> {
+        None
+    }
+
+    /// When a file was last modified, where that is known.
+    fn modified(&self, _path: &Path) -> Option<SystemTime> {
+        None
+    }
+
+    /// Read a file as UTF-8, failing with `InvalidData` otherwise.
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        String::from_utf8(self.read(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl<T: FileSystem + ?Sized> FileSystem for &T {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        (**self).read(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        (**self).read_dir(path)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        (**self).is_file(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        (**self).is_dir(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        (**self).canonicalize(path)
+    }
+
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        (**self).size(path)
+    }
+
+    fn is_placeholder(&self, path: &Path) -> bool {
+        (**self).is_placeholder(path)
+    }
+
+    fn read_link(&self, path: &Path) -> Option<PathBuf> {
+        (**self).read_link(path)
+    }
+
+    fn modified(&self, path: &Path) -> Option<SystemTime> {
+        (**self).modified(path)
+    }
+}
+
+/// The real filesystem.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RealFs;
+
+impl FileSystem for RealFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        Ok(fs::read_dir(path)?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .collect())
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        Ok(fs::metadata(path)?.len())
+    }
+
+    fn read_link(&self, path: &Path) -> Option<PathBuf> {
+        fs::read_link(path).ok()
+    }
+
+    fn modified(&self, path: &Path) -> Option<SystemTime> {
+        fs::metadata(path).ok()?.modified().ok()
+    }
+
+    #[cfg(windows)]
+    fn is_placeholder(&self, path: &Path) -> bool {
+        use std::os::windows::fs::MetadataExt;
+        const FILE_ATTRIBUTE_OFFLINE: u32 = 0x1000;
+        const FILE_ATTRIBUTE_RECALL_ON_OPEN: u32 = 0x40000;
+        const FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS: u32 = 0x400000;
+
+        let recall = FILE_ATTRIBUTE_OFFLINE | FILE_ATTRIBUTE_RECALL_ON_OPEN | FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS;
+        fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_attributes() & recall != 0)
+    }
+}
+
+/// The real filesystem with file contents and directory listings kept in
+/// memory, for a long-running process. Each entry is still looked up on
+/// every access and read again once its modification time or size changes.
+#[derive(Debug, Default)]
+pub struct CachedFs {
+    files: Mutex<HashMap<PathBuf, (Stamp, Vec<u8>)>>,
+    dirs: Mutex<HashMap<PathBuf, (Stamp, Vec<PathBuf>)>>,
+}
+
+/// What tells a changed entry from a cached one.
+type Stamp = (Option<SystemTime>, u64);
+
+impl CachedFs {
+    pub fn new() -> Self {
+        CachedFs::default()
+    }
+}
+
+// Relative paths are keyed by the absolute path, as the working directory
+// may change between uses
+fn cache_key(path: &Path) -> io::Result<(PathBuf, Stamp)> {
+    let metadata = fs::metadata(path)?;
+    let key = if path.is_absolute() { path.to_path_buf() } else { std::env::current_dir()?.join(path) };
+    Ok((key, (metadata.modified().ok(), metadata.len())))
+}
+
+impl FileSystem for CachedFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let (key, stamp) = cache_key(path)?;
+        let mut files = self.files.lock().unwrap();
+        if let Some((cached, content)) = files.get(&key) {
+            if *cached == stamp {
+                return Ok(content.clone());
+            }
+        }
+        let content = RealFs.read(path)?;
+        files.insert(key, (stamp, content.clone()));
+        Ok(content)
+    }
+
+    // Entries are returned under the path asked for, however it was cached
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let (key, stamp) = cache_key(path)?;
+        let mut dirs = self.dirs.lock().unwrap();
+        let names = match dirs.get(&key) {
+            Some((cached, names)) if *cached == stamp => names.clone(),
+            _ => {
+                let names: Vec<PathBuf> = RealFs
+                    .read_dir(path)?
+                    .into_iter()
+                    .filter_map(|entry| entry.file_name().map(PathBuf::from))
+                    .collect();
+                dirs.insert(key, (stamp, names.clone()));
+                names
+            }
+        };
+        Ok(names.into_iter().map(|name| path.join(name)).collect())
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        RealFs.is_file(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        RealFs.is_dir(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        RealFs.canonicalize(path)
+    }
+
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        RealFs.size(path)
+    }
+
+    fn is_placeholder(&self, path: &Path) -> bool {
+        RealFs.is_placeholder(path)
+    }
+
+    fn read_link(&self, path: &Path) -> Option<PathBuf> {
+        RealFs.read_link(path)
+    }
+
+    fn modified(&self, path: &Path) -> Option<SystemTime> {
+        RealFs.modified(path)
+    }
+}
+
+/// An in-memory tree rooted at `/`. Relative paths are resolved against the
+/// root, and directories are created implicitly for every file added.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryFs {
+    files: BTreeMap<PathBuf, Vec<u8>>,
+    dirs: BTreeSet<PathBuf>,
+    placeholders: BTreeSet<PathBuf>,
+    symlinks: BTreeMap<PathBuf, PathBuf>,
+    modified: BTreeMap<PathBuf, SystemTime>,
+}
+
+/// How many links are followed in resolving one path before giving up, as
+/// the kernel does.
+const MAX_SYMLINK_HOPS: usize = 40;
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        MemoryFs::default()
+    }
+
+    pub fn add_file(&mut self, path: impl AsRef<Path>, content: impl Into<Vec<u8>>) -> &mut Self {
+        let key = key(path.as_ref());
+        if let Some(parent) = key.parent() {
+            self.add_dir(parent);
+        }
+        self.files.insert(key, content.into());
+        self
+    }
+
+    /// Add a file that reports itself as a cloud placeholder.
+    pub fn add_placeholder(&mut self, path: impl AsRef<Path>, content: impl Into<Vec<u8>>) -> &mut Self {
+        self.placeholders.insert(key(path.as_ref()));
+        self.add_file(path, content)
+    }
+
+    /// Add a symbolic link to `target`, which is relative to the link's
+    /// directory unless it is absolute.
+    pub fn add_symlink(&mut self, path: impl AsRef<Path>, target: impl AsRef<Path>) -> &mut Self {
+        let key = key(path.as_ref());
+        if let Some(parent) = key.parent() {
+            self.add_dir(parent);
+        }
+        self.symlinks.insert(key, target.as_ref().to_path_buf());
+        self
+    }
+
+    /// Give a file a modification time, which files otherwise lack.
+    pub fn set_modified(&mut self, path: impl AsRef<Path>, time: SystemTime) -> &mut Self {
+        self.modified.insert(key(path.as_ref()), time);
+        self
+    }
+
+    /// The entry `path` names, with every link along it followed.
+    fn resolve(&self, path: &Path) -> PathBuf {
+        let mut resolved = PathBuf::new();
+        let mut rest: Vec<Component> = path.components().rev().collect();
+        let mut hops = 0;
+        while let Some(component) = rest.pop() {
+            match component {
+                Component::Normal(name) => resolved.push(name),
+                Component::ParentDir => {
+                    resolved.pop();
+                    continue;
+                }
+                _ => continue,
+            }
+            if let Some(target) = self.symlinks.get(&resolved).filter(|_| hops < MAX_SYMLINK_HOPS) {
+                hops += 1;
+                resolved.pop();
+                if target.is_absolute() {
+                    resolved = PathBuf::new();
+                }
+                rest.extend(target.components().rev());
+            }
+        }
+        resolved
+    }
+
+    pub fn add_dir(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        let key = key(path.as_ref());
+        for ancestor in key.ancestors() {
+            if ancestor != Path::new("") {
+                self.dirs.insert(ancestor.to_path_buf());
+            }
+        }
+        self
+    }
+}
+
+impl FileSystem for MemoryFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.files.get(&self.resolve(path)).cloned().ok_or_else(|| not_found(path))
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        if !self.is_dir(path) {
+            return Err(not_found(path));
+        }
+
+        let dir = self.resolve(path);
+        let children = self
+            .dirs
+            .iter()
+            .chain(self.files.keys())
+            .chain(self.symlinks.keys())
+            .filter(|child| child.parent() == Some(&dir))
+            .filter_map(|child| child.file_name())
+            .map(|name| path.join(name))
+            .collect();
+        Ok(children)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.files.contains_key(&self.resolve(path))
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        let key = self.resolve(path);
+        key == Path::new("") || self.dirs.contains(&key)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        if self.exists(path) {
+            Ok(Path::new("/").join(self.resolve(path)))
+        } else {
+            Err(not_found(path))
+        }
+    }
+
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        self.files.get(&self.resolve(path)).map(|content| content.len() as u64).ok_or_else(|| not_found(path))
+    }
+
+    fn is_placeholder(&self, path: &Path) -> bool {
+        self.placeholders.contains(&self.resolve(path))
+    }
+
+    fn read_link(&self, path: &Path) -> Option<PathBuf> {
+        let parent = self.resolve(path.parent().unwrap_or(Path::new("")));
+        self.symlinks.get(&parent.join(path.file_name()?)).cloned()
+    }
+
+    fn modified(&self, path: &Path) -> Option<SystemTime> {
+        self.modified.get(&self.resolve(path)).copied()
+    }
+}
+
+// `./a/b`, `/a/b` and `a/b` all name the same entry
+fn key(path: &Path) -> PathBuf {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(name) => Some(name),
+            _ => None,
+        })
+        .collect()
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{}: No such file or directory", path.display()),
+    )
+}
+
+/// Files from `upper` layered over `lower`, e.g. fetched content over the
+/// real disk. Directories from both are merged.
+#[derive(Debug, Default, Clone)]
+pub struct OverlayFs<U, L> {
+    pub upper: U,
+    pub lower: L,
+}
+
+impl<U: FileSystem, L: FileSystem> FileSystem for OverlayFs<U, L> {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        if self.upper.is_file(path) {
+            self.upper.read(path)
+        } else {
+            self.lower.read(path)
+        }
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut entries = if self.lower.is_dir(path) {
+            self.lower.read_dir(path)?
+        } else {
+            Vec::new()
+        };
+        if self.upper.is_dir(path) {
+            for entry in self.upper.read_dir(path)? {
+                if !entries.contains(&entry) {
+                    entries.push(entry);
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.upper.is_file(path) || self.lower.is_file(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.upper.is_dir(path) || self.lower.is_dir(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.lower.canonicalize(path).or_else(|_| self.upper.canonicalize(path))
+    }
+
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        if self.upper.is_file(path) {
+            self.upper.size(path)
+        } else {
+            self.lower.size(path)
+        }
+    }
+
+    fn is_placeholder(&self, path: &Path) -> bool {
+        if self.upper.exists(path) {
+            self.upper.is_placeholder(path)
+        } else {
+            self.lower.is_placeholder(path)
+        }
+    }
+
+    fn read_link(&self, path: &Path) -> Option<PathBuf> {
+        self.upper.read_link(path).or_else(|| self.lower.read_link(path))
+    }
+
+    fn modified(&self, path: &Path) -> Option<SystemTime> {
+        if self.upper.is_file(path) {
+            self.upper.modified(path)
+        } else {
+            self.lower.modified(path)
+        }
+    }
+}
+
+/// Another filesystem seen from `root`: relative paths are taken from
+/// there rather than from the process's working directory, and directory
+/// entries are listed under the path as given.
+#[derive(Debug, Default, Clone)]
+pub struct RootedFs<F> {
+    pub root: PathBuf,
+    pub fs: F,
+}
+
+impl<F: FileSystem> RootedFs<F> {
+    fn resolve(&self, path: &Path) -> PathBuf {
+        self.root.join(path)
+    }
+}
+
+impl<F: FileSystem> FileSystem for RootedFs<F> {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.fs.read(&self.resolve(path))
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let entries = self.fs.read_dir(&self.resolve(path))?;
+        Ok(entries.into_iter().filter_map(|entry| Some(path.join(entry.file_name()?))).collect())
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.fs.is_file(&self.resolve(path))
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.fs.is_dir(&self.resolve(path))
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.fs.canonicalize(&self.resolve(path))
+    }
+
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        self.fs.size(&self.resolve(path))
+    }
+
+    fn is_placeholder(&self, path: &Path) -> bool {
+        self.fs.is_placeholder(&self.resolve(path))
+    }
+
+    fn read_link(&self, path: &Path) -> Option<PathBuf> {
+        self.fs.read_link(&self.resolve(path))
+    }
+
+    fn modified(&self, path: &Path) -> Option<SystemTime> {
+        self.fs.modified(&self.resolve(path))
+    }
+}
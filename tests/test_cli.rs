@@ -0,0 +1,1257 @@
+// End-to-end tests of the binary: argument parsing, stdin, output files and
+// anything else that lives outside the library collector.
+use assert_cmd::Command;
+use predicates::prelude::*;
+use regex::Regex;
+use std::fs;
+use tempfile::TempDir;
+
+fn files_to_prompt(cwd: &TempDir) -> Command {
+    let mut cmd = Command::cargo_bin("files-to-prompt").unwrap();
+    cmd.current_dir(cwd.path());
+    cmd
+}
+
+fn setup() -> TempDir {
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir_all(temp_dir.path().join("test_dir1")).unwrap();
+    fs::create_dir_all(temp_dir.path().join("test_dir2")).unwrap();
+    fs::write(temp_dir.path().join("test_dir1/file1.txt"), "Contents of file1").unwrap();
+    fs::write(temp_dir.path().join("test_dir2/file2.txt"), "Contents of file2").unwrap();
+    temp_dir
+}
+
+#[test]
+fn test_basic_functionality() {
+    let temp_dir = setup();
+    fs::write(temp_dir.path().join("test_dir1/binary_file.bin"), [0xff]).unwrap();
+
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1", "test_dir2", "missing.txt"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("test_dir1/file1.txt\n---\nContents of file1"))
+        .stdout(predicate::str::contains("test_dir2/file2.txt\n---\nContents of file2"))
+        .stderr(predicate::str::contains("Warning: Skipping file test_dir1/binary_file.bin due to UnicodeDecodeError"))
+        .stderr(predicate::str::contains("Path does not exist: missing.txt"));
+}
+
+#[test]
+fn test_output_option() {
+    let temp_dir = setup();
+    let output_file = temp_dir.path().join("output.txt");
+    let expected = "test_dir1/file1.txt\n---\nContents of file1\n\n---";
+
+    // Test -o option
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1", "-o", output_file.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout("");
+    assert_eq!(fs::read_to_string(&output_file).unwrap().trim(), expected);
+
+    // Test --output option
+    fs::remove_file(&output_file).unwrap();
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1", "--output", output_file.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout("");
+    assert_eq!(fs::read_to_string(&output_file).unwrap().trim(), expected);
+}
+
+#[test]
+fn test_reading_paths_from_stdin() {
+    let temp_dir = setup();
+
+    // Test newline-separated paths from stdin
+    files_to_prompt(&temp_dir)
+        .write_stdin("test_dir1/file1.txt\ntest_dir2/file2.txt")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("test_dir1/file1.txt"))
+        .stdout(predicate::str::contains("Contents of file1"))
+        .stdout(predicate::str::contains("test_dir2/file2.txt"))
+        .stdout(predicate::str::contains("Contents of file2"));
+
+    // Test null-separated paths from stdin, with both spellings of the flag
+    for flag in ["--null", "-0"] {
+        files_to_prompt(&temp_dir)
+            .arg(flag)
+            .write_stdin("test_dir1/file1.txt\0test_dir2/file2.txt")
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("test_dir1/file1.txt"))
+            .stdout(predicate::str::contains("Contents of file1"))
+            .stdout(predicate::str::contains("test_dir2/file2.txt"))
+            .stdout(predicate::str::contains("Contents of file2"));
+    }
+}
+
+#[test]
+fn test_paths_from_arguments_and_stdin() {
+    let temp_dir = setup();
+
+    files_to_prompt(&temp_dir)
+        .arg("test_dir1")
+        .write_stdin("test_dir2/file2.txt")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("test_dir1/file1.txt"))
+        .stdout(predicate::str::contains("Contents of file1"))
+        .stdout(predicate::str::contains("test_dir2/file2.txt"))
+        .stdout(predicate::str::contains("Contents of file2"));
+}
+
+#[test]
+fn test_emit_command_header() {
+    let temp_dir = setup();
+    let timestamp = Regex::new(r"timestamp: \d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}Z").unwrap();
+
+    let output = files_to_prompt(&temp_dir).args(["test_dir1", "--emit-command-header"]).output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with(&format!("# files-to-prompt {}\n", env!("CARGO_PKG_VERSION"))));
+    assert!(stdout.contains(" test_dir1 --emit-command-header\n"));
+    assert!(timestamp.is_match(&stdout));
+    assert!(stdout.contains("test_dir1/file1.txt\n---\nContents of file1"));
+
+    let output = files_to_prompt(&temp_dir).args(["test_dir1", "--cxml", "--emit-command-header"]).output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(" test_dir1 -\\-cxml -\\-emit-command-header\n"));
+    assert!(stdout.contains("-->\n<documents>\n"));
+
+    // A time zone without daylight saving time, so the offset is fixed
+    let output = files_to_prompt(&temp_dir)
+        .args(["test_dir1", "--emit-command-header", "--local-time"])
+        .env("TZ", "Asia/Kolkata")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(Regex::new(r"timestamp: \d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}\+05:30\n").unwrap().is_match(&stdout));
+}
+
+// Each time has the offset in effect then, on either side of a daylight saving change
+#[cfg(unix)]
+#[test]
+fn test_local_time_offsets() {
+    let temp_dir = setup();
+    for (file, stamp) in [("test_dir1/file1.txt", "202401151200"), ("test_dir2/file2.txt", "202407151200")] {
+        let touched = std::process::Command::new("touch")
+            .args(["-t", stamp, file])
+            .current_dir(temp_dir.path())
+            .env("TZ", "Europe/Berlin")
+            .status()
+            .unwrap();
+        assert!(touched.success());
+    }
+
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1", "test_dir2", "--write-manifest", "manifest.json", "--local-time"])
+        .env("TZ", "Europe/Berlin")
+        .assert()
+        .success();
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(temp_dir.path().join("manifest.json")).unwrap()).unwrap();
+    assert_eq!(manifest["files"][0]["modified"], "2024-01-15T12:00:00+01:00");
+    assert_eq!(manifest["files"][1]["modified"], "2024-07-15T12:00:00+02:00");
+}
+
+#[test]
+fn test_output_format_inference() {
+    let temp_dir = setup();
+
+    // Each output gets the format implied by its extension
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1", "-o", "out.md", "-o", "out.xml", "-o", "out.txt"])
+        .assert()
+        .success()
+        .stdout("");
+
+    let markdown = fs::read_to_string(temp_dir.path().join("out.md")).unwrap();
+    assert_eq!(markdown.trim(), "test_dir1/file1.txt\n```\nContents of file1\n```");
+    let xml = fs::read_to_string(temp_dir.path().join("out.xml")).unwrap();
+    assert!(xml.starts_with("<documents>\n<document index=\"1\">\n<source>test_dir1/file1.txt</source>"));
+    let text = fs::read_to_string(temp_dir.path().join("out.txt")).unwrap();
+    assert_eq!(text.trim(), "test_dir1/file1.txt\n---\nContents of file1\n\n---");
+
+    // An explicit format flag wins, with a warning when it disagrees
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1", "--markdown", "-o", "out.xml"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Warning: writing Markdown output to out.xml despite its XML extension"));
+    let xml = fs::read_to_string(temp_dir.path().join("out.xml")).unwrap();
+    assert!(xml.contains("```"));
+}
+
+#[test]
+fn test_tool_artifacts_excluded() {
+    let temp_dir = setup();
+    let test_dir = temp_dir.path().join("test_dir1");
+    fs::create_dir_all(test_dir.join(".files-to-prompt")).unwrap();
+    fs::write(test_dir.join(".files-to-prompt/manifest.json"), "{}").unwrap();
+    fs::write(test_dir.join("prompt.txt"), "Previous prompt").unwrap();
+
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1", "--include-hidden", "-o", "test_dir1/prompt.txt"])
+        .assert()
+        .success();
+    let actual = fs::read_to_string(test_dir.join("prompt.txt")).unwrap();
+    assert_eq!(actual.trim(), "test_dir1/file1.txt\n---\nContents of file1\n\n---");
+}
+
+#[test]
+fn test_confirm_above_only_applies_to_terminals() {
+    let temp_dir = setup();
+
+    // Piped output is never held back for confirmation
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1", "--confirm-above", "1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Contents of file1"))
+        .stderr("");
+}
+
+#[test]
+fn test_timeout_parsing() {
+    let temp_dir = setup();
+
+    for timeout in ["30s", "500ms", "2m", "10"] {
+        files_to_prompt(&temp_dir)
+            .args(["test_dir1", "--timeout", timeout])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("Contents of file1"));
+    }
+
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1", "--timeout", "30x"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("unknown unit 'x'"));
+}
+
+#[test]
+fn test_suggest_ignores() {
+    let temp_dir = setup();
+    fs::write(temp_dir.path().join("test_dir1/Cargo.lock"), "lock").unwrap();
+    fs::create_dir_all(temp_dir.path().join("test_dir1/dist")).unwrap();
+    fs::write(temp_dir.path().join("test_dir1/dist/app.js"), "bundle").unwrap();
+
+    files_to_prompt(&temp_dir)
+        .args(["suggest-ignores", "test_dir1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("# Build output and dependency directories (1 file, 6 B)\ndist/\n"))
+        .stdout(predicate::str::contains("# Lockfiles (1 file, 4 B)\nCargo.lock\n"))
+        .stdout(predicate::str::contains("# --ignore dist --ignore Cargo.lock\n"));
+
+    // Options after the subcommand narrow the analysed set
+    files_to_prompt(&temp_dir)
+        .args(["suggest-ignores", "test_dir1", "--ignore", "dist"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("dist/").not());
+}
+
+#[test]
+fn test_git_log() {
+    let temp_dir = setup();
+    let git = |args: &[&str]| {
+        let output = std::process::Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(temp_dir.path())
+            .output();
+        output.is_ok_and(|output| output.status.success())
+    };
+    if !git(&["init", "-q"]) {
+        // git is not available
+        return;
+    }
+    git(&["add", "test_dir1"]);
+    git(&["commit", "-q", "-m", "Add file1"]);
+    git(&["add", "test_dir2"]);
+    git(&["commit", "-q", "-m", "Add file2"]);
+
+    let output = files_to_prompt(&temp_dir).args(["test_dir1", "--git-log", "5"]).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let log = Regex::new(r"^Recent commits\n---\n[0-9a-f]+ Add file2\n[0-9a-f]+ Add file1\n\n---\ntest_dir1/file1.txt\n").unwrap();
+    assert!(log.is_match(&stdout), "{}", stdout);
+
+    // Scoped to the selected paths, only commits touching them are listed
+    let output = files_to_prompt(&temp_dir).args(["test_dir1", "--git-log", "5", "--git-log-scoped"]).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Add file1"));
+    assert!(!stdout.contains("Add file2"));
+}
+
+#[test]
+fn test_from_patch() {
+    let temp_dir = setup();
+    let patch = "--- a/test_dir1/file1.txt\n+++ b/test_dir1/file1.txt\n@@ -1 +1 @@\n-Old contents\n+Contents of file1\n";
+    fs::write(temp_dir.path().join("change.diff"), patch).unwrap();
+    fs::write(temp_dir.path().join("change.txt"), patch).unwrap();
+
+    // A .diff path pulls in the files it touches after the patch itself
+    let output = files_to_prompt(&temp_dir).args(["change.diff"]).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("change.diff\n---\n--- a/test_dir1/file1.txt\n"));
+    assert!(stdout.contains("test_dir1/file1.txt\n---\nContents of file1\n"));
+    assert!(!stdout.contains("test_dir2"));
+
+    // --from-patch works whatever the extension, and files already given are not repeated
+    let output = files_to_prompt(&temp_dir).args(["test_dir1/file1.txt", "--from-patch", "change.txt"]).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("change.txt\n---\n"));
+    assert_eq!(stdout.matches("test_dir1/file1.txt\n---\nContents of file1").count(), 1);
+}
+
+#[test]
+fn test_paths_from_root() {
+    let temp_dir = setup();
+    let initialized = std::process::Command::new("git")
+        .args(["init", "-q"])
+        .current_dir(temp_dir.path())
+        .output()
+        .is_ok_and(|output| output.status.success());
+    if !initialized {
+        // git is not available
+        return;
+    }
+
+    let output = files_to_prompt(&temp_dir)
+        .current_dir(temp_dir.path().join("test_dir1"))
+        .args(["file1.txt", "--paths-from-root"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("test_dir1/file1.txt\n---\nContents of file1\n"), "{}", stdout);
+}
+
+#[test]
+fn test_since_ref() {
+    let temp_dir = setup();
+    let git = |args: &[&str]| {
+        let output = std::process::Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(temp_dir.path())
+            .output();
+        output.is_ok_and(|output| output.status.success())
+    };
+    if !git(&["init", "-q", "-b", "main"]) {
+        // git is not available
+        return;
+    }
+    git(&["add", "."]);
+    git(&["commit", "-q", "-m", "Initial"]);
+
+    // A feature branch changes file2 while main moves on and changes file1
+    git(&["checkout", "-q", "-b", "feature"]);
+    fs::write(temp_dir.path().join("test_dir2/file2.txt"), "Changed file2").unwrap();
+    git(&["commit", "-q", "-am", "Change file2"]);
+    git(&["checkout", "-q", "main"]);
+    fs::write(temp_dir.path().join("test_dir1/file1.txt"), "Changed file1").unwrap();
+    git(&["commit", "-q", "-am", "Change file1"]);
+    git(&["checkout", "-q", "feature"]);
+
+    let output = files_to_prompt(&temp_dir).args(["--since-ref", "main"]).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.trim(), "./test_dir2/file2.txt\n---\nChanged file2\n\n---");
+
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1", "--since-ref", "no-such-ref"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("git merge-base no-such-ref HEAD failed"));
+}
+
+#[test]
+fn test_git_diff() {
+    let temp_dir = setup();
+    let git = |args: &[&str]| {
+        let output = std::process::Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(temp_dir.path())
+            .output();
+        output.is_ok_and(|output| output.status.success())
+    };
+    if !git(&["init", "-q", "-b", "main"]) {
+        // git is not available
+        return;
+    }
+    git(&["add", "."]);
+    git(&["commit", "-q", "-m", "Initial"]);
+    fs::write(temp_dir.path().join("test_dir1/file1.txt"), "Changed file1\n").unwrap();
+    fs::write(temp_dir.path().join("test_dir2/new.txt"), "New file\n").unwrap();
+
+    // Uncommitted changes, untracked files included
+    let output = files_to_prompt(&temp_dir).arg("--git-diff").output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        stdout,
+        "./test_dir1/file1.txt\n---\nChanged file1\n\n\n---\n./test_dir2/new.txt\n---\nNew file\n\n\n---\n"
+    );
+
+    let output = files_to_prompt(&temp_dir).args(["test_dir1", "--git-diff", "--diff-only"]).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("test_dir1/file1.txt.diff\n---\ndiff --git"), "{}", stdout);
+    assert!(stdout.contains("-Contents of file1\n\\ No newline at end of file\n+Changed file1\n"));
+
+    let output = files_to_prompt(&temp_dir).args(["test_dir2", "--git-diff", "--diff-only"]).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("+New file\n"), "{}", stdout);
+
+    // --since compares with the commit itself
+    git(&["commit", "-q", "-am", "Change file1"]);
+    let output = files_to_prompt(&temp_dir).args(["--since", "HEAD~1"]).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout, "./test_dir1/file1.txt\n---\nChanged file1\n\n\n---\n");
+
+    files_to_prompt(&temp_dir).arg("--diff-only").assert().failure();
+}
+
+#[test]
+fn test_apply() {
+    let temp_dir = setup();
+    let response = "Here you go:\n\n\
+                    test_dir1/file1.txt\n---\nNew contents of file1\n\n---\n\
+                    test_dir3/file3.txt\n---\nContents of file3\n\n---\n";
+    fs::write(temp_dir.path().join("response.txt"), response).unwrap();
+
+    // --patch-out only describes the changes
+    files_to_prompt(&temp_dir).args(["apply", "response.txt", "--patch-out", "-"]).assert().success().stdout(
+        "--- a/test_dir1/file1.txt\n+++ b/test_dir1/file1.txt\n@@ -1 +1 @@\n-Contents of file1\n\\ No newline at end of file\n\
+         +New contents of file1\n\\ No newline at end of file\n\
+         --- /dev/null\n+++ b/test_dir3/file3.txt\n@@ -0,0 +1 @@\n+Contents of file3\n",
+    );
+    assert_eq!(fs::read_to_string(temp_dir.path().join("test_dir1/file1.txt")).unwrap(), "Contents of file1");
+    assert!(!temp_dir.path().join("test_dir3").exists());
+
+    files_to_prompt(&temp_dir)
+        .args(["apply", "response.txt"])
+        .assert()
+        .success()
+        .stderr("Updated test_dir1/file1.txt\nCreated test_dir3/file3.txt\n");
+    // Without a final newline before, still without one after
+    assert_eq!(fs::read_to_string(temp_dir.path().join("test_dir1/file1.txt")).unwrap(), "New contents of file1");
+    assert_eq!(fs::read_to_string(temp_dir.path().join("test_dir3/file3.txt")).unwrap(), "Contents of file3\n");
+
+    files_to_prompt(&temp_dir)
+        .arg("apply")
+        .write_stdin("../escape.txt\n---\nNope\n\n---\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("refusing to write ../escape.txt"));
+}
+
+#[test]
+fn test_config_file() {
+    let temp_dir = setup();
+    fs::write(temp_dir.path().join("test_dir1/notes.md"), "Notes").unwrap();
+    fs::write(
+        temp_dir.path().join(".files-to-prompt.toml"),
+        "[profile.default]\nextension = [\n    \"md\", # docs only\n]\nmarkdown = true\n",
+    )
+    .unwrap();
+
+    files_to_prompt(&temp_dir)
+        .arg("test_dir1")
+        .assert()
+        .success()
+        .stdout("test_dir1/notes.md\n```\nNotes\n```\n");
+
+    // The environment overrides the file, and flags override both
+    files_to_prompt(&temp_dir)
+        .arg("test_dir1")
+        .env("FILES_TO_PROMPT_EXTENSION", "txt")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("test_dir1/file1.txt\n```\n"));
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1", "--cxml", "-e", "txt"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("<source>test_dir1/file1.txt</source>"));
+
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1", "--no-config"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("test_dir1/file1.txt\n---\n").and(predicate::str::contains("notes.md")));
+
+    fs::write(temp_dir.path().join(".files-to-prompt.toml"), "[profile.default]\nbogus = true\n").unwrap();
+    files_to_prompt(&temp_dir)
+        .arg("test_dir1")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(".files-to-prompt.toml: unknown option 'bogus'"));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_daemon() {
+    let temp_dir = setup();
+    let socket = temp_dir.path().join("daemon.sock");
+    let mut daemon = std::process::Command::new(assert_cmd::cargo::cargo_bin("files-to-prompt"))
+        .args(["daemon", "--socket"])
+        .arg(&socket)
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    for _ in 0..100 {
+        if socket.exists() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+
+    let request = || {
+        let output = files_to_prompt(&temp_dir)
+            .arg("--daemon-socket")
+            .arg(&socket)
+            .arg("test_dir1")
+            .assert()
+            .success();
+        String::from_utf8(output.get_output().stdout.clone()).unwrap()
+    };
+    let first = request();
+    assert_eq!(first, "test_dir1/file1.txt\n---\nContents of file1\n\n---\n");
+
+    // Only this user can connect
+    use std::os::unix::fs::PermissionsExt;
+    assert_eq!(fs::metadata(&socket).unwrap().permissions().mode() & 0o777, 0o600);
+
+    // Changed files are read again
+    fs::write(temp_dir.path().join("test_dir1/file1.txt"), "Changed contents").unwrap();
+    fs::write(temp_dir.path().join("test_dir1/new.txt"), "New file").unwrap();
+    let second = request();
+    assert!(second.contains("Changed contents") && second.contains("test_dir1/new.txt"));
+
+    files_to_prompt(&temp_dir)
+        .args(["--daemon-socket", socket.to_str().unwrap(), "missing"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Path does not exist: missing"));
+
+    // Paths are the client's: relative to its directory, or piped to it
+    let output = files_to_prompt(&temp_dir)
+        .current_dir(temp_dir.path().join("test_dir1"))
+        .args(["--daemon-socket", socket.to_str().unwrap(), "file1.txt", "-o", "prompt.txt"])
+        .assert()
+        .success();
+    assert!(output.get_output().stdout.is_empty());
+    let written = fs::read_to_string(temp_dir.path().join("test_dir1/prompt.txt")).unwrap();
+    assert_eq!(written, "file1.txt\n---\nChanged contents\n\n---\n");
+    files_to_prompt(&temp_dir)
+        .args(["--daemon-socket", socket.to_str().unwrap()])
+        .write_stdin("test_dir1/new.txt\n")
+        .assert()
+        .success()
+        .stdout("test_dir1/new.txt\n---\nNew file\n\n---\n");
+
+    // Options acting on the daemon's own process are refused, and the
+    // daemon keeps serving
+    files_to_prompt(&temp_dir)
+        .args(["--daemon-socket", socket.to_str().unwrap(), "test_dir1", "--pipe", "false"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("the daemon cannot run --pipe"));
+    assert!(request().contains("Changed contents"));
+
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+}
+
+// The default socket outside $XDG_RUNTIME_DIR is in a directory only this
+// user can enter, and one somebody else could have made is refused
+#[test]
+#[cfg(unix)]
+fn test_daemon_private_dir() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let temp_dir = setup();
+    let uid = fs::metadata(temp_dir.path()).unwrap().uid();
+    let dir = temp_dir.path().join(format!("files-to-prompt-{}", uid));
+    fs::create_dir(&dir).unwrap();
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o777)).unwrap();
+
+    files_to_prompt(&temp_dir)
+        .arg("daemon")
+        .env_remove("XDG_RUNTIME_DIR")
+        .env("TMPDIR", temp_dir.path())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("is not a directory only this user can use"));
+    files_to_prompt(&temp_dir)
+        .args(["--daemon-socket", dir.join("files-to-prompt.sock").to_str().unwrap(), "test_dir1"])
+        .env("TMPDIR", temp_dir.path())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("is not a directory only this user can use"));
+}
+
+#[test]
+fn test_watch() {
+    let temp_dir = setup();
+    let output = temp_dir.path().join("prompt.txt");
+    let mut watcher = std::process::Command::new(assert_cmd::cargo::cargo_bin("files-to-prompt"))
+        .current_dir(temp_dir.path())
+        .args(["test_dir1", "--watch", "-o", "prompt.txt"])
+        .stdin(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let wait_for = |text: &str| {
+        for _ in 0..100 {
+            if fs::read_to_string(&output).is_ok_and(|content| content.contains(text)) {
+                return true;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+        false
+    };
+    assert!(wait_for("Contents of file1"));
+
+    fs::write(temp_dir.path().join("test_dir1/added.txt"), "Added later").unwrap();
+    assert!(wait_for("Added later"));
+    fs::write(temp_dir.path().join("test_dir1/file1.txt"), "Changed contents").unwrap();
+    assert!(wait_for("Changed contents"));
+
+    watcher.kill().unwrap();
+    watcher.wait().unwrap();
+
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1", "--watch"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--output"));
+}
+
+#[test]
+fn test_estimate() {
+    let temp_dir = setup();
+    fs::write(temp_dir.path().join("test_dir1/big.txt"), "x".repeat(4000)).unwrap();
+
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1", "test_dir2", "--estimate"])
+        .assert()
+        .success()
+        .stdout("3 files, 3.9 KB, ~1k tokens\n");
+    files_to_prompt(&temp_dir)
+        .args(["test_dir2", "--estimate"])
+        .assert()
+        .success()
+        .stdout("1 file, 17 B, ~4 tokens\n");
+}
+
+#[test]
+fn test_list() {
+    let temp_dir = setup();
+    fs::write(temp_dir.path().join("test_dir1/big.txt"), "x".repeat(4000)).unwrap();
+
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1", "test_dir2", "--list"])
+        .assert()
+        .success()
+        .stdout("test_dir1/big.txt\ntest_dir1/file1.txt\ntest_dir2/file2.txt\n");
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1", "--list", "-l", "--ignore", "file1.txt"])
+        .assert()
+        .success()
+        .stdout("   3.9 KB    ~1k tokens  test_dir1/big.txt\n");
+    files_to_prompt(&temp_dir).args(["test_dir1", "-l"]).assert().failure();
+}
+
+#[test]
+fn test_decompress() {
+    let temp_dir = setup();
+    // gzip of "GET /index.html 200\nGET /missing 404\n"
+    let access_log = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03\x73\x77\x0d\x51\xd0\xcf\xcc\x4b\x49\xad\xd0\xcb\x28\
+        \xc9\xcd\x51\x30\x32\x30\xe0\x72\x07\
+        \x89\xe5\x66\x16\x17\x67\xe6\xa5\x2b\x98\x18\x98\x70\x01\x00\x53\x0a\x4a\xe5\x25\x00\x00\x00";
+    fs::write(temp_dir.path().join("test_dir1/access.log.gz"), access_log).unwrap();
+
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("GET").not())
+        .stderr(predicate::str::contains("Skipping file test_dir1/access.log.gz"));
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1", "--decompress"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("test_dir1/access.log.gz\n---\nGET /index.html 200\nGET /missing 404\n"))
+        .stderr("");
+}
+
+// A one-page PDF showing `text`, with the byte offsets its cross-reference table needs
+fn pdf(text: &str) -> Vec<u8> {
+    let stream = format!("BT /F1 12 Tf 20 50 Td ({}) Tj ET", text);
+    let objects = [
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+        "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 300 100] /Contents 4 0 R \
+         /Resources << /Font << /F1 5 0 R >> >> >>"
+            .to_string(),
+        format!("<< /Length {} >>\nstream\n{}\nendstream", stream.len(), stream),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_string(),
+    ];
+    let mut pdf = String::from("%PDF-1.4\n");
+    let mut offsets = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.push_str(&format!("{} 0 obj\n{}\nendobj\n", i + 1, object));
+    }
+    let xref = pdf.len();
+    pdf.push_str(&format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1));
+    for offset in offsets {
+        pdf.push_str(&format!("{:010} 00000 n \n", offset));
+    }
+    pdf.push_str(&format!("trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", objects.len() + 1, xref));
+    pdf.into_bytes()
+}
+
+#[test]
+fn test_extract() {
+    let temp_dir = setup();
+    fs::write(
+        temp_dir.path().join("test_dir1/page.html"),
+        "<html><head><title>Guide</title><script>track()</script></head>\n<body><h1>Install &amp; run</h1>\n\
+         <p>Run   the\n  tool.</p><ul><li>one</li><li>two</li></ul></body></html>\n",
+    )
+    .unwrap();
+    // A .docx holding the paragraphs "Hello & welcome" and "Bye"
+    let notes = b"\x50\x4b\x03\x04\x14\x00\x00\x00\x08\x00\x00\x00\x21\x00\x0a\xac\xde\x6c\x2c\x00\x00\x00\x42\x00\x00\x00\
+        \x11\x00\x00\x00\x77\x6f\x72\x64\x2f\x64\x6f\x63\x75\x6d\x65\x6e\x74\x2e\x78\x6d\x6c\xb3\x29\xb7\x2a\xb0\
+        \xb3\x29\xb7\x2a\xb1\xf3\x48\xcd\xc9\xc9\x57\x50\x4b\xcc\x2d\xb0\x56\x28\x4f\xcd\x49\xce\xcf\x4d\xb5\xd1\
+        \x07\x49\x80\x48\xb0\x1a\xa8\x42\xa7\x4a\x14\x09\x00\x50\x4b\x01\x02\x14\x03\x14\x00\x00\x00\x08\x00\x00\
+        \x00\x21\x00\x0a\xac\xde\x6c\x2c\x00\x00\x00\x42\x00\x00\x00\x11\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\
+        \x00\x80\x01\x00\x00\x00\x00\x77\x6f\x72\x64\x2f\x64\x6f\x63\x75\x6d\x65\x6e\x74\x2e\x78\x6d\x6c\x50\x4b\
+        \x05\x06\x00\x00\x00\x00\x01\x00\x01\x00\x3f\x00\x00\x00\x5b\x00\x00\x00\x00\x00";
+    fs::write(temp_dir.path().join("test_dir1/notes.docx"), notes).unwrap();
+    fs::write(temp_dir.path().join("test_dir1/hello.pdf"), pdf("Hello PDF")).unwrap();
+
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("<h1>Install &amp; run</h1>"))
+        .stderr(predicate::str::contains("Skipping file test_dir1/notes.docx"));
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1", "--extract"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("test_dir1/notes.docx\n---\nHello & welcome\nBye\n"))
+        .stdout(predicate::str::contains("test_dir1/hello.pdf\n---\nHello PDF\n"))
+        .stdout(predicate::str::contains("test_dir1/page.html\n---\nGuide\n\n# Install & run\n\nRun the tool.\n\n- one\n- two\n"))
+        .stderr("");
+}
+
+#[test]
+fn test_stdin_content() {
+    let temp_dir = setup();
+
+    files_to_prompt(&temp_dir)
+        .args(["-", "test_dir1"])
+        .write_stdin("-old line\n+new line\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("stdin\n---\n-old line\n+new line\n"))
+        .stdout(predicate::str::contains("test_dir1/file1.txt\n---\nContents of file1"))
+        .stdout(predicate::str::contains("file2").not());
+    files_to_prompt(&temp_dir)
+        .args(["-", "--stdin-name", "changes.diff", "--cxml"])
+        .write_stdin("+added\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("<source>changes.diff</source>"))
+        .stdout(predicate::str::contains("+added"));
+}
+
+#[test]
+fn test_build_info() {
+    let temp_dir = setup();
+    let output = files_to_prompt(&temp_dir).arg("--build-info").output().unwrap();
+    assert!(output.status.success());
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(info["schema_version"], files_to_prompt::SCHEMA_VERSION);
+    assert!(info["formats"].as_array().unwrap().contains(&"cxml".into()));
+    assert!(info["options"].as_array().unwrap().contains(&"line-numbers".into()));
+    assert!(info["subcommands"].as_array().unwrap().contains(&"apply".into()));
+
+    files_to_prompt(&temp_dir).arg("--version-json").assert().success().stdout(predicate::str::contains("\"version\""));
+}
+
+#[test]
+fn test_file_size_limits() {
+    let temp_dir = setup();
+    fs::write(temp_dir.path().join("test_dir1/Cargo.lock"), "x".repeat(3000)).unwrap();
+    fs::write(temp_dir.path().join("test_dir1/empty.txt"), "").unwrap();
+
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1", "--max-file-size", "2KB", "--min-file-size", "1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("test_dir1/file1.txt\n---\nContents of file1"))
+        .stdout(predicate::str::contains("Cargo.lock").not())
+        .stdout(predicate::str::contains("empty.txt").not())
+        .stderr(predicate::str::contains("Skipping file test_dir1/Cargo.lock for its size: 2.9 KB, over --max-file-size"))
+        .stderr(predicate::str::contains("Skipping file test_dir1/empty.txt for its size: 0 B, under --min-file-size"));
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1", "--max-file-size", "1.5k", "--show-skipped"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("test_dir1/Cargo.lock (skipped: 2.9 KB, over --max-file-size)"));
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1", "--max-file-size", "2PB"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("unknown unit 'PB'"));
+}
+
+#[test]
+fn test_convert() {
+    let temp_dir = setup();
+    let output = files_to_prompt(&temp_dir).args(["test_dir1", "test_dir2", "--cxml"]).output().unwrap();
+    fs::write(temp_dir.path().join("prompt.xml"), &output.stdout).unwrap();
+    let markdown = files_to_prompt(&temp_dir).args(["test_dir1", "test_dir2", "--markdown"]).output().unwrap();
+
+    files_to_prompt(&temp_dir)
+        .args(["convert", "--from", "cxml", "--to", "markdown", "prompt.xml"])
+        .assert()
+        .success()
+        .stdout(String::from_utf8(markdown.stdout).unwrap());
+    files_to_prompt(&temp_dir)
+        .args(["convert", "--from", "markdown", "--to", "default"])
+        .write_stdin("a.py\n```python\nprint(1)\n```\n")
+        .assert()
+        .success()
+        .stdout("a.py\n---\nprint(1)\n\n---\n");
+    files_to_prompt(&temp_dir)
+        .args(["convert", "--from", "json", "--to", "cxml"])
+        .write_stdin("[]")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no documents found in the input"));
+}
+
+#[test]
+fn test_split_output() {
+    let temp_dir = setup();
+    let long: String = (1..=60).map(|i| format!("line {}\n", i)).collect();
+    fs::write(temp_dir.path().join("test_dir1/long.txt"), long).unwrap();
+
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1", "test_dir2", "-o", "out.txt", "--split-bytes", "300"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Wrote 5 parts of out.txt"));
+    let parts: Vec<String> =
+        (1..=5).map(|n| fs::read_to_string(temp_dir.path().join(format!("out.part{}.txt", n))).unwrap()).collect();
+    assert!(parts.iter().all(|part| part.len() <= 300));
+    assert!(parts[0].starts_with("Part 1 of 5\n---\nPart 1 of 5; the remaining files follow in later parts.\n"));
+    assert!(parts[0].contains("test_dir1/file1.txt\n---\nContents of file1"));
+    assert!(parts[1].starts_with("Part 2 of 5\n---\nPart 2 of 5; earlier parts hold the files before these.\n"));
+    assert!(parts[1].contains("test_dir1/long.txt (lines 1-22)\n---\nline 1\n"));
+    assert!(parts[3].contains("test_dir1/long.txt (lines 43-60)\n---\nline 43\n"));
+    assert!(parts[4].contains("test_dir2/file2.txt\n---\nContents of file2"));
+    assert!(!temp_dir.path().join("out.part6.txt").exists());
+}
+
+#[test]
+fn test_split_by_dir() {
+    let temp_dir = setup();
+    fs::write(temp_dir.path().join("notes.txt"), "Top-level notes").unwrap();
+
+    // Run twice: the second run must not read back the first one's output
+    for _ in 0..2 {
+        files_to_prompt(&temp_dir)
+            .args([".", "--split-by-dir", "--out-dir", "prompts", "--markdown"])
+            .assert()
+            .success()
+            .stdout("")
+            .stderr(predicate::str::contains("Wrote 3 files to prompts"));
+    }
+    let read = |name: &str| fs::read_to_string(temp_dir.path().join("prompts").join(name)).unwrap();
+    let first = read("test_dir1.md");
+    assert!(first.contains("Contents of file1") && !first.contains("Contents of file2"));
+    let second = read("test_dir2.md");
+    assert!(second.contains("Contents of file2") && !second.contains("Contents of file1"));
+    assert_eq!(read("root.md"), "./notes.txt\n```\nTop-level notes\n```\n");
+    assert!(!temp_dir.path().join("prompts/prompts.md").exists());
+
+    files_to_prompt(&temp_dir).args(["test_dir1", "--split-by-dir"]).assert().failure();
+}
+
+#[test]
+fn test_manifests() {
+    let temp_dir = setup();
+
+    files_to_prompt(&temp_dir).args(["test_dir1", "test_dir2", "--write-manifest", "manifest.json"]).assert().success();
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(temp_dir.path().join("manifest.json")).unwrap()).unwrap();
+    assert_eq!(manifest["schema_version"], files_to_prompt::SCHEMA_VERSION);
+    assert_eq!(manifest["tool_version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(manifest["files"][0]["path"], "test_dir1/file1.txt");
+    assert!(manifest["files"][0].get("path_bytes").is_none());
+    assert_eq!(manifest["files"][1]["size"], 17);
+    let modified = Regex::new(r"^\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}Z$").unwrap();
+    assert!(modified.is_match(manifest["files"][1]["modified"].as_str().unwrap()));
+
+    // Files added since are left out, as no walk happens
+    fs::write(temp_dir.path().join("test_dir1/new.txt"), "new").unwrap();
+    files_to_prompt(&temp_dir)
+        .args(["--exact-manifest", "manifest.json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("test_dir1/file1.txt\n---\nContents of file1"))
+        .stdout(predicate::str::contains("test_dir2/file2.txt\n---\nContents of file2"))
+        .stdout(predicate::str::contains("new.txt").not());
+
+    fs::write(temp_dir.path().join("test_dir2/file2.txt"), "Edited").unwrap();
+    fs::remove_file(temp_dir.path().join("test_dir1/file1.txt")).unwrap();
+    files_to_prompt(&temp_dir)
+        .args(["--exact-manifest", "manifest.json"])
+        .assert()
+        .failure()
+        .stdout("")
+        .stderr(predicate::str::contains("test_dir1/file1.txt is missing, test_dir2/file2.txt has changed"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_manifest_non_utf8_names() {
+    use std::os::unix::ffi::OsStrExt;
+    let temp_dir = setup();
+    let name = std::ffi::OsStr::from_bytes(b"caf\xe9.txt");
+    fs::write(temp_dir.path().join("test_dir1").join(name), "Latin-1 name").unwrap();
+
+    files_to_prompt(&temp_dir).args(["test_dir1", "--write-manifest", "manifest.json"]).assert().success();
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(temp_dir.path().join("manifest.json")).unwrap()).unwrap();
+    assert_eq!(manifest["files"][0]["path"], "test_dir1/caf\\xE9.txt");
+    assert_eq!(manifest["files"][0]["path_bytes"], "746573745f646972312f636166e92e747874");
+
+    files_to_prompt(&temp_dir)
+        .args(["--exact-manifest", "manifest.json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Latin-1 name"));
+}
+
+#[test]
+fn test_output_directories() {
+    let temp_dir = setup();
+
+    files_to_prompt(&temp_dir).args(["test_dir1", "-o", "nested/dir/out.txt"]).assert().success();
+    assert!(fs::read_to_string(temp_dir.path().join("nested/dir/out.txt")).unwrap().contains("Contents of file1"));
+
+    // The error names the operation and the path it failed on
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1", "-o", "test_dir2/file2.txt/out.txt"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::starts_with("Error: could not create directory test_dir2/file2.txt: "));
+    files_to_prompt(&temp_dir)
+        .args(["apply", "missing.txt"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::starts_with("Error: could not read missing.txt: "));
+}
+
+#[test]
+fn test_cxml_strict() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir(temp_dir.path().join("dir")).unwrap();
+    fs::write(temp_dir.path().join("dir/a&b.txt"), "if a < b {}\n</document_content>\n]]>\n").unwrap();
+
+    files_to_prompt(&temp_dir).args(["dir", "--cxml"]).assert().success().stdout(predicate::str::contains(
+        "<source>dir/a&b.txt</source>\n<document_content>\nif a < b {}\n</document_content>\n]]>\n",
+    ));
+    let output = files_to_prompt(&temp_dir).args(["dir", "--cxml", "--cxml-strict"]).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(concat!(
+        "<source>dir/a&amp;b.txt</source>\n<document_content>\n",
+        "<![CDATA[if a < b {}\n</document_content>\n]]]]><![CDATA[>\n]]>\n</document_content>\n"
+    )));
+
+    // convert reads the strict output back to the original contents
+    fs::write(temp_dir.path().join("out.xml"), &stdout).unwrap();
+    files_to_prompt(&temp_dir)
+        .args(["convert", "out.xml", "--from", "cxml", "--to", "default"])
+        .assert()
+        .success()
+        .stdout("dir/a&b.txt\n---\nif a < b {}\n</document_content>\n]]>\n\n\n---\n");
+
+    files_to_prompt(&temp_dir)
+        .args(["dir", "--cxml", "--cxml-strict", "--cxml-lines"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("<line n=\"1\">if a &lt; b {}</line>\n<line n=\"2\">&lt;/document_content&gt;</line>"));
+}
+
+#[test]
+fn test_repository_url() {
+    let temp_dir = setup();
+    let git = |args: &[&str]| {
+        let output = std::process::Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(temp_dir.path())
+            .output();
+        output.is_ok_and(|output| output.status.success())
+    };
+    if !git(&["init", "-q", "-b", "main"]) {
+        // git is not available
+        return;
+    }
+    fs::write(temp_dir.path().join(".gitignore"), "test_dir2/\n").unwrap();
+    git(&["add", "."]);
+    git(&["commit", "-q", "-m", "Initial"]);
+    git(&["checkout", "-q", "-b", "feature"]);
+    fs::write(temp_dir.path().join("test_dir1/file1.txt"), "Changed file1\n").unwrap();
+    git(&["commit", "-q", "-am", "Change file1"]);
+    git(&["checkout", "-q", "main"]);
+
+    let url = format!("file://{}", temp_dir.path().display());
+    let name = temp_dir.path().file_name().unwrap().to_string_lossy().into_owned();
+    let run = |args: &[&str]| {
+        let output = files_to_prompt(&temp_dir).arg(&url).args(args).output().unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    // Labeled with the repository name, and its .gitignore applies
+    let stdout = run(&[]);
+    assert_eq!(stdout.trim(), format!("{}/test_dir1/file1.txt\n---\nContents of file1\n\n---", name));
+    assert!(run(&["--branch", "feature"]).contains("\n---\nChanged file1\n"));
+
+    let output = std::process::Command::new("git").args(["rev-parse", "feature"]).current_dir(temp_dir.path()).output().unwrap();
+    let commit = String::from_utf8(output.stdout).unwrap();
+    assert!(run(&["--rev", commit.trim()]).contains("\n---\nChanged file1\n"));
+
+    files_to_prompt(&temp_dir)
+        .arg(format!("{}/missing", url))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("git clone"));
+}
+
+#[test]
+fn test_score_cmd() {
+    let temp_dir = setup();
+
+    // Scored by content, the file scored lower is left out
+    let output = files_to_prompt(&temp_dir)
+        .args([".", "--max-bytes", "60", "--score-cmd", "grep -c file2 >/dev/null && echo 2 || echo 1; true"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("test_dir2/file2.txt") && !stdout.contains("test_dir1/file1.txt"), "{}", stdout);
+
+    let output = files_to_prompt(&temp_dir)
+        .args(["test_dir1", "--max-bytes", "60", "--score-cmd", "echo high; true"])
+        .output()
+        .unwrap();
+    assert!(String::from_utf8(output.stderr).unwrap().contains("no score for test_dir1/file1.txt, ranking it last"));
+}
+
+#[test]
+fn test_init() {
+    let temp_dir = setup();
+    fs::create_dir_all(temp_dir.path().join("node_modules/left-pad")).unwrap();
+    fs::write(temp_dir.path().join("node_modules/left-pad/index.js"), "module.exports = 1").unwrap();
+
+    files_to_prompt(&temp_dir)
+        .arg("init")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Wrote ./.files-to-prompt.toml based on 3 files"));
+    let config = fs::read_to_string(temp_dir.path().join(".files-to-prompt.toml")).unwrap();
+    assert!(config.contains("[profile.default]\n"));
+    assert!(config.contains("    \"node_modules\",\n"));
+
+    // An existing configuration is only replaced on request
+    files_to_prompt(&temp_dir)
+        .arg("init")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("already exists, use --force to overwrite it"));
+    files_to_prompt(&temp_dir).args(["init", "--force"]).assert().success();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_directory_cycles() {
+    let temp_dir = setup();
+    std::os::unix::fs::symlink("..", temp_dir.path().join("test_dir1/parent")).unwrap();
+
+    // Links are listed rather than followed by default
+    files_to_prompt(&temp_dir)
+        .arg("test_dir1")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("test_dir1/parent -> ..\n"))
+        .stdout(predicate::str::contains("file2").not());
+
+    let output = files_to_prompt(&temp_dir).args(["test_dir1", "--follow-symlinks"]).output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    // The link is followed into the parent, but not back into test_dir1
+    assert_eq!(stdout.matches("Contents of file1").count(), 1);
+    assert!(stdout.contains("test_dir1/parent/test_dir2/file2.txt"));
+    assert!(stderr.contains("Warning: not following test_dir1/parent/test_dir1, which loops back to"));
+}
+
+#[test]
+fn test_stdin_format_json() {
+    let temp_dir = setup();
+    fs::write(temp_dir.path().join("test_dir1/lines.txt"), "a\nb\nc\nd\n").unwrap();
+
+    let output = files_to_prompt(&temp_dir)
+        .args(["--stdin-format", "json"])
+        .write_stdin(r#"[{"path": "test_dir1/lines.txt", "lines": "2-3", "label": "middle"}, "test_dir2/file2.txt"]"#)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        stdout,
+        "middle (lines 2-3)\n---\nb\nc\n\n---\ntest_dir2/file2.txt\n---\nContents of file2\n\n---\n"
+    );
+
+    files_to_prompt(&temp_dir)
+        .args(["--stdin-format", "json"])
+        .write_stdin(r#"[{"path": "test_dir1/lines.txt", "lines": "3-1"}]"#)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid line range '3-1' for test_dir1/lines.txt, expected START-END"));
+}
+
+#[test]
+fn test_path_annotations() {
+    let temp_dir = setup();
+    fs::write(temp_dir.path().join("test_dir2/notes#1.txt"), "Notes").unwrap();
+
+    let output = files_to_prompt(&temp_dir)
+        .args(["test_dir1#label=extra,priority=2", "test_dir2/file2.txt#priority=1", "test_dir2/notes#1.txt"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let headers: Vec<&str> = stdout.lines().filter(|line| line.starts_with("test_dir")).collect();
+    assert_eq!(
+        headers,
+        vec![
+            "test_dir2/file2.txt (priority: 1)",
+            "test_dir1/file1.txt (label: extra, priority: 2)",
+            "test_dir2/notes#1.txt",
+        ]
+    );
+}
+
+#[test]
+fn test_require_paths() {
+    let temp_dir = setup();
+
+    // Piped input that turns out empty never falls back to the current directory
+    files_to_prompt(&temp_dir).write_stdin("").assert().success().stdout("");
+
+    files_to_prompt(&temp_dir)
+        .arg("--require-paths")
+        .write_stdin("")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Error: no paths given"));
+    files_to_prompt(&temp_dir)
+        .args(["--require-paths", "test_dir1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Contents of file1"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_pipe() {
+    let temp_dir = setup();
+
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1", "--pipe", "tr a-z A-Z > piped.txt"])
+        .assert()
+        .success()
+        .stdout("");
+    let piped = fs::read_to_string(temp_dir.path().join("piped.txt")).unwrap();
+    assert_eq!(piped, "TEST_DIR1/FILE1.TXT\n---\nCONTENTS OF FILE1\n\n---\n");
+
+    // The command's exit code is passed on, even when it reads nothing
+    files_to_prompt(&temp_dir).args(["test_dir1", "--pipe", "exit 3"]).assert().code(3);
+}
+
+// Without a display server there is no clipboard to copy to
+#[cfg(all(unix, not(target_os = "macos")))]
+#[test]
+fn test_copy() {
+    let temp_dir = setup();
+
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1", "--copy"])
+        .env_remove("DISPLAY")
+        .env_remove("WAYLAND_DISPLAY")
+        .assert()
+        .failure()
+        .stdout("")
+        .stderr(predicate::str::contains("cannot use the clipboard"));
+}
+
+// A reader that stops early ends the run quietly, as SIGPIPE would
+#[cfg(unix)]
+#[test]
+fn test_broken_pipe() {
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+
+    let temp_dir = setup();
+    let line = "A line of text\n".repeat(20_000);
+    for i in 0..10 {
+        fs::write(temp_dir.path().join(format!("test_dir1/big{}.txt", i)), &line).unwrap();
+    }
+
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("files-to-prompt"))
+        .arg("test_dir1")
+        .current_dir(temp_dir.path())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut first = String::new();
+    BufReader::new(child.stdout.take().unwrap()).read_line(&mut first).unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(first, "test_dir1/big0.txt\n");
+    assert_eq!(output.status.code(), Some(141));
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+}
+
+#[test]
+fn test_escape() {
+    let temp_dir = setup();
+    fs::write(temp_dir.path().join("test_dir1/file1.txt"), "It's \"quoted\"").unwrap();
+
+    let output = files_to_prompt(&temp_dir).args(["test_dir1", "--escape", "json"]).output().unwrap();
+    let escaped: String = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(escaped, "test_dir1/file1.txt\n---\nIt's \"quoted\"\n\n---\n");
+
+    files_to_prompt(&temp_dir)
+        .args(["test_dir1", "--escape", "shell"])
+        .assert()
+        .success()
+        .stdout("'test_dir1/file1.txt\n---\nIt'\\''s \"quoted\"\n\n---\n'\n");
+}
@@ -0,0 +1,123 @@
+// Property tests for the escaping of the output formats: whatever a file
+// holds, XML output stays well-formed, Markdown fences stay closed, and
+// `apply` reads back exactly what was written.
+use files_to_prompt::apply;
+use files_to_prompt::vfs::MemoryFs;
+use files_to_prompt::{collect, Options, OutputFormat};
+use proptest::prelude::*;
+use std::path::PathBuf;
+
+// Helper function to run the collector in-process and return stdout
+fn run(paths: &[&str], options: &Options, fs: &MemoryFs) -> String {
+    let paths: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+    let mut stdout = Vec::new();
+    collect(&paths, options, fs, &mut stdout, &mut Vec::new()).expect("collect failed");
+    String::from_utf8(stdout).unwrap()
+}
+
+/// File contents built mostly from pieces of the output formats, so the
+/// cases that could break them come up often.
+fn content() -> impl Strategy<Value = String> {
+    let piece = prop_oneof![
+        Just("\n".to_string()),
+        Just("\r\n".to_string()),
+        Just("\r".to_string()),
+        Just("---".to_string()),
+        Just("```".to_string()),
+        Just("`".to_string()),
+        Just("]]>".to_string()),
+        Just("]]]]><![CDATA[>".to_string()),
+        Just("<![CDATA[".to_string()),
+        Just("</document_content>".to_string()),
+        Just("</document>".to_string()),
+        Just("<source>".to_string()),
+        Just("&amp;".to_string()),
+        Just("&".to_string()),
+        Just("<".to_string()),
+        Just("\t".to_string()),
+        "[a-z ]{1,6}",
+        "\\PC{1,4}",
+    ];
+    prop::collection::vec(piece, 0..24).prop_map(|pieces| pieces.concat())
+}
+
+/// File names with the characters XML escapes.
+fn name() -> impl Strategy<Value = String> {
+    "[a-z][a-z&<>\"' ]{0,6}\\.txt"
+}
+
+fn options(format: OutputFormat) -> Options {
+    Options { format, cxml_strict: true, ..Options::default() }
+}
+
+/// What an XML parser reads back from text written as `content`: line
+/// breaks normalized and characters XML can't hold replaced.
+fn xml_text(content: &str) -> String {
+    content.replace("\r\n", "\n").replace('\r', "\n").replace(invalid_xml, "\u{fffd}")
+}
+
+fn invalid_xml(c: char) -> bool {
+    (c < ' ' && !matches!(c, '\t' | '\n' | '\r')) || matches!(c, '\u{fffe}' | '\u{ffff}')
+}
+
+proptest! {
+    #[test]
+    fn cxml_strict_output_is_well_formed(contents in prop::collection::vec((name(), content()), 1..4)) {
+        let mut fs = MemoryFs::new();
+        for (i, (name, content)) in contents.iter().enumerate() {
+            fs.add_file(format!("dir/{}/{}", i, name), content.as_str());
+        }
+
+        let stdout = run(&["dir"], &options(OutputFormat::Cxml), &fs);
+        let xml = roxmltree::Document::parse(&stdout);
+        prop_assert!(xml.is_ok(), "{:?} in:\n{}", xml.err(), stdout);
+        let xml = xml.unwrap();
+        let documents: Vec<_> = xml.descendants().filter(|node| node.has_tag_name("document")).collect();
+        prop_assert_eq!(documents.len(), contents.len());
+        for (document, (name, content)) in documents.iter().zip(&contents) {
+            let text = |tag| {
+                let element = document.children().find(|node| node.has_tag_name(tag)).unwrap();
+                element.descendants().filter(|node| node.is_text()).filter_map(|node| node.text()).collect::<String>()
+            };
+            let source = text("source");
+            prop_assert!(source.ends_with(&format!("/{}", name)), "{}", source);
+            prop_assert_eq!(text("document_content"), format!("\n{}\n", xml_text(content)));
+        }
+    }
+
+    #[test]
+    fn markdown_fences_stay_closed(content in content()) {
+        let mut fs = MemoryFs::new();
+        fs.add_file("file.txt", content.as_str());
+
+        let stdout = run(&["file.txt"], &options(OutputFormat::Markdown), &fs);
+        let lines: Vec<&str> = stdout.strip_suffix('\n').unwrap().split('\n').collect();
+        let fence = lines[1].trim_end_matches(|c: char| c != '`');
+        prop_assert!(fence.len() >= 3);
+        // A line of at least as many backticks closes the fence; the only
+        // one is the last
+        let closing = lines.iter().skip(2).position(|line| line.trim().starts_with(fence));
+        prop_assert_eq!(closing.map(|i| i + 2), Some(lines.len() - 1), "{}", stdout);
+    }
+
+    #[test]
+    fn apply_round_trips_content(content in content(), format in prop_oneof![
+        Just(OutputFormat::Default),
+        Just(OutputFormat::Cxml),
+        Just(OutputFormat::Markdown),
+    ]) {
+        // XML has no way to write the characters it can't hold, and the
+        // default format marks the end of a file with a `---` line
+        prop_assume!(format != OutputFormat::Cxml || !content.contains(invalid_xml));
+        prop_assume!(format != OutputFormat::Default || !content.split('\n').any(|line| line.trim_end() == "---"));
+        let mut fs = MemoryFs::new();
+        fs.add_file("file.txt", content.as_str());
+
+        let stdout = run(&["file.txt"], &options(format), &fs);
+        let documents = apply::parse_documents(&stdout);
+        prop_assert_eq!(documents.len(), 1, "{}", stdout);
+        prop_assert_eq!(&documents[0].path, &PathBuf::from("file.txt"));
+        // What `apply` writes over the file it came from
+        prop_assert_eq!(documents[0].new_content(Some(&content)), content);
+    }
+}